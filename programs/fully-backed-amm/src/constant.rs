@@ -1,3 +1,59 @@
 
 pub const POOL: &[u8] = b"liquid_pool";
 pub const MINT_LP: &[u8] = b"mint_lp";
+pub const LOCKED_LP: &[u8] = b"locked_lp";
+pub const OBSERVATIONS: &[u8] = b"observations";
+pub const POOL_PAIR: &[u8] = b"pool_pair";
+pub const GLOBAL_CONFIG: &[u8] = b"global_config";
+pub const PENDING_CHANGE: &[u8] = b"pending_change";
+pub const CONCENTRATED_POOL: &[u8] = b"concentrated_pool";
+pub const TICK_ARRAY: &[u8] = b"tick_array";
+pub const POSITION: &[u8] = b"position";
+
+// lp tokens locked forever on the first deposit, so the lp supply never goes back to zero
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+// fees are expressed in basis points (1 bps = 0.01%)
+pub const FEE_DENOMINATOR: u64 = 10_000;
+pub const MAX_FEE_BPS: u16 = 1_000;
+pub const MAX_FEE_TIERS: usize = 8; // fee tiers the global config can allow
+
+// upper bound for the observation ring buffer, each slot is Observation::INIT_SPACE bytes. Every
+// swap, deposit and withdraw deserializes the whole buffer onto the 32 KB heap, which is never
+// freed, and swap_route loads one per hop: a full route of full buffers stays within half of it
+pub const MAX_OBSERVATION_CARDINALITY: u16 = 64;
+
+// bounds for the StableSwap amplification coefficient
+pub const MIN_AMP: u64 = 1;
+pub const MAX_AMP: u64 = 10_000;
+
+// an amp ramp lasts at least a day and changes A by at most 10x, so the rate of change is bounded
+pub const MIN_RAMP_DURATION: i64 = 86_400;
+pub const MAX_RAMP_DURATION: i64 = 365 * 86_400;
+pub const MAX_AMP_CHANGE: u64 = 10;
+
+// weighted pools: weights are in bps of FEE_DENOMINATOR, neither side below 1%
+pub const MIN_WEIGHT_BPS: u16 = 100;
+
+// concentrated liquidity: sqrt(1.0001^tick) stays inside a Q64.64 between these ticks
+pub const MIN_TICK: i32 = -443_636;
+pub const MAX_TICK: i32 = 443_636;
+pub const MAX_TICK_SPACING: u16 = 1_000;
+pub const TICK_ARRAY_SIZE: i32 = 32; // initializable ticks per tick array account
+
+// swap_route: accounts per hop in remaining_accounts and the longest route
+pub const ROUTE_HOP_ACCOUNTS: usize = 7;
+pub const MAX_ROUTE_HOPS: usize = 4;
+
+// bounds of the timelock on pool parameter changes, never zero so queue_change and
+// execute_change can't land in the same transaction
+pub const MIN_TIMELOCK_DELAY: i64 = 60;
+pub const MAX_TIMELOCK_DELAY: i64 = 30 * 86_400;
+
+// last seed of a pending change: the global admin and the pool owner each queue in their own slot
+pub const ADMIN_CHANGE_SLOT: u8 = 0;
+pub const OWNER_CHANGE_SLOT: u8 = 1;
+
+// PoolConfig layout, bumped whenever a field is added, with migrate_pool upgrading older pools
+pub const POOL_CONFIG_VERSION: u8 = 1;
+pub const POOL_CONFIG_RESERVED: usize = 128; // bytes kept free at the end for future fields
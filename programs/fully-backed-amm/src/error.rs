@@ -16,4 +16,157 @@ pub enum AMMError {
 
     #[msg("Slippage Occured !!")]
     SlippageLimitExceeded,
+
+    #[msg("The fee is higher than the allowed maximum")]
+    InvalidFee,
+
+    #[msg("Only the pool owner can call this instruction")]
+    Unauthorized,
+
+    #[msg("The LP tokens minted are less than the minimum requested")]
+    MinLpOutNotMet,
+
+    #[msg("The withdrawn amount is less than the minimum requested")]
+    WithdrawSlippageExceeded,
+
+    #[msg("The required input is more than the maximum allowed")]
+    MaxAmountInExceeded,
+
+    #[msg("The pool does not have enough liquidity for this swap")]
+    InsufficientLiquidity,
+
+    #[msg("The observations are not in increasing time order")]
+    InvalidObservation,
+
+    #[msg("The requested time is older than the oldest observation")]
+    ObservationTooOld,
+
+    #[msg("The observation cardinality can only grow, up to the maximum")]
+    InvalidCardinality,
+
+    #[msg("The oracle account does not match the pool oracle")]
+    OracleMismatch,
+
+    #[msg("The oracle price is not valid")]
+    OracleInvalid,
+
+    #[msg("The oracle price is stale")]
+    OracleStale,
+
+    #[msg("The oracle confidence interval is too wide")]
+    OracleConfidenceTooWide,
+
+    #[msg("The pool price moved too far away from the oracle price")]
+    OraclePriceDeviation,
+
+    #[msg("Amplification coefficient is out of range")]
+    InvalidAmp,
+
+    #[msg("StableSwap invariant did not converge")]
+    CurveNotConverged,
+
+    #[msg("Only StableSwap pools have an amplification coefficient")]
+    NotStableSwap,
+
+    #[msg("Amp ramp is too short, too long or changes A too much")]
+    InvalidRamp,
+
+    #[msg("Pool weights must sum to 10000 bps with each side at least MIN_WEIGHT_BPS")]
+    InvalidWeights,
+
+    #[msg("Tick spacing is out of range")]
+    InvalidTickSpacing,
+
+    #[msg("Tick is out of range or not a multiple of the tick spacing")]
+    InvalidTickRange,
+
+    #[msg("Sqrt price is out of range")]
+    InvalidSqrtPrice,
+
+    #[msg("Tick array does not belong to this pool or does not cover the tick")]
+    InvalidTickArray,
+
+    #[msg("Swap needs a tick array that was not passed")]
+    TickArrayMissing,
+
+    #[msg("Route hops are malformed or do not connect the input and output mints")]
+    InvalidRoute,
+
+    #[msg("Pool has an outstanding flash loan")]
+    FlashLoanOutstanding,
+
+    #[msg("flash_borrow needs a flash_repay for the same pool later in the transaction")]
+    FlashRepayMissing,
+
+    #[msg("flash_borrow must be called directly, not through a CPI")]
+    FlashLoanCpi,
+
+    #[msg("No flash loan to repay")]
+    NoFlashLoan,
+
+    #[msg("Flash swap callback must be another executable program")]
+    InvalidCallback,
+
+    #[msg("Flash swap callback did not pay enough input")]
+    FlashSwapNotRepaid,
+
+    #[msg("Pool mints must be different")]
+    IdenticalMints,
+
+    #[msg("Fee tiers must be 1 to 8 distinct values no higher than the max fee")]
+    InvalidFeeTiers,
+
+    #[msg("Fee tier is not allowed by the global config")]
+    FeeTierNotAllowed,
+
+    #[msg("Pool creation is restricted to the global config admin")]
+    PoolCreationRestricted,
+
+    #[msg("Creation fee accounts are missing or do not match the global config")]
+    InvalidCreationFee,
+
+    #[msg("Swaps are paused on this pool")]
+    SwapsPaused,
+
+    #[msg("Deposits are paused on this pool")]
+    DepositsPaused,
+
+    #[msg("Pool is in withdraw-only mode")]
+    WithdrawOnly,
+
+    #[msg("Protocol is paused, only withdrawals are allowed")]
+    ProtocolPaused,
+
+    #[msg("Signer is not the pending owner of this pool")]
+    NotPendingOwner,
+
+    #[msg("Protocol fees have to be claimed before the ownership is renounced")]
+    UnclaimedProtocolFees,
+
+    #[msg("Pending change is still in its timelock")]
+    ChangeNotReady,
+
+    #[msg("Timelock delay is out of range")]
+    InvalidTimelockDelay,
+
+    #[msg("Pool still has liquidity providers")]
+    PoolNotEmpty,
+
+    #[msg("Pool account is not in a layout migrate_pool can upgrade")]
+    InvalidPoolVersion,
+
+    #[msg("Only one flash_repay per pool may follow a flash_borrow")]
+    DuplicateFlashRepay,
+
+    #[msg("Registered pools have to close their pool pair entry")]
+    PoolPairMissing,
+
+    #[msg("Execute or cancel the pending change before renouncing the ownership")]
+    ChangePending,
+
+    #[msg("Treasury accounts must be owned by the global config fee recipient")]
+    InvalidTreasury,
+
+    #[msg("Pool has to be Active to be closed")]
+    PoolNotActive,
 }
@@ -0,0 +1,270 @@
+use anchor_lang::prelude::*;
+
+use crate::state::{CurveType, ParameterChange, PoolStatus};
+
+// Reserves in every event are the post-trade vault balances minus the owed protocol fees
+
+#[event]
+pub struct PoolInitialized {
+    pub pool: Pubkey,
+    pub admin: Pubkey,
+
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub mint_lp: Pubkey,
+
+    pub seed: u64,
+    pub fee_bps: u16,
+    pub protocol_fee_bps: u16,
+    pub curve: CurveType,
+}
+
+#[event]
+pub struct LiquidityAdded {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub lp_minted: u64,
+
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct Swapped {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+
+    pub is_a: bool, // true when the user paid token A and received token B
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub protocol_fee: u64,
+
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct LiquidityRemoved {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub lp_burned: u64,
+
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct FlashLoanBorrowed {
+    pub pool: Pubkey,
+    pub borrower: Pubkey,
+
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct FlashLoanRepaid {
+    pub pool: Pubkey,
+    pub borrower: Pubkey,
+
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub fee_a: u64, // stays in the vault for the LPs
+    pub fee_b: u64,
+
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct PoolStatusChanged {
+    pub pool: Pubkey,
+    pub authority: Pubkey, // the pool owner or the guardian
+
+    pub status: PoolStatus,
+}
+
+#[event]
+pub struct ProtocolPauseChanged {
+    pub authority: Pubkey,
+
+    pub paused: bool,
+}
+
+#[event]
+pub struct OwnershipTransferStarted {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    pub pending_owner: Option<Pubkey>, // None when the proposal was withdrawn
+}
+
+#[event]
+pub struct OwnershipTransferred {
+    pub pool: Pubkey,
+    pub previous_owner: Pubkey,
+
+    pub new_owner: Option<Pubkey>, // None once the ownership is renounced
+}
+
+#[event]
+pub struct ChangeQueued {
+    pub pool: Pubkey,
+    pub authority: Pubkey, // the global admin for a protocol fee change, the pool owner otherwise
+
+    pub change: ParameterChange,
+    pub executable_at: i64, // users have until then to withdraw
+}
+
+#[event]
+pub struct ChangeExecuted {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+
+    pub change: ParameterChange,
+}
+
+#[event]
+pub struct ChangeCancelled {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+
+    pub change: ParameterChange,
+}
+
+#[event]
+pub struct PoolClosed {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    // left in the vaults, sent to the treasury before they closed
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct PoolMigrated {
+    pub pool: Pubkey,
+    pub payer: Pubkey,
+
+    pub version: u8,
+}
+
+#[event]
+pub struct ProtocolFeesClaimed {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    // sent from the vaults to the treasury token accounts
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct AmpRampStopped {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    pub amp: u64, // A is frozen here until the next ramp
+}
+
+#[event]
+pub struct ObservationCardinalityIncreased {
+    pub pool: Pubkey,
+    pub payer: Pubkey,
+
+    pub cardinality_old: u16,
+    pub cardinality_new: u16,
+}
+
+// Concentrated pools report the price and the active liquidity instead of reserves
+
+#[event]
+pub struct ConcentratedPoolInitialized {
+    pub pool: Pubkey,
+    pub admin: Pubkey,
+
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+
+    pub seed: u64,
+    pub tick_spacing: u16,
+    pub fee_bps: u16,
+    pub sqrt_price_x64: u128,
+    pub tick_current: i32,
+}
+
+#[event]
+pub struct TickArrayInitialized {
+    pub pool: Pubkey,
+    pub payer: Pubkey,
+
+    pub tick_array: Pubkey,
+    pub start_tick_index: i32,
+}
+
+#[event]
+pub struct PositionOpened {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    pub position: Pubkey,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+}
+
+#[event]
+pub struct ConcentratedLiquidityAdded {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    pub position: Pubkey,
+    pub liquidity: u128,
+    pub amount_a: u64,
+    pub amount_b: u64,
+
+    pub pool_liquidity: u128, // only changes when the position is in range
+}
+
+#[event]
+pub struct ConcentratedLiquidityRemoved {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    pub position: Pubkey,
+    pub liquidity: u128,
+    pub amount_a: u64,
+    pub amount_b: u64,
+
+    pub pool_liquidity: u128,
+}
+
+#[event]
+pub struct PositionFeesCollected {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+
+    pub position: Pubkey,
+    pub fees_a: u64,
+    pub fees_b: u64,
+}
+
+#[event]
+pub struct ConcentratedSwapped {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+
+    pub is_a: bool, // true when the user paid token A and received token B
+    pub amount_in: u64,
+    pub amount_out: u64,
+
+    pub sqrt_price_x64: u128,
+    pub tick_current: i32,
+    pub liquidity: u128,
+}
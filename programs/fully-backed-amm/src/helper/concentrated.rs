@@ -0,0 +1,411 @@
+use crate::{
+    constant::{FEE_DENOMINATOR, MAX_TICK, MIN_TICK},
+    error::AMMError,
+    state::Tick,
+};
+use anchor_lang::prelude::*;
+
+// Concentrated liquidity math, same model as Uniswap V3 with Q64.64 square root prices:
+// sqrt_price_x64 = sqrt(1.0001^tick) * 2^64, liquidity L = sqrt(x * y) inside the active range
+// dA = L * (1/sqrt_lower - 1/sqrt_upper), dB = L * (sqrt_upper - sqrt_lower)
+
+pub const Q64: u128 = 1 << 64;
+
+// (1/sqrt(1.0001))^(2^i) in Q64.64, for i in 0..19
+const TICK_RATIOS: [u128; 19] = [
+    0xfffcb933bd6fad37,
+    0xfff97272373d4132,
+    0xfff2e50f5f656932,
+    0xffe5caca7e10e4e6,
+    0xffcb9843d60f6159,
+    0xff973b41fa98c081,
+    0xff2ea16466c96a38,
+    0xfe5dee046a99a2a8,
+    0xfcbe86c7900a88ae,
+    0xf987a7253ac41317,
+    0xf3392b0822b70005,
+    0xe7159475a2c29b74,
+    0xd097f3bdfd2022b8,
+    0xa9f746462d870fdf,
+    0x70d869a156d2a1b8,
+    0x31be135f97d08fd9,
+    0x9aa508b5b7a84e1,
+    0x5d6af8dedb8119,
+    0x2216e584f5fa,
+];
+
+// a * b / c with a 256 bit intermediate product
+pub fn full_mul_div(a: u128, b: u128, c: u128, round_up: bool) -> Result<u128> {
+    require!(c != 0, AMMError::AmountZero);
+
+    // a * b = hi * 2^128 + lo
+    let (a1, a0) = (a >> 64, a & (Q64 - 1));
+    let (b1, b0) = (b >> 64, b & (Q64 - 1));
+    let (cross_1, cross_2) = (a1 * b0, a0 * b1);
+    let (lo, carry_1) = (a0 * b0).overflowing_add(cross_1 << 64);
+    let (lo, carry_2) = lo.overflowing_add(cross_2 << 64);
+    let hi = a1 * b1 + (cross_1 >> 64) + (cross_2 >> 64) + carry_1 as u128 + carry_2 as u128;
+
+    // the quotient has to fit in 128 bits
+    require!(hi < c, AMMError::Overflow);
+
+    // binary long division of (hi, lo) by c, rem stays below c
+    let mut quotient = 0u128;
+    let mut rem = hi;
+    for i in (0..128).rev() {
+        let carry = rem >> 127;
+        rem = (rem << 1) | ((lo >> i) & 1);
+        if carry == 1 || rem >= c {
+            rem = rem.wrapping_sub(c);
+            quotient |= 1 << i;
+        }
+    }
+
+    match round_up && rem != 0 {
+        true => quotient.checked_add(1).ok_or(AMMError::Overflow.into()),
+        false => Ok(quotient),
+    }
+}
+
+pub fn sqrt_price_at_tick(tick: i32) -> Result<u128> {
+    require!(
+        (MIN_TICK..=MAX_TICK).contains(&tick),
+        AMMError::InvalidTickRange
+    );
+
+    // 1.0001^(-|tick|/2) from the binary expansion of |tick|, every factor is below one
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = Q64;
+    for (i, factor) in TICK_RATIOS.iter().enumerate() {
+        if abs_tick & (1 << i) != 0 {
+            ratio = (ratio * factor) >> 64;
+        }
+    }
+
+    match tick > 0 {
+        true => Ok(u128::MAX / ratio),
+        false => Ok(ratio),
+    }
+}
+
+// greatest tick whose sqrt price is at or below sqrt_price_x64
+pub fn tick_at_sqrt_price(sqrt_price_x64: u128) -> Result<i32> {
+    require!(
+        sqrt_price_x64 >= sqrt_price_at_tick(MIN_TICK)?
+            && sqrt_price_x64 <= sqrt_price_at_tick(MAX_TICK)?,
+        AMMError::InvalidSqrtPrice
+    );
+
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        match sqrt_price_at_tick(mid)? <= sqrt_price_x64 {
+            true => low = mid,
+            false => high = mid - 1,
+        }
+    }
+    Ok(low)
+}
+
+// dA = L * (sqrt_upper - sqrt_lower) / (sqrt_upper * sqrt_lower)
+pub fn amount_a_delta(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<u128> {
+    require!(sqrt_price_lower != 0, AMMError::InvalidSqrtPrice);
+    let (lower, upper) = (
+        sqrt_price_lower.min(sqrt_price_upper),
+        sqrt_price_lower.max(sqrt_price_upper),
+    );
+
+    let amount = full_mul_div(liquidity, upper - lower, upper, round_up)?;
+    full_mul_div(amount, Q64, lower, round_up)
+}
+
+// dB = L * (sqrt_upper - sqrt_lower)
+pub fn amount_b_delta(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<u128> {
+    let difference = sqrt_price_lower.abs_diff(sqrt_price_upper);
+    full_mul_div(liquidity, difference, Q64, round_up)
+}
+
+// token amounts backing `liquidity` in [sqrt_price_lower, sqrt_price_upper) at the current price
+pub fn amounts_for_liquidity(
+    sqrt_price: u128,
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<(u64, u64)> {
+    let (amount_a, amount_b) = if sqrt_price < sqrt_price_lower {
+        // range is above the price, only token A
+        let amount_a = amount_a_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?;
+        (amount_a, 0)
+    } else if sqrt_price >= sqrt_price_upper {
+        // range is below the price, only token B
+        let amount_b = amount_b_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?;
+        (0, amount_b)
+    } else {
+        (
+            amount_a_delta(sqrt_price, sqrt_price_upper, liquidity, round_up)?,
+            amount_b_delta(sqrt_price_lower, sqrt_price, liquidity, round_up)?,
+        )
+    };
+
+    Ok((
+        u64::try_from(amount_a).map_err(|_| AMMError::Overflow)?,
+        u64::try_from(amount_b).map_err(|_| AMMError::Overflow)?,
+    ))
+}
+
+// sqrt price after adding amount_in of the input token, rounded so the price moves less
+fn next_sqrt_price_from_input(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount_in: u64,
+    a_to_b: bool,
+) -> Result<u128> {
+    match a_to_b {
+        // sqrt' = L * sqrt / (L + dA * sqrt), rounded up
+        true => {
+            let denominator = liquidity
+                .checked_add(full_mul_div(amount_in as u128, sqrt_price, Q64, false)?)
+                .ok_or(AMMError::Overflow)?;
+            full_mul_div(liquidity, sqrt_price, denominator, true)
+        }
+        // sqrt' = sqrt + dB / L, rounded down
+        false => sqrt_price
+            .checked_add(full_mul_div(amount_in as u128, Q64, liquidity, false)?)
+            .ok_or(AMMError::Overflow.into()),
+    }
+}
+
+pub struct SwapStep {
+    pub sqrt_price_next: u128,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee_amount: u64,
+}
+
+impl SwapStep {
+    // one exact input step from sqrt_price towards sqrt_price_target inside a constant liquidity
+    pub fn compute(
+        sqrt_price: u128,
+        sqrt_price_target: u128,
+        liquidity: u128,
+        amount_remaining: u64,
+        fee_bps: u16,
+    ) -> Result<SwapStep> {
+        let a_to_b = sqrt_price >= sqrt_price_target;
+        let fee_rate = FEE_DENOMINATOR - fee_bps as u64;
+        let amount_remaining_less_fee =
+            (amount_remaining as u128 * fee_rate as u128 / FEE_DENOMINATOR as u128) as u64;
+
+        let amount_in_to_target = match a_to_b {
+            true => amount_a_delta(sqrt_price_target, sqrt_price, liquidity, true)?,
+            false => amount_b_delta(sqrt_price, sqrt_price_target, liquidity, true)?,
+        };
+
+        let sqrt_price_next = match amount_remaining_less_fee as u128 >= amount_in_to_target {
+            true => sqrt_price_target,
+            false => next_sqrt_price_from_input(
+                sqrt_price,
+                liquidity,
+                amount_remaining_less_fee,
+                a_to_b,
+            )?,
+        };
+        let reached_target = sqrt_price_next == sqrt_price_target;
+
+        let (amount_in, amount_out) = match a_to_b {
+            true => (
+                amount_a_delta(sqrt_price_next, sqrt_price, liquidity, true)?,
+                amount_b_delta(sqrt_price_next, sqrt_price, liquidity, false)?,
+            ),
+            false => (
+                amount_b_delta(sqrt_price, sqrt_price_next, liquidity, true)?,
+                amount_a_delta(sqrt_price, sqrt_price_next, liquidity, false)?,
+            ),
+        };
+        let amount_in = u64::try_from(amount_in).map_err(|_| AMMError::Overflow)?;
+        let amount_out = u64::try_from(amount_out).map_err(|_| AMMError::Overflow)?;
+
+        // a partial step keeps the whole remainder, the pool takes what it didn't use as fee
+        let fee_amount = match reached_target {
+            true => (amount_in as u128 * fee_bps as u128).div_ceil(fee_rate as u128) as u64,
+            false => amount_remaining
+                .checked_sub(amount_in)
+                .ok_or(AMMError::Overflow)?,
+        };
+
+        Ok(SwapStep {
+            sqrt_price_next,
+            amount_in,
+            amount_out,
+            fee_amount,
+        })
+    }
+}
+
+// fees earned per unit of liquidity between two ticks, Uniswap V3 fee growth inside
+pub fn fee_growth_inside(
+    lower: &Tick,
+    tick_lower: i32,
+    upper: &Tick,
+    tick_upper: i32,
+    tick_current: i32,
+    fee_growth_global_a: u128,
+    fee_growth_global_b: u128,
+) -> (u128, u128) {
+    let below = |outside: u128, global: u128| match tick_current >= tick_lower {
+        true => outside,
+        false => global.wrapping_sub(outside),
+    };
+    let above = |outside: u128, global: u128| match tick_current < tick_upper {
+        true => outside,
+        false => global.wrapping_sub(outside),
+    };
+
+    (
+        fee_growth_global_a
+            .wrapping_sub(below(lower.fee_growth_outside_a, fee_growth_global_a))
+            .wrapping_sub(above(upper.fee_growth_outside_a, fee_growth_global_a)),
+        fee_growth_global_b
+            .wrapping_sub(below(lower.fee_growth_outside_b, fee_growth_global_b))
+            .wrapping_sub(above(upper.fee_growth_outside_b, fee_growth_global_b)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_mul_div_matches_u128_when_it_fits() {
+        for (a, b, c) in [
+            (7, 9, 4),
+            (u64::MAX as u128, 3, 5),
+            (1 << 100, 1 << 20, 1 << 30),
+        ] {
+            assert_eq!(full_mul_div(a, b, c, false).unwrap(), a * b / c);
+            assert_eq!(full_mul_div(a, b, c, true).unwrap(), (a * b).div_ceil(c));
+        }
+    }
+
+    #[test]
+    fn full_mul_div_uses_the_full_product() {
+        assert_eq!(
+            full_mul_div(u128::MAX, u128::MAX, u128::MAX, false).unwrap(),
+            u128::MAX
+        );
+        assert_eq!(full_mul_div(1 << 127, 4, 8, false).unwrap(), 1 << 126);
+        assert!(full_mul_div(u128::MAX, 2, 1, false).is_err());
+    }
+
+    #[test]
+    fn sqrt_price_at_tick_matches_f64() {
+        assert_eq!(sqrt_price_at_tick(0).unwrap(), Q64);
+        for tick in [-443_636, -100_000, -1, 1, 10, 1_000, 100_000, 443_636] {
+            let expected = 1.0001f64.powf(tick as f64 / 2.0);
+            let actual = sqrt_price_at_tick(tick).unwrap() as f64 / Q64 as f64;
+            assert!(
+                (actual - expected).abs() <= expected * 1e-9,
+                "tick = {tick}"
+            );
+        }
+        assert!(sqrt_price_at_tick(MAX_TICK + 1).is_err());
+    }
+
+    #[test]
+    fn tick_at_sqrt_price_inverts_sqrt_price_at_tick() {
+        for tick in [MIN_TICK, -50_001, -1, 0, 1, 887, 200_000, MAX_TICK] {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            assert_eq!(tick_at_sqrt_price(sqrt_price).unwrap(), tick);
+            if tick < MAX_TICK {
+                assert_eq!(tick_at_sqrt_price(sqrt_price + 1).unwrap(), tick);
+            }
+            if tick > MIN_TICK {
+                assert_eq!(tick_at_sqrt_price(sqrt_price - 1).unwrap(), tick - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn amounts_for_liquidity_depend_on_the_range() {
+        let lower = sqrt_price_at_tick(-1_000).unwrap();
+        let upper = sqrt_price_at_tick(1_000).unwrap();
+
+        let (a, b) = amounts_for_liquidity(Q64, lower, upper, 1_000_000, true).unwrap();
+        assert!(a > 0 && b > 0 && a.abs_diff(b) <= 1);
+
+        let below = sqrt_price_at_tick(-2_000).unwrap();
+        assert_eq!(
+            amounts_for_liquidity(below, lower, upper, 1_000_000, true)
+                .unwrap()
+                .1,
+            0
+        );
+        let above = sqrt_price_at_tick(2_000).unwrap();
+        assert_eq!(
+            amounts_for_liquidity(above, lower, upper, 1_000_000, true)
+                .unwrap()
+                .0,
+            0
+        );
+    }
+
+    #[test]
+    fn swap_step_stops_at_the_target() {
+        let target = sqrt_price_at_tick(-10).unwrap();
+        let step = SwapStep::compute(Q64, target, 1_000_000_000, u64::MAX / 4, 30).unwrap();
+
+        assert_eq!(step.sqrt_price_next, target);
+        let expected_in = amount_a_delta(target, Q64, 1_000_000_000, true).unwrap() as u64;
+        assert_eq!(step.amount_in, expected_in);
+        assert!(step.fee_amount > 0);
+    }
+
+    #[test]
+    fn swap_step_keeps_the_remainder_as_fee_on_a_partial_step() {
+        let target = sqrt_price_at_tick(-10_000).unwrap();
+        let step = SwapStep::compute(Q64, target, 1_000_000_000, 10_000, 30).unwrap();
+
+        assert!(step.sqrt_price_next > target && step.sqrt_price_next < Q64);
+        assert_eq!(step.amount_in + step.fee_amount, 10_000);
+        // close to 1:1 at the current price, minus the 0.3% fee
+        assert!(step.amount_out < 9_970 && step.amount_out > 9_960);
+    }
+
+    #[test]
+    fn swap_step_b_to_a_moves_the_price_up() {
+        let target = sqrt_price_at_tick(10_000).unwrap();
+        let step = SwapStep::compute(Q64, target, 1_000_000_000, 10_000, 0).unwrap();
+
+        assert!(step.sqrt_price_next > Q64);
+        assert_eq!(step.amount_in, 10_000);
+        assert!(step.amount_out < 10_000 && step.amount_out > 9_990);
+    }
+
+    #[test]
+    fn fee_growth_inside_only_counts_the_range() {
+        let lower = Tick {
+            fee_growth_outside_a: 10,
+            ..Tick::default()
+        };
+        let upper = Tick {
+            fee_growth_outside_a: 5,
+            ..Tick::default()
+        };
+
+        // price inside the range: global - below - above
+        assert_eq!(fee_growth_inside(&lower, -10, &upper, 10, 0, 100, 0).0, 85);
+        // price below the range: outside_lower - outside_upper
+        assert_eq!(fee_growth_inside(&lower, -10, &upper, 10, -20, 100, 0).0, 5);
+    }
+}
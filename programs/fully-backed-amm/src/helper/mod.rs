@@ -1,7 +1,19 @@
-use crate::error::AMMError;
-use anchor_lang::prelude::*;
+use crate::{
+    constant::{FEE_DENOMINATOR, MINIMUM_LIQUIDITY},
+    error::AMMError,
+    state::{CurveType, GlobalConfig},
+};
+use anchor_lang::{
+    prelude::*,
+    system_program::{transfer, Transfer},
+};
+use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TransferChecked};
 use fixed::types::I64F64;
 
+pub mod concentrated;
+pub mod stable_swap;
+pub mod weighted;
+
 macro_rules! check_zero {
     ($arr:expr) => {
         if $arr.contains(&0u64) {
@@ -10,17 +22,6 @@ macro_rules! check_zero {
     };
 }
 
-#[macro_export]
-macro_rules! check_asset {
-    ($amount_a:expr, $amount_b:expr, $total_amount_a:expr, $total_amount_b:expr) => {
-        require!(
-            $total_amount_a / $total_amount_b
-                == ($total_amount_a + $amount_a) / ($total_amount_b + $amount_b),
-            AMMError::NotValidAsset
-        );
-    };
-}
-
 #[macro_export]
 macro_rules! swap_slippage_check {
     ($slippage_amount:expr,$actual_amount:expr) => {
@@ -30,61 +31,172 @@ macro_rules! swap_slippage_check {
     };
 }
 
+#[macro_export]
+macro_rules! deposit_slippage_check {
+    ($min_lp_out:expr,$lp_amount:expr) => {
+        if $min_lp_out > $lp_amount {
+            return err!(AMMError::MinLpOutNotMet);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! withdraw_slippage_check {
+    ($min_amount:expr,$actual_amount:expr) => {
+        if $min_amount > $actual_amount {
+            return err!(AMMError::WithdrawSlippageExceeded);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! swap_max_input_check {
+    ($max_amount_in:expr,$actual_amount:expr) => {
+        if $actual_amount > $max_amount_in {
+            return err!(AMMError::MaxAmountInExceeded);
+        }
+    };
+}
+
+// a * b / c in u128, rounded down
+pub fn mul_div(a: u64, b: u64, c: u64) -> Result<u64> {
+    require!(c != 0, AMMError::AmountZero);
+    let result = (a as u128)
+        .checked_mul(b as u128)
+        .ok_or(AMMError::Overflow)?
+        / c as u128;
+
+    u64::try_from(result).map_err(|_| AMMError::Overflow.into())
+}
+
+// a * b / c in u128, rounded up
+pub fn mul_div_ceil(a: u64, b: u64, c: u64) -> Result<u64> {
+    require!(c != 0, AMMError::AmountZero);
+    let result = (a as u128)
+        .checked_mul(b as u128)
+        .ok_or(AMMError::Overflow)?
+        .div_ceil(c as u128);
+
+    u64::try_from(result).map_err(|_| AMMError::Overflow.into())
+}
+
+// floor(sqrt(n)) using Newton's method
+pub fn integer_sqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    let mut x = n / 2 + 1;
+    let mut y = (x + n / x) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
 pub struct LiquidityPool {
     pub total_amount_a: u64,
     pub total_amount_b: u64,
     pub deposit_amount_a: u64,
     pub deposit_amount_b: u64,
     pub mint_supply: u64,
+    pub curve: CurveType,
 }
 
 impl LiquidityPool {
     pub fn calculate_liquidity(lp_request: LiquidityPool) -> Result<u64> {
-        if lp_request.mint_supply == 0
-            && lp_request.total_amount_a == 0
-            && lp_request.total_amount_b == 0
-        {
+        // no lp supply is the first deposit, even with tokens donated to the vaults before it.
+        // The first LP gets those, instead of the pool never taking a deposit
+        if lp_request.mint_supply == 0 {
             let lp_amount = LiquidityPool::calculate_first_liquidity(
                 lp_request.deposit_amount_a,
                 lp_request.deposit_amount_b,
+                lp_request.curve,
             )?;
             Ok(lp_amount)
         } else {
-            check_asset!(
-                lp_request.deposit_amount_a,
-                lp_request.deposit_amount_b,
-                lp_request.total_amount_a,
-                lp_request.total_amount_b
-            );
             let lp_amount = LiquidityPool::calculate_lp_token(lp_request)?;
 
             Ok(lp_amount)
         }
     }
 
-    fn calculate_first_liquidity(amount_x: u64, amount_y: u64) -> Result<u64> {
-        // K = sqrt(XY); this is given to the admin who created the pool
-        // Let's say X = 1000 and Y = 1000, K = 1000 lp tokens
+    // Same as the Uniswap V2 router: take all of one side and the matching amount of the
+    // other side at the current vault ratio, never more than the provider allowed.
+    // For weighted pools the vault ratio already is the weighted ratio, so this holds for every curve
+    pub fn optimal_deposit(
+        max_amount_a: u64,
+        max_amount_b: u64,
+        total_amount_a: u64,
+        total_amount_b: u64,
+    ) -> Result<(u64, u64)> {
+        if total_amount_a == 0 && total_amount_b == 0 {
+            return Ok((max_amount_a, max_amount_b));
+        }
+        check_zero!([total_amount_a, total_amount_b]);
+
+        // dy = dx * Y / X, rounded up since the provider owes it
+        let amount_b_optimal = mul_div_ceil(max_amount_a, total_amount_b, total_amount_a)?;
+        if amount_b_optimal <= max_amount_b {
+            return Ok((max_amount_a, amount_b_optimal));
+        }
+
+        let amount_a_optimal = mul_div_ceil(max_amount_b, total_amount_a, total_amount_b)?;
+        require!(amount_a_optimal <= max_amount_a, AMMError::NotValidAsset);
+
+        Ok((amount_a_optimal, max_amount_b))
+    }
+
+    fn calculate_first_liquidity(amount_x: u64, amount_y: u64, curve: CurveType) -> Result<u64> {
+        // K = sqrt(XY) - MINIMUM_LIQUIDITY; this is given to the admin who created the pool
+        // Let's say X = 10000 and Y = 10000, K = 9000 lp tokens and 1000 are locked
+        // StableSwap pools use K = D - MINIMUM_LIQUIDITY instead, like Curve, and weighted
+        // pools the weighted geometric mean a^w_a * b^w_b
         check_zero!([amount_x, amount_y]);
-        let liquidity = (amount_x.checked_mul(amount_y).ok_or(AMMError::Overflow)? as f64).sqrt();
+        let liquidity = match curve {
+            CurveType::ConstantProduct => {
+                let product = (amount_x as u128)
+                    .checked_mul(amount_y as u128)
+                    .ok_or(AMMError::Overflow)?;
 
-        Ok(liquidity.round() as u64)
+                // rounded down, sqrt of a u128 always fits in a u64
+                integer_sqrt(product) as u64
+            }
+            CurveType::StableSwap { amp } => {
+                let d = stable_swap::compute_d(amp, amount_x, amount_y)?;
+                u64::try_from(d).map_err(|_| AMMError::Overflow)?
+            }
+            CurveType::Weighted { weight_a, weight_b } => {
+                weighted::invariant(amount_x, amount_y, weight_a, weight_b)?
+            }
+        };
+        require!(liquidity > MINIMUM_LIQUIDITY, AMMError::MinAmount);
+
+        Ok(liquidity - MINIMUM_LIQUIDITY)
     }
 
     fn calculate_lp_token(lp_deposite: LiquidityPool) -> Result<u64> {
-        // s = min(dx/X,dy/Y)*T
+        // s = min(dx/X,dy/Y)*T = min(dx*T/X, dy*T/Y), rounded down
         check_zero!([
             lp_deposite.total_amount_a,
+            lp_deposite.total_amount_b,
             lp_deposite.deposit_amount_a,
+            lp_deposite.deposit_amount_b,
             lp_deposite.mint_supply
         ]);
 
-        let lp_token = (lp_deposite.mint_supply as f64)
-            * f64::min(
-                (lp_deposite.deposit_amount_a as f64) / (lp_deposite.total_amount_a as f64),
-                (lp_deposite.deposit_amount_b as f64) / (lp_deposite.total_amount_b as f64),
-            );
-        Ok(lp_token as u64)
+        let lp_token_a = mul_div(
+            lp_deposite.deposit_amount_a,
+            lp_deposite.mint_supply,
+            lp_deposite.total_amount_a,
+        )?;
+        let lp_token_b = mul_div(
+            lp_deposite.deposit_amount_b,
+            lp_deposite.mint_supply,
+            lp_deposite.total_amount_b,
+        )?;
+        Ok(lp_token_a.min(lp_token_b))
     }
 }
 
@@ -93,10 +205,40 @@ pub struct SwapToken {
     pub deposit_amount: u64,
     pub total_amount_a: u64,
     pub total_amount_b: u64,
+    pub fee_bps: u16,
+    pub curve: CurveType,
 }
 
 impl SwapToken {
-    pub fn swap_token(swap_arg: SwapToken) -> Result<u64> {
+    pub fn swap_token(mut swap_arg: SwapToken) -> Result<u64> {
+        // fee is taken off the input before quoting, the full input still goes to the vault
+        swap_arg.deposit_amount =
+            SwapToken::amount_after_fee(swap_arg.deposit_amount, swap_arg.fee_bps)?;
+
+        let (total_in, total_out) = match swap_arg.is_a {
+            true => (swap_arg.total_amount_a, swap_arg.total_amount_b),
+            false => (swap_arg.total_amount_b, swap_arg.total_amount_a),
+        };
+        match swap_arg.curve {
+            CurveType::StableSwap { amp } => {
+                return stable_swap::swap_out(amp, total_in, total_out, swap_arg.deposit_amount);
+            }
+            CurveType::Weighted { weight_a, weight_b } => {
+                let (weight_in, weight_out) = match swap_arg.is_a {
+                    true => (weight_a, weight_b),
+                    false => (weight_b, weight_a),
+                };
+                return weighted::swap_out(
+                    total_in,
+                    total_out,
+                    weight_in,
+                    weight_out,
+                    swap_arg.deposit_amount,
+                );
+            }
+            CurveType::ConstantProduct => {}
+        }
+
         match swap_arg.is_a {
             true => {
                 // dy = Ydx/(X + dx)
@@ -112,32 +254,113 @@ impl SwapToken {
     }
 
     fn swap_b_for_a(swap_arg: SwapToken) -> Result<u64> {
-        let total_b = I64F64::from_num(swap_arg.total_amount_b);
-        let total_a = I64F64::from_num(swap_arg.total_amount_a);
-        let deposit_a = I64F64::from_num(swap_arg.deposit_amount);
+        // swap_amount = total_b * deposit_a / (total_a + deposit_a), rounded down
+        let denominator = swap_arg
+            .total_amount_a
+            .checked_add(swap_arg.deposit_amount)
+            .ok_or(AMMError::Overflow)?;
 
-        // swap_amount = total_b * deposit_a / (total_a + deposit_a)
-        let numerator = total_b.checked_mul(deposit_a).ok_or(AMMError::Overflow)?;
-        let denominator = total_a + deposit_a;
-        let swap_amount = numerator / denominator;
-        Ok(swap_amount.round().to_num::<u64>()) // sending rounding number, which might cause some percision loss
+        mul_div(
+            swap_arg.total_amount_b,
+            swap_arg.deposit_amount,
+            denominator,
+        )
     }
 
     fn swap_a_for_b(swap_arg: SwapToken) -> Result<u64> {
-        // dx = Xdy/(Y + dy)
-        let total_amount_a = I64F64::from_num(swap_arg.total_amount_a);
-        let total_amount_b = I64F64::from_num(swap_arg.total_amount_b);
-        let deposit_b = I64F64::from_num(swap_arg.deposit_amount);
-
-        let numerator = total_amount_a
-            .checked_mul(deposit_b)
-            .ok_or(AMMError::Overflow)?;
-        let denominator = total_amount_b
-            .checked_add(deposit_b)
+        // dx = Xdy/(Y + dy), rounded down
+        let denominator = swap_arg
+            .total_amount_b
+            .checked_add(swap_arg.deposit_amount)
             .ok_or(AMMError::Overflow)?;
 
-        let swap_amount = numerator / denominator;
-        Ok(swap_amount.round().to_num::<u64>()) // sending rounding number, which might cause some percision loss
+        mul_div(
+            swap_arg.total_amount_a,
+            swap_arg.deposit_amount,
+            denominator,
+        )
+    }
+
+    // protocol share of the input amount, taken before the LP fee
+    pub fn protocol_fee(amount: u64, protocol_fee_bps: u16) -> Result<u64> {
+        let protocol_fee = (amount as u128)
+            .checked_mul(protocol_fee_bps as u128)
+            .ok_or(AMMError::Overflow)?
+            / FEE_DENOMINATOR as u128;
+
+        Ok(protocol_fee as u64)
+    }
+
+    // dx' = dx * (10_000 - fee_bps) / 10_000, rounded down so the pool keeps the dust
+    fn amount_after_fee(amount: u64, fee_bps: u16) -> Result<u64> {
+        let amount_after_fee = (amount as u128)
+            .checked_mul((FEE_DENOMINATOR - fee_bps as u64) as u128)
+            .ok_or(AMMError::Overflow)?
+            / FEE_DENOMINATOR as u128;
+
+        Ok(amount_after_fee as u64)
+    }
+}
+
+pub struct SwapExactOut {
+    pub is_a: bool,
+    pub amount_out: u64,
+    pub total_amount_a: u64,
+    pub total_amount_b: u64,
+    pub fee_bps: u16,
+    pub protocol_fee_bps: u16,
+    pub curve: CurveType,
+}
+
+impl SwapExactOut {
+    // Inverse of SwapToken::swap_token, every step rounds up since the user owes the input
+    pub fn calculate_amount_in(swap_arg: SwapExactOut) -> Result<u64> {
+        let (total_in, total_out) = match swap_arg.is_a {
+            true => (swap_arg.total_amount_a, swap_arg.total_amount_b),
+            false => (swap_arg.total_amount_b, swap_arg.total_amount_a),
+        };
+        check_zero!([swap_arg.amount_out, total_in]);
+        require!(
+            swap_arg.amount_out < total_out,
+            AMMError::InsufficientLiquidity
+        );
+
+        let amount_in_after_fee = match swap_arg.curve {
+            // dx = X*dy/(Y - dy)
+            CurveType::ConstantProduct => mul_div_ceil(
+                total_in,
+                swap_arg.amount_out,
+                total_out - swap_arg.amount_out,
+            )?,
+            CurveType::StableSwap { amp } => {
+                stable_swap::amount_in(amp, total_in, total_out, swap_arg.amount_out)?
+            }
+            CurveType::Weighted { weight_a, weight_b } => {
+                let (weight_in, weight_out) = match swap_arg.is_a {
+                    true => (weight_a, weight_b),
+                    false => (weight_b, weight_a),
+                };
+                weighted::amount_in(
+                    total_in,
+                    total_out,
+                    weight_in,
+                    weight_out,
+                    swap_arg.amount_out,
+                )?
+            }
+        };
+
+        // undo the LP fee and then the protocol fee
+        let amount_in_before_fee = mul_div_ceil(
+            amount_in_after_fee,
+            FEE_DENOMINATOR,
+            FEE_DENOMINATOR - swap_arg.fee_bps as u64,
+        )?;
+        mul_div_ceil(
+            amount_in_before_fee,
+            FEE_DENOMINATOR,
+            FEE_DENOMINATOR - swap_arg.protocol_fee_bps as u64,
+        )
     }
 }
 
@@ -153,9 +376,718 @@ impl WithdrawAsset {
     // for value of each lp token to token_a is:- a = A/T
     pub fn calculate_token(withdraw_arg: WithdrawAsset) -> Result<u64> {
         check_zero!([withdraw_arg.lp_share_amount, withdraw_arg.mint_supply]);
-        let lp_ratio = withdraw_arg.lp_share_amount as f64 / withdraw_arg.mint_supply as f64; // S/T
 
-        let token_amount = (withdraw_arg.total_amount_vault as f64) * lp_ratio; //  Eg:- A(S/T)
-        Ok(token_amount as u64)
+        // A(S/T) = A*S/T, rounded down
+        mul_div(
+            withdraw_arg.total_amount_vault,
+            withdraw_arg.lp_share_amount,
+            withdraw_arg.mint_supply,
+        )
+    }
+}
+
+// spot price numerator/denominator, saturating at I64F64::MAX when it doesn't fit
+pub fn spot_price(numerator: u64, denominator: u64) -> I64F64 {
+    I64F64::saturating_from_num(numerator)
+        .checked_div(I64F64::saturating_from_num(denominator))
+        .unwrap_or(I64F64::MAX)
+}
+
+pub struct PriceCumulative {
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    pub time_elapsed: u64,
+}
+
+impl PriceCumulative {
+    // Uniswap V2 style: cumulative += price * time_elapsed, on the raw I64F64 bits.
+    // It is meant to wrap around, only the difference between two observations matters
+    pub fn accumulate(cumulative_arg: PriceCumulative) -> (u128, u128) {
+        if cumulative_arg.time_elapsed == 0
+            || cumulative_arg.reserve_a == 0
+            || cumulative_arg.reserve_b == 0
+        {
+            return (
+                cumulative_arg.price_a_cumulative,
+                cumulative_arg.price_b_cumulative,
+            );
+        }
+
+        // price of A in B and of B in A
+        let price_a = spot_price(cumulative_arg.reserve_b, cumulative_arg.reserve_a);
+        let price_b = spot_price(cumulative_arg.reserve_a, cumulative_arg.reserve_b);
+
+        (
+            cumulative_arg.price_a_cumulative.wrapping_add(
+                (price_a.to_bits() as u128).wrapping_mul(cumulative_arg.time_elapsed as u128),
+            ),
+            cumulative_arg.price_b_cumulative.wrapping_add(
+                (price_b.to_bits() as u128).wrapping_mul(cumulative_arg.time_elapsed as u128),
+            ),
+        )
+    }
+}
+
+pub struct Twap {
+    pub cumulative_start: u128,
+    pub cumulative_end: u128,
+    pub timestamp_start: i64,
+    pub timestamp_end: i64,
+}
+
+impl Twap {
+    // TWAP = (cumulative_end - cumulative_start) / (timestamp_end - timestamp_start)
+    pub fn calculate_twap(twap_arg: Twap) -> Result<I64F64> {
+        require!(
+            twap_arg.timestamp_end > twap_arg.timestamp_start,
+            AMMError::InvalidObservation
+        );
+
+        let time_elapsed = (twap_arg.timestamp_end - twap_arg.timestamp_start) as u128;
+        let price_delta = twap_arg
+            .cumulative_end
+            .wrapping_sub(twap_arg.cumulative_start);
+
+        Ok(I64F64::from_bits((price_delta / time_elapsed) as i128))
+    }
+}
+
+// value * 10^expo
+fn scale_by_power_of_ten(value: I64F64, expo: i32) -> Result<I64F64> {
+    let ten = I64F64::from_num(10);
+    let mut scaled = value;
+    for _ in 0..expo.unsigned_abs() {
+        scaled = if expo > 0 {
+            scaled.checked_mul(ten).ok_or(AMMError::Overflow)?
+        } else {
+            scaled / ten
+        };
+    }
+    Ok(scaled)
+}
+
+pub struct OracleGuard {
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    pub decimals_a: u8,
+    pub decimals_b: u8,
+
+    // Pyth price of A in B, price * 10^expo
+    pub oracle_price: i64,
+    pub oracle_conf: u64,
+    pub oracle_expo: i32,
+
+    pub max_deviation_bps: u16,
+    pub max_confidence_bps: u16,
+}
+
+impl OracleGuard {
+    pub fn check_price(guard: OracleGuard) -> Result<()> {
+        require!(guard.oracle_price > 0, AMMError::OracleInvalid);
+
+        // conf/price <= max_confidence_bps/10_000
+        require!(
+            guard.oracle_conf as u128 * FEE_DENOMINATOR as u128
+                <= guard.oracle_price as u128 * guard.max_confidence_bps as u128,
+            AMMError::OracleConfidenceTooWide
+        );
+
+        // pool price in whole tokens: (B / 10^decimals_b) / (A / 10^decimals_a)
+        let pool_price = scale_by_power_of_ten(
+            spot_price(guard.reserve_b, guard.reserve_a),
+            guard.decimals_a as i32 - guard.decimals_b as i32,
+        )?;
+        let oracle_price =
+            scale_by_power_of_ten(I64F64::from_num(guard.oracle_price), guard.oracle_expo)?;
+        require!(oracle_price > 0, AMMError::OracleInvalid);
+
+        // |pool - oracle| / oracle <= max_deviation_bps/10_000
+        let deviation_bps = (pool_price - oracle_price)
+            .abs()
+            .checked_div(oracle_price)
+            .and_then(|deviation| deviation.checked_mul(I64F64::from_num(FEE_DENOMINATOR)))
+            .ok_or(AMMError::Overflow)?;
+        require!(
+            deviation_bps <= I64F64::from_num(guard.max_deviation_bps),
+            AMMError::OraclePriceDeviation
+        );
+
+        Ok(())
+    }
+}
+
+// Accounts the pool creation fee moves through, shared by initialize and
+// initialize_concentrated_pool. The token ones are only read when the fee is paid in a token
+pub struct CreationFee<'a, 'info> {
+    pub config: &'a GlobalConfig,
+    pub payer: AccountInfo<'info>,
+    pub fee_recipient: AccountInfo<'info>,
+
+    pub fee_mint: Option<&'a InterfaceAccount<'info, Mint>>,
+    pub payer_fee_token: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    pub fee_recipient_token: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: AccountInfo<'info>,
+    pub fee_token_program: AccountInfo<'info>,
+}
+
+impl CreationFee<'_, '_> {
+    pub fn pay(fee_arg: CreationFee) -> Result<()> {
+        let config = fee_arg.config;
+        if config.creation_fee == 0 {
+            return Ok(());
+        }
+
+        let Some(fee_mint) = config.creation_fee_mint else {
+            let accounts = Transfer {
+                from: fee_arg.payer,
+                to: fee_arg.fee_recipient,
+            };
+            let ctx = CpiContext::new(fee_arg.system_program, accounts);
+            return transfer(ctx, config.creation_fee);
+        };
+
+        let (Some(mint), Some(from), Some(to)) = (
+            fee_arg.fee_mint,
+            fee_arg.payer_fee_token,
+            fee_arg.fee_recipient_token,
+        ) else {
+            return err!(AMMError::InvalidCreationFee);
+        };
+        require!(
+            mint.key() == fee_mint && to.mint == fee_mint && to.owner == config.fee_recipient,
+            AMMError::InvalidCreationFee
+        );
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: fee_arg.payer,
+        };
+        let ctx = CpiContext::new(fee_arg.fee_token_program, accounts);
+        transfer_checked(ctx, config.creation_fee, mint.decimals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the previous f64 / I64F64 implementations, kept here to compare against
+    fn old_first_liquidity(x: u64, y: u64) -> u64 {
+        ((x * y) as f64).sqrt().round() as u64
+    }
+
+    fn old_lp_token(dx: u64, dy: u64, x: u64, y: u64, supply: u64) -> u64 {
+        ((supply as f64) * f64::min(dx as f64 / x as f64, dy as f64 / y as f64)) as u64
+    }
+
+    fn old_withdraw(vault: u64, share: u64, supply: u64) -> u64 {
+        ((vault as f64) * (share as f64 / supply as f64)) as u64
+    }
+
+    fn old_swap(total_in: u64, total_out: u64, amount_in: u64) -> u64 {
+        let numerator = I64F64::from_num(total_out) * I64F64::from_num(amount_in);
+        let denominator = I64F64::from_num(total_in) + I64F64::from_num(amount_in);
+        (numerator / denominator).round().to_num::<u64>()
+    }
+
+    const VALUES: [u64; 12] = [
+        1, 2, 3, 7, 10, 99, 100, 1_000, 1_001, 12_345, 65_536, 999_999,
+    ];
+
+    #[test]
+    fn integer_sqrt_is_floor_sqrt() {
+        for n in 0u128..10_000 {
+            let root = integer_sqrt(n);
+            assert!(root * root <= n && (root + 1) * (root + 1) > n, "n = {n}");
+        }
+        assert_eq!(
+            integer_sqrt(u64::MAX as u128 * u64::MAX as u128),
+            u64::MAX as u128
+        );
+        assert_eq!(integer_sqrt(u128::MAX), u64::MAX as u128);
+    }
+
+    #[test]
+    fn first_liquidity_matches_old_rounded_down() {
+        for x in VALUES {
+            for y in VALUES {
+                let old = old_first_liquidity(x, y);
+                if old <= MINIMUM_LIQUIDITY + 1 {
+                    continue;
+                }
+                let new =
+                    LiquidityPool::calculate_first_liquidity(x, y, CurveType::ConstantProduct)
+                        .unwrap()
+                        + MINIMUM_LIQUIDITY;
+                // old rounded to nearest, new always rounds down
+                assert!(old == new || old == new + 1, "x = {x}, y = {y}");
+            }
+        }
+        assert_eq!(
+            LiquidityPool::calculate_first_liquidity(10_000, 10_000, CurveType::ConstantProduct)
+                .unwrap(),
+            10_000 - MINIMUM_LIQUIDITY
+        );
+    }
+
+    #[test]
+    fn first_liquidity_locks_minimum_liquidity() {
+        // sqrt(1000 * 1000) leaves nothing for the depositor
+        assert!(
+            LiquidityPool::calculate_first_liquidity(1_000, 1_000, CurveType::ConstantProduct)
+                .is_err()
+        );
+        assert_eq!(
+            LiquidityPool::calculate_first_liquidity(1_001, 1_001, CurveType::ConstantProduct)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn donated_vaults_still_take_a_first_deposit() {
+        let lp_request = LiquidityPool {
+            total_amount_a: 1,
+            total_amount_b: 0,
+            deposit_amount_a: 10_000,
+            deposit_amount_b: 10_000,
+            mint_supply: 0,
+            curve: CurveType::ConstantProduct,
+        };
+        assert_eq!(
+            LiquidityPool::calculate_liquidity(lp_request).unwrap(),
+            10_000 - MINIMUM_LIQUIDITY
+        );
+    }
+
+    #[test]
+    fn first_liquidity_is_exact_above_f64_precision() {
+        // 2^53 + 1 squared can't be represented in f64
+        let x = (1u64 << 53) + 1;
+        assert_eq!(
+            LiquidityPool::calculate_first_liquidity(x, x, CurveType::ConstantProduct).unwrap(),
+            x - MINIMUM_LIQUIDITY
+        );
+        assert_eq!(
+            LiquidityPool::calculate_first_liquidity(
+                u64::MAX,
+                u64::MAX,
+                CurveType::ConstantProduct
+            )
+            .unwrap(),
+            u64::MAX - MINIMUM_LIQUIDITY
+        );
+    }
+
+    #[test]
+    fn lp_token_matches_old() {
+        for x in VALUES {
+            for y in VALUES {
+                for dx in VALUES {
+                    let dy = dx;
+                    let supply = integer_sqrt(x as u128 * y as u128) as u64;
+                    if supply == 0 {
+                        continue;
+                    }
+                    let new = LiquidityPool::calculate_lp_token(LiquidityPool {
+                        total_amount_a: x,
+                        total_amount_b: y,
+                        deposit_amount_a: dx,
+                        deposit_amount_b: dy,
+                        mint_supply: supply,
+                        curve: CurveType::ConstantProduct,
+                    })
+                    .unwrap();
+                    let old = old_lp_token(dx, dy, x, y, supply);
+                    // f64 division can land just under or over the exact value
+                    assert!(new.abs_diff(old) <= 1, "x = {x}, y = {y}, dx = {dx}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lp_token_rounds_down() {
+        // 1 * 10 / 3 = 3.33
+        let lp = LiquidityPool::calculate_lp_token(LiquidityPool {
+            total_amount_a: 3,
+            total_amount_b: 3,
+            deposit_amount_a: 1,
+            deposit_amount_b: 1,
+            mint_supply: 10,
+            curve: CurveType::ConstantProduct,
+        })
+        .unwrap();
+        assert_eq!(lp, 3);
+    }
+
+    #[test]
+    fn withdraw_matches_old() {
+        for vault in VALUES {
+            for supply in VALUES {
+                for share in VALUES.into_iter().filter(|share| *share <= supply) {
+                    let new = WithdrawAsset::calculate_token(WithdrawAsset {
+                        mint_supply: supply,
+                        lp_share_amount: share,
+                        total_amount_vault: vault,
+                    })
+                    .unwrap();
+                    let old = old_withdraw(vault, share, supply);
+                    assert!(new.abs_diff(old) <= 1, "vault = {vault}, share = {share}");
+                    assert!(new <= vault);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn withdraw_is_exact_above_f64_precision() {
+        let vault = u64::MAX - 1;
+        let token = WithdrawAsset::calculate_token(WithdrawAsset {
+            mint_supply: 3,
+            lp_share_amount: 1,
+            total_amount_vault: vault,
+        })
+        .unwrap();
+        assert_eq!(token, vault / 3);
+    }
+
+    #[test]
+    fn swap_matches_old_rounded_down() {
+        for total_in in VALUES {
+            for total_out in VALUES {
+                for amount_in in VALUES {
+                    for is_a in [true, false] {
+                        let (total_amount_a, total_amount_b) = if is_a {
+                            (total_in, total_out)
+                        } else {
+                            (total_out, total_in)
+                        };
+                        let new = SwapToken::swap_token(SwapToken {
+                            is_a,
+                            deposit_amount: amount_in,
+                            total_amount_a,
+                            total_amount_b,
+                            fee_bps: 0,
+                            curve: CurveType::ConstantProduct,
+                        })
+                        .unwrap();
+                        let old = old_swap(total_in, total_out, amount_in);
+                        // old rounded to nearest, new always rounds down
+                        assert!(
+                            old == new || old == new + 1,
+                            "in = {total_in}, out = {total_out}"
+                        );
+                        assert!(new < total_out || total_out == 0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn optimal_deposit_follows_vault_ratio() {
+        // first deposit takes everything
+        assert_eq!(
+            LiquidityPool::optimal_deposit(100, 300, 0, 0).unwrap(),
+            (100, 300)
+        );
+        // B is the limiting side
+        assert_eq!(
+            LiquidityPool::optimal_deposit(100, 100, 2_000, 1_000).unwrap(),
+            (100, 50)
+        );
+        // A is the limiting side
+        assert_eq!(
+            LiquidityPool::optimal_deposit(100, 10, 2_000, 1_000).unwrap(),
+            (20, 10)
+        );
+        // the owed side is rounded up: 101 * 1_000 / 2_000 = 50.5
+        assert_eq!(
+            LiquidityPool::optimal_deposit(101, 100, 2_000, 1_000).unwrap(),
+            (101, 51)
+        );
+    }
+
+    fn quote_exact_out(
+        is_a: bool,
+        amount_out: u64,
+        x: u64,
+        y: u64,
+        fee: u16,
+        protocol: u16,
+    ) -> u64 {
+        SwapExactOut::calculate_amount_in(SwapExactOut {
+            is_a,
+            amount_out,
+            total_amount_a: x,
+            total_amount_b: y,
+            fee_bps: fee,
+            protocol_fee_bps: protocol,
+            curve: CurveType::ConstantProduct,
+        })
+        .unwrap()
+    }
+
+    fn quote_exact_in(is_a: bool, amount_in: u64, x: u64, y: u64, fee: u16, protocol: u16) -> u64 {
+        let protocol_fee = SwapToken::protocol_fee(amount_in, protocol).unwrap();
+        SwapToken::swap_token(SwapToken {
+            is_a,
+            deposit_amount: amount_in - protocol_fee,
+            total_amount_a: x,
+            total_amount_b: y,
+            fee_bps: fee,
+            curve: CurveType::ConstantProduct,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn exact_out_input_always_covers_output() {
+        for x in VALUES.into_iter().filter(|x| *x > 1) {
+            for y in VALUES.into_iter().filter(|y| *y > 1) {
+                for (fee, protocol) in [(0, 0), (30, 0), (30, 20), (1_000, 0)] {
+                    for is_a in [true, false] {
+                        let total_out = if is_a { y } else { x };
+                        for amount_out in [1, total_out / 2, total_out - 1] {
+                            let amount_in = quote_exact_out(is_a, amount_out, x, y, fee, protocol);
+                            let received = quote_exact_in(is_a, amount_in, x, y, fee, protocol);
+                            assert!(
+                                received >= amount_out,
+                                "x = {x}, y = {y}, out = {amount_out}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exact_out_input_is_minimal_without_fees() {
+        for x in VALUES.into_iter().filter(|x| *x > 1) {
+            for y in VALUES.into_iter().filter(|y| *y > 1) {
+                for amount_out in [1, y / 2, y - 1] {
+                    let amount_in = quote_exact_out(true, amount_out, x, y, 0, 0);
+                    let received = quote_exact_in(true, amount_in - 1, x, y, 0, 0);
+                    assert!(
+                        received < amount_out,
+                        "x = {x}, y = {y}, out = {amount_out}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exact_out_rejects_draining_the_vault() {
+        assert!(SwapExactOut::calculate_amount_in(SwapExactOut {
+            is_a: true,
+            amount_out: 1_000,
+            total_amount_a: 1_000,
+            total_amount_b: 1_000,
+            fee_bps: 0,
+            protocol_fee_bps: 0,
+            curve: CurveType::ConstantProduct,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn twap_of_a_constant_price_is_the_price() {
+        let (price_a_cumulative, price_b_cumulative) =
+            PriceCumulative::accumulate(PriceCumulative {
+                price_a_cumulative: 0,
+                price_b_cumulative: 0,
+                reserve_a: 1_000,
+                reserve_b: 4_000,
+                time_elapsed: 60,
+            });
+
+        let twap_a = Twap::calculate_twap(Twap {
+            cumulative_start: 0,
+            cumulative_end: price_a_cumulative,
+            timestamp_start: 100,
+            timestamp_end: 160,
+        })
+        .unwrap();
+        let twap_b = Twap::calculate_twap(Twap {
+            cumulative_start: 0,
+            cumulative_end: price_b_cumulative,
+            timestamp_start: 100,
+            timestamp_end: 160,
+        })
+        .unwrap();
+
+        assert_eq!(twap_a, I64F64::from_num(4));
+        assert_eq!(twap_b, I64F64::from_num(0.25));
+    }
+
+    #[test]
+    fn twap_weights_prices_by_time() {
+        // price 2 for 30 seconds, then price 1 for 90 seconds
+        let (first, _) = PriceCumulative::accumulate(PriceCumulative {
+            price_a_cumulative: 0,
+            price_b_cumulative: 0,
+            reserve_a: 500,
+            reserve_b: 1_000,
+            time_elapsed: 30,
+        });
+        let (second, _) = PriceCumulative::accumulate(PriceCumulative {
+            price_a_cumulative: first,
+            price_b_cumulative: 0,
+            reserve_a: 1_000,
+            reserve_b: 1_000,
+            time_elapsed: 90,
+        });
+
+        let twap = Twap::calculate_twap(Twap {
+            cumulative_start: 0,
+            cumulative_end: second,
+            timestamp_start: 0,
+            timestamp_end: 120,
+        })
+        .unwrap();
+        assert_eq!(twap, I64F64::from_num(1.25));
+    }
+
+    #[test]
+    fn twap_survives_cumulative_wrap_around() {
+        let start = u128::MAX - I64F64::from_num(10).to_bits() as u128 + 1;
+        let (end, _) = PriceCumulative::accumulate(PriceCumulative {
+            price_a_cumulative: start,
+            price_b_cumulative: 0,
+            reserve_a: 1_000,
+            reserve_b: 3_000,
+            time_elapsed: 10,
+        });
+        assert!(end < start);
+
+        let twap = Twap::calculate_twap(Twap {
+            cumulative_start: start,
+            cumulative_end: end,
+            timestamp_start: 0,
+            timestamp_end: 10,
+        })
+        .unwrap();
+        assert_eq!(twap, I64F64::from_num(3));
+    }
+
+    #[test]
+    fn twap_rejects_unordered_observations() {
+        assert!(Twap::calculate_twap(Twap {
+            cumulative_start: 0,
+            cumulative_end: 0,
+            timestamp_start: 10,
+            timestamp_end: 10,
+        })
+        .is_err());
+    }
+
+    // pool of 1_000 A (6 decimals) and 2_000 B (6 decimals), so A = 2 B
+    fn oracle_guard(oracle_price: i64, oracle_conf: u64, oracle_expo: i32) -> OracleGuard {
+        OracleGuard {
+            reserve_a: 1_000_000_000,
+            reserve_b: 2_000_000_000,
+            decimals_a: 6,
+            decimals_b: 6,
+            oracle_price,
+            oracle_conf,
+            oracle_expo,
+            max_deviation_bps: 100,
+            max_confidence_bps: 50,
+        }
+    }
+
+    #[test]
+    fn oracle_price_close_to_pool_price_passes() {
+        // 2.00 and 2.015 with expo -8
+        assert!(OracleGuard::check_price(oracle_guard(200_000_000, 10_000, -8)).is_ok());
+        assert!(OracleGuard::check_price(oracle_guard(201_500_000, 10_000, -8)).is_ok());
+    }
+
+    #[test]
+    fn oracle_price_far_from_pool_price_fails() {
+        // 2.05 is 2.4% away from the pool price of 2
+        let result = OracleGuard::check_price(oracle_guard(205_000_000, 10_000, -8));
+        assert_eq!(result, Err(AMMError::OraclePriceDeviation.into()));
+    }
+
+    #[test]
+    fn oracle_wide_confidence_fails() {
+        // conf of 0.02 on 2.00 is 1%
+        let result = OracleGuard::check_price(oracle_guard(200_000_000, 2_000_000, -8));
+        assert_eq!(result, Err(AMMError::OracleConfidenceTooWide.into()));
+    }
+
+    #[test]
+    fn oracle_negative_price_fails() {
+        let result = OracleGuard::check_price(oracle_guard(-200_000_000, 0, -8));
+        assert_eq!(result, Err(AMMError::OracleInvalid.into()));
+    }
+
+    #[test]
+    fn oracle_check_accounts_for_decimals() {
+        // same pool value, but B has 9 decimals: 1_000 A and 2_000 B
+        let mut guard = oracle_guard(200_000_000, 10_000, -8);
+        guard.decimals_b = 9;
+        guard.reserve_b = 2_000_000_000_000;
+        assert!(OracleGuard::check_price(guard).is_ok());
+    }
+
+    #[test]
+    fn zero_amounts_are_rejected() {
+        assert!(
+            LiquidityPool::calculate_first_liquidity(0, 10, CurveType::ConstantProduct).is_err()
+        );
+        assert!(WithdrawAsset::calculate_token(WithdrawAsset {
+            mint_supply: 0,
+            lp_share_amount: 1,
+            total_amount_vault: 10,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn stable_first_liquidity_is_d() {
+        let curve = CurveType::StableSwap { amp: 100 };
+        assert_eq!(
+            LiquidityPool::calculate_first_liquidity(10_000, 10_000, curve).unwrap(),
+            20_000 - MINIMUM_LIQUIDITY
+        );
+    }
+
+    #[test]
+    fn stable_exact_out_round_trips_through_swap_token() {
+        let curve = CurveType::StableSwap { amp: 100 };
+        let (x, y) = (1_000_000_000, 800_000_000);
+        for amount_out in [1, 10_000, 5_000_000, 700_000_000] {
+            let amount_in = SwapExactOut::calculate_amount_in(SwapExactOut {
+                is_a: true,
+                amount_out,
+                total_amount_a: x,
+                total_amount_b: y,
+                fee_bps: 30,
+                protocol_fee_bps: 20,
+                curve,
+            })
+            .unwrap();
+            let protocol_fee = SwapToken::protocol_fee(amount_in, 20).unwrap();
+            let received = SwapToken::swap_token(SwapToken {
+                is_a: true,
+                deposit_amount: amount_in - protocol_fee,
+                total_amount_a: x,
+                total_amount_b: y,
+                fee_bps: 30,
+                curve,
+            })
+            .unwrap();
+            assert!(received >= amount_out, "amount_out = {amount_out}");
+        }
     }
 }
@@ -0,0 +1,341 @@
+use crate::error::AMMError;
+use anchor_lang::prelude::*;
+use u256::U256;
+
+// StableSwap invariant for two coins (n = 2), same formulation as Curve v1:
+// A * n^n * (x + y) + D = A * D * n^n + D^(n+1) / (n^n * x * y)
+// Ann below is A * n. The Newton steps run in U256 like Curve's: D^3 / (n^n * x * y) and
+// Ann * S * D pass u128 at about 1e17 a side, while every intermediate of u64 reserves fits here
+
+// own module, the macro output can't see the anchor prelude Result
+mod u256 {
+    #![allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+}
+
+const N_COINS: u64 = 2;
+const MAX_ITERATIONS: u16 = 255;
+
+// D such that the invariant holds for the given balances, D == x + y when x == y
+pub fn compute_d(amp: u64, amount_a: u64, amount_b: u64) -> Result<u128> {
+    let (x, y) = (U256::from(amount_a), U256::from(amount_b));
+    let sum = x + y;
+    if sum.is_zero() {
+        return Ok(0);
+    }
+    require!(!x.is_zero() && !y.is_zero(), AMMError::AmountZero);
+    require!(amp != 0, AMMError::InvalidAmp);
+
+    let ann = U256::from(amp) * N_COINS;
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        // d_p = D^3 / (n^n * x * y)
+        let d_p = d
+            .checked_mul(d)
+            .ok_or(AMMError::Overflow)?
+            .checked_div(x * N_COINS)
+            .ok_or(AMMError::Overflow)?
+            .checked_mul(d)
+            .ok_or(AMMError::Overflow)?
+            .checked_div(y * N_COINS)
+            .ok_or(AMMError::Overflow)?;
+        let d_prev = d;
+
+        // D = (Ann * S + n * d_p) * D / ((Ann - 1) * D + (n + 1) * d_p)
+        let numerator = ann
+            .checked_mul(sum)
+            .and_then(|v| v.checked_add(d_p.checked_mul(N_COINS.into())?))
+            .and_then(|v| v.checked_mul(d))
+            .ok_or(AMMError::Overflow)?;
+        let denominator = (ann - 1)
+            .checked_mul(d)
+            .and_then(|v| v.checked_add(d_p.checked_mul((N_COINS + 1).into())?))
+            .ok_or(AMMError::Overflow)?;
+        d = numerator / denominator;
+
+        if d.max(d_prev) - d.min(d_prev) <= U256::one() {
+            return u128::try_from(d).map_err(|_| AMMError::Overflow.into());
+        }
+    }
+
+    err!(AMMError::CurveNotConverged)
+}
+
+// balance of the other coin that keeps D unchanged once this coin's balance is new_amount
+pub fn compute_y(amp: u64, new_amount: u128, d: u128) -> Result<u128> {
+    require!(new_amount != 0, AMMError::AmountZero);
+    require!(amp != 0, AMMError::InvalidAmp);
+
+    let (x, d) = (U256::from(new_amount), U256::from(d));
+    let ann = U256::from(amp) * N_COINS;
+
+    // c = D^3 / (n^n * x * Ann), b = x + D / Ann
+    let c = d
+        .checked_mul(d)
+        .ok_or(AMMError::Overflow)?
+        .checked_div(x * N_COINS)
+        .ok_or(AMMError::Overflow)?
+        .checked_mul(d)
+        .ok_or(AMMError::Overflow)?
+        .checked_div(ann * N_COINS)
+        .ok_or(AMMError::Overflow)?;
+    let b = x.checked_add(d / ann).ok_or(AMMError::Overflow)?;
+
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+
+        // y = (y^2 + c) / (2y + b - D)
+        let numerator = y
+            .checked_mul(y)
+            .and_then(|v| v.checked_add(c))
+            .ok_or(AMMError::Overflow)?;
+        let denominator = y
+            .checked_mul(2.into())
+            .and_then(|v| v.checked_add(b))
+            .and_then(|v| v.checked_sub(d))
+            .filter(|v| !v.is_zero())
+            .ok_or(AMMError::Overflow)?;
+        y = numerator / denominator;
+
+        if y.max(y_prev) - y.min(y_prev) <= U256::one() {
+            return u128::try_from(y).map_err(|_| AMMError::Overflow.into());
+        }
+    }
+
+    err!(AMMError::CurveNotConverged)
+}
+
+// reserves whose ratio is the marginal price dy/dx of the invariant, for the TWAP and the oracle
+// check. dy/dx = y * (Ann * x + d_p) / (x * (Ann * y + d_p)), each side is scaled by the larger
+// factor so a balanced pool gets its reserves back unchanged
+pub fn price_reserves(amp: u64, amount_a: u64, amount_b: u64) -> Result<(u64, u64)> {
+    if amount_a == 0 || amount_b == 0 {
+        return Ok((amount_a, amount_b));
+    }
+
+    let d = U256::from(compute_d(amp, amount_a, amount_b)?);
+    let (x, y) = (U256::from(amount_a), U256::from(amount_b));
+    let ann = U256::from(amp) * N_COINS;
+
+    // d_p = D^3 / (n^n * x * y), below (x + y)^3 / (4 * x * y) so it fits with room to spare
+    let d_p = d * d / (x * N_COINS) * d / (y * N_COINS);
+    let (factor_a, factor_b) = (ann * x + d_p, ann * y + d_p);
+    let max_factor = factor_a.max(factor_b);
+
+    // both at most the reserve they scale, so they fit back in a u64
+    Ok((
+        (x * factor_b / max_factor).low_u64(),
+        (y * factor_a / max_factor).low_u64(),
+    ))
+}
+
+// amount out for amount_in already net of fees, rounded down by one unit so the pool keeps the dust
+pub fn swap_out(amp: u64, total_in: u64, total_out: u64, amount_in: u64) -> Result<u64> {
+    let d = compute_d(amp, total_in, total_out)?;
+    let new_in = (total_in as u128)
+        .checked_add(amount_in as u128)
+        .ok_or(AMMError::Overflow)?;
+    let new_out = compute_y(amp, new_in, d)?;
+
+    let amount_out = (total_out as u128)
+        .saturating_sub(new_out)
+        .saturating_sub(1);
+
+    Ok(amount_out as u64)
+}
+
+// net input needed to take amount_out from the pool, rounded up by one unit
+pub fn amount_in(amp: u64, total_in: u64, total_out: u64, amount_out: u64) -> Result<u64> {
+    require!(amount_out < total_out, AMMError::InsufficientLiquidity);
+
+    let d = compute_d(amp, total_in, total_out)?;
+    let new_in = compute_y(amp, (total_out - amount_out) as u128, d)?;
+
+    let amount_in = new_in
+        .checked_sub(total_in as u128)
+        .and_then(|v| v.checked_add(1))
+        .ok_or(AMMError::Overflow)?;
+
+    u64::try_from(amount_in).map_err(|_| AMMError::Overflow.into())
+}
+
+pub struct AmpRamp {
+    pub initial_amp: u64,
+    pub target_amp: u64,
+    pub start_timestamp: i64,
+    pub stop_timestamp: i64,
+    pub now: i64,
+}
+
+impl AmpRamp {
+    // A = A0 + (A1 - A0) * (now - t0) / (t1 - t0), clamped to the ramp window
+    pub fn current_amp(ramp: AmpRamp) -> u64 {
+        if ramp.now >= ramp.stop_timestamp || ramp.stop_timestamp <= ramp.start_timestamp {
+            return ramp.target_amp;
+        }
+        if ramp.now <= ramp.start_timestamp {
+            return ramp.initial_amp;
+        }
+
+        let elapsed = (ramp.now - ramp.start_timestamp) as u128;
+        let duration = (ramp.stop_timestamp - ramp.start_timestamp) as u128;
+        let change = ramp.initial_amp.abs_diff(ramp.target_amp) as u128 * elapsed / duration;
+
+        // change is at most |A1 - A0|, so neither branch leaves the [A0, A1] range
+        match ramp.target_amp > ramp.initial_amp {
+            true => ramp.initial_amp + change as u64,
+            false => ramp.initial_amp - change as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::MAX_AMP;
+
+    #[test]
+    fn d_of_balanced_pool_is_the_sum() {
+        let d = compute_d(100, 1_000_000, 1_000_000).unwrap();
+        assert!(d.abs_diff(2_000_000) <= 1);
+    }
+
+    #[test]
+    fn d_is_between_product_and_sum_invariants() {
+        let (x, y) = (1_000_000u64, 3_000_000u64);
+        let d = compute_d(100, x, y).unwrap();
+        let geometric = 2 * crate::helper::integer_sqrt(x as u128 * y as u128);
+
+        assert!(d > geometric);
+        assert!(d < (x + y) as u128);
+    }
+
+    #[test]
+    fn compute_y_keeps_d() {
+        let d = compute_d(100, 1_000_000, 1_000_000).unwrap();
+        let y = compute_y(100, 1_000_000, d).unwrap();
+        assert!(y.abs_diff(1_000_000) <= 1);
+    }
+
+    #[test]
+    fn stable_swap_has_low_slippage_near_peg() {
+        let out = swap_out(100, 1_000_000_000, 1_000_000_000, 1_000_000).unwrap();
+
+        // constant product would give 999_000, the amplified curve stays within 0.01% of 1:1
+        assert!(out < 1_000_000);
+        assert!(out > 999_900);
+    }
+
+    #[test]
+    fn stable_swap_never_beats_one_to_one_on_a_balanced_pool() {
+        for amp in [1, 10, 100, 1_000, 10_000] {
+            let out = swap_out(amp, 1_000_000, 1_000_000, 10_000).unwrap();
+            assert!(out < 10_000);
+        }
+    }
+
+    #[test]
+    fn amount_in_covers_swap_out() {
+        let (x, y, amp) = (5_000_000u64, 2_000_000u64, 200);
+        for amount_out in [1, 1_000, 500_000, 1_900_000] {
+            let needed = amount_in(amp, x, y, amount_out).unwrap();
+            assert!(swap_out(amp, x, y, needed).unwrap() >= amount_out);
+        }
+    }
+
+    #[test]
+    fn amount_in_rejects_draining_the_pool() {
+        assert!(amount_in(100, 1_000, 1_000, 1_000).is_err());
+    }
+
+    #[test]
+    fn full_u64_reserves_at_max_amp_do_not_overflow() {
+        let (x, y) = (u64::MAX, u64::MAX - 1_000_000_000_000);
+
+        let d = compute_d(MAX_AMP, x, y).unwrap();
+        assert!(d < x as u128 + y as u128);
+        assert!(d > x as u128 + y as u128 - 1_000_000);
+
+        // this close to the peg the quote is 1:1 either way, give or take the unit the pool keeps
+        for (total_in, total_out) in [(x, y), (y, x)] {
+            let out = swap_out(MAX_AMP, total_in, total_out, 1_000_000_000).unwrap();
+            assert!(out.abs_diff(1_000_000_000) <= 1);
+
+            let needed = amount_in(MAX_AMP, total_in, total_out, out).unwrap();
+            assert!(swap_out(MAX_AMP, total_in, total_out, needed).unwrap() >= out);
+        }
+    }
+
+    #[test]
+    fn imbalanced_pool_is_priced_by_the_invariant() {
+        // 70/30 at amp 100, the amplified curve still prices A close to 1 B
+        let (x, y, amp) = (7_000_000_000u64, 3_000_000_000u64, 100);
+        let (price_a, price_b) = price_reserves(amp, x, y).unwrap();
+
+        // the marginal price matches a small trade, where the raw ratio would say 3/7
+        let quote = swap_out(amp, x, y, 1_000_000).unwrap();
+        let price = price_b as f64 / price_a as f64;
+        assert!((price - quote as f64 / 1_000_000.0).abs() < 0.001);
+        assert!(price > 0.95 && price < 1.0);
+
+        assert_eq!(price_reserves(amp, y, y).unwrap(), (y, y));
+        assert_eq!(price_reserves(amp, 0, y).unwrap(), (0, y));
+    }
+
+    fn amp_at(now: i64) -> u64 {
+        AmpRamp::current_amp(AmpRamp {
+            initial_amp: 100,
+            target_amp: 1_000,
+            start_timestamp: 1_000,
+            stop_timestamp: 1_000 + 86_400,
+            now,
+        })
+    }
+
+    #[test]
+    fn amp_ramp_is_clamped_to_the_window() {
+        assert_eq!(amp_at(0), 100);
+        assert_eq!(amp_at(1_000), 100);
+        assert_eq!(amp_at(1_000 + 43_200), 550);
+        assert_eq!(amp_at(1_000 + 86_400), 1_000);
+        assert_eq!(amp_at(i64::MAX), 1_000);
+    }
+
+    #[test]
+    fn amp_ramp_down_mirrors_ramp_up() {
+        let amp = AmpRamp::current_amp(AmpRamp {
+            initial_amp: 1_000,
+            target_amp: 100,
+            start_timestamp: 0,
+            stop_timestamp: 86_400,
+            now: 43_200,
+        });
+        assert_eq!(amp, 550);
+    }
+
+    #[test]
+    fn mid_ramp_quotes_are_continuous() {
+        let (x, y) = (1_000_000_000u64, 900_000_000u64);
+        let mut previous_amp = amp_at(999);
+        let mut previous_out = swap_out(previous_amp, x, y, 1_000_000).unwrap();
+
+        for now in (1_000..=1_000 + 86_400 + 1).step_by(60) {
+            let amp = amp_at(now);
+            let out = swap_out(amp, x, y, 1_000_000).unwrap();
+
+            // 60 seconds of a 900 per day ramp moves A by at most 1, the quote moves less than 0.01%
+            assert!(
+                amp >= previous_amp && amp - previous_amp <= 1,
+                "now = {now}"
+            );
+            assert!(out.abs_diff(previous_out) <= 100, "now = {now}");
+
+            previous_amp = amp;
+            previous_out = out;
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::error::AMMError;
+use anchor_lang::prelude::*;
+use fixed::types::{I64F64, U64F64};
+
+// Weighted product invariant (Balancer): V = B_a^w_a * B_b^w_b with w_a + w_b = 1
+// pow is exp(e * ln(x)) on fixed point numbers, every result that ends up in a transfer is
+// pushed by MAX_POW_RELATIVE_ERROR in the direction that favours the pool
+
+const LN_2: I64F64 = I64F64::from_bits(0xB172_17F7_D1CF_79AB);
+const MAX_POW_RELATIVE_ERROR_SHIFT: u32 = 40; // 2^-40, far above the error of ln and exp
+const ONE: u128 = 1 << 64; // 1.0 in U64F64 bits
+
+// natural logarithm of x > 0
+pub fn ln(x: U64F64) -> Result<I64F64> {
+    let bits = x.to_bits();
+    require!(bits != 0, AMMError::AmountZero);
+
+    // x = m * 2^k with m in [1, 2)
+    let k = 63 - bits.leading_zeros() as i32;
+    let m_bits = match k >= 0 {
+        true => bits >> k,
+        false => bits << -k,
+    };
+    let m = I64F64::from_bits(m_bits as i128);
+    let one = I64F64::from_num(1);
+
+    // ln(m) = 2 * atanh(z) = 2 * (z + z^3/3 + z^5/5 + ...), z = (m - 1)/(m + 1) < 1/3
+    let z = (m - one) / (m + one);
+    let z2 = z * z;
+    let mut term = z;
+    let mut sum = z;
+    let mut n = 1;
+    loop {
+        term *= z2;
+        if term.to_bits() == 0 {
+            break;
+        }
+        sum += term / I64F64::from_num(2 * n + 1);
+        n += 1;
+    }
+
+    Ok(sum * I64F64::from_num(2) + LN_2 * I64F64::from_num(k))
+}
+
+// e^x, errors when the result doesn't fit in a U64F64
+pub fn exp(x: I64F64) -> Result<U64F64> {
+    if x.to_bits() < 0 {
+        // anything below 2^-64 rounds to zero
+        return Ok(match exp(-x) {
+            Ok(inverse) => U64F64::from_num(1) / inverse,
+            Err(_) => U64F64::from_bits(0),
+        });
+    }
+
+    // x = k * ln(2) + r with r in [0, ln(2)), e^x = 2^k * e^r
+    let k = x
+        .checked_div(LN_2)
+        .ok_or(AMMError::Overflow)?
+        .to_num::<i64>();
+    require!(k < 64, AMMError::Overflow);
+    let r = x - LN_2 * I64F64::from_num(k);
+
+    // Taylor series, e^r is in [1, 2)
+    let mut term = I64F64::from_num(1);
+    let mut sum = term;
+    let mut n = 1;
+    loop {
+        term = term * r / I64F64::from_num(n);
+        if term.to_bits() == 0 {
+            break;
+        }
+        sum += term;
+        n += 1;
+    }
+
+    Ok(U64F64::from_bits((sum.to_bits() as u128) << k))
+}
+
+// base^exponent, rounded up by the relative error bound
+pub fn pow_up(base: U64F64, exponent: I64F64) -> Result<U64F64> {
+    if base.to_bits() == 0 {
+        return Ok(base);
+    }
+
+    let power = exp(ln(base)?.checked_mul(exponent).ok_or(AMMError::Overflow)?)?.to_bits();
+    let power = power
+        .checked_add(power >> MAX_POW_RELATIVE_ERROR_SHIFT)
+        .and_then(|power| power.checked_add(1))
+        .ok_or(AMMError::Overflow)?;
+
+    Ok(U64F64::from_bits(power))
+}
+
+fn weight_ratio(numerator: u16, denominator: u16) -> I64F64 {
+    I64F64::from_num(numerator) / I64F64::from_num(denominator)
+}
+
+// out = B_out * (1 - (B_in / (B_in + A_in))^(w_in / w_out)), rounded down
+pub fn swap_out(
+    total_in: u64,
+    total_out: u64,
+    weight_in: u16,
+    weight_out: u16,
+    amount_in: u64,
+) -> Result<u64> {
+    require!(total_in != 0 && total_out != 0, AMMError::AmountZero);
+
+    // base is in (0, 1], rounded up so the power doesn't come out too small
+    let base = ((total_in as u128) << 64).div_ceil(total_in as u128 + amount_in as u128);
+    let power = pow_up(U64F64::from_bits(base), weight_ratio(weight_in, weight_out))?;
+
+    let complement = ONE.saturating_sub(power.to_bits());
+    Ok(((total_out as u128 * complement) >> 64) as u64)
+}
+
+// in = B_in * ((B_out / (B_out - A_out))^(w_out / w_in) - 1), rounded up
+pub fn amount_in(
+    total_in: u64,
+    total_out: u64,
+    weight_in: u16,
+    weight_out: u16,
+    amount_out: u64,
+) -> Result<u64> {
+    require!(total_in != 0, AMMError::AmountZero);
+    require!(amount_out < total_out, AMMError::InsufficientLiquidity);
+
+    // base is >= 1, rounded up like the power
+    let base = ((total_out as u128) << 64).div_ceil((total_out - amount_out) as u128);
+    let power = pow_up(U64F64::from_bits(base), weight_ratio(weight_out, weight_in))?;
+
+    // B_in * excess, split in integer and fractional part so the product stays in a u128
+    let excess = power.to_bits().saturating_sub(ONE);
+    let integer_part = (total_in as u128)
+        .checked_mul(excess >> 64)
+        .ok_or(AMMError::Overflow)?;
+    let fraction_part = (total_in as u128 * (excess & (ONE - 1))).div_ceil(ONE);
+
+    let amount_in = integer_part
+        .checked_add(fraction_part)
+        .ok_or(AMMError::Overflow)?;
+    u64::try_from(amount_in).map_err(|_| AMMError::Overflow.into())
+}
+
+// V = a^w_a * b^w_b with normalized weights, rounded down; sqrt(ab) for a 50/50 pool
+pub fn invariant(amount_a: u64, amount_b: u64, weight_a: u16, weight_b: u16) -> Result<u64> {
+    require!(amount_a != 0 && amount_b != 0, AMMError::AmountZero);
+
+    let total_weight = weight_a as u32 + weight_b as u32;
+    let log = ln(U64F64::from_num(amount_a))? * I64F64::from_num(weight_a)
+        / I64F64::from_num(total_weight)
+        + ln(U64F64::from_num(amount_b))? * I64F64::from_num(weight_b)
+            / I64F64::from_num(total_weight);
+
+    let value = exp(log)?.to_bits();
+    let value = value.saturating_sub((value >> MAX_POW_RELATIVE_ERROR_SHIFT) + 1);
+
+    Ok((value >> 64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() <= expected.abs() * 1e-12 + 1e-15
+    }
+
+    #[test]
+    fn ln_matches_f64() {
+        for x in [0.001f64, 0.5, 1.0, 1.5, 2.0, 10.0, 12345.678, 1e18] {
+            let actual = ln(U64F64::from_num(x)).unwrap().to_num::<f64>();
+            assert!(close(actual, x.ln()), "x = {x}, ln = {actual}");
+        }
+    }
+
+    #[test]
+    fn exp_matches_f64() {
+        for x in [-30.0f64, -1.0, -0.25, 0.0, 0.5, 1.0, 10.0, 40.0] {
+            let actual = exp(I64F64::from_num(x)).unwrap().to_num::<f64>();
+            assert!(
+                (actual - x.exp()).abs() <= x.exp() * 1e-12 + 1e-18,
+                "x = {x}, exp = {actual}"
+            );
+        }
+    }
+
+    #[test]
+    fn exp_overflow_is_an_error_and_underflow_is_zero() {
+        assert!(exp(I64F64::from_num(45)).is_err());
+        assert_eq!(exp(I64F64::from_num(-45)).unwrap().to_bits(), 0);
+    }
+
+    #[test]
+    fn pow_up_is_never_below_the_exact_power() {
+        for (base, exponent) in [(0.5, 4.0), (0.999, 0.25), (3.0, 2.0), (1.0, 99.0)] {
+            let actual = pow_up(U64F64::from_num(base), I64F64::from_num(exponent))
+                .unwrap()
+                .to_num::<f64>();
+            let expected = f64::powf(base, exponent);
+            assert!(actual >= expected * (1.0 - 1e-15));
+            assert!(actual <= expected * (1.0 + 1e-11) + 1e-18);
+        }
+    }
+
+    #[test]
+    fn equal_weights_match_the_constant_product() {
+        let (x, y) = (1_000_000_000u64, 2_000_000_000u64);
+        for amount_in in [1_000, 1_000_000, 500_000_000] {
+            let weighted = swap_out(x, y, 5_000, 5_000, amount_in).unwrap();
+            let constant_product = (y as u128 * amount_in as u128 / (x + amount_in) as u128) as u64;
+
+            assert!(weighted <= constant_product);
+            assert!(constant_product - weighted <= 1 + constant_product / 1_000_000_000);
+        }
+    }
+
+    #[test]
+    fn eighty_twenty_spot_price_follows_the_weights() {
+        // 80/20 pool holding 4:1 in value terms at a 1:1 price
+        let out = swap_out(4_000_000_000, 1_000_000_000, 8_000, 2_000, 1_000).unwrap();
+        assert!((998..=1_000).contains(&out));
+    }
+
+    #[test]
+    fn amount_in_is_never_below_the_exact_input() {
+        let (x, y) = (4_000_000_000u64, 1_000_000_000u64);
+        for (weight_in, weight_out) in [(8_000, 2_000), (2_000, 8_000), (5_000, 5_000)] {
+            for amount_out in [1, 1_000, 1_000_000, 900_000_000] {
+                let needed = amount_in(x, y, weight_in, weight_out, amount_out).unwrap();
+                let exact = x as f64
+                    * (f64::powf(
+                        y as f64 / (y - amount_out) as f64,
+                        weight_out as f64 / weight_in as f64,
+                    ) - 1.0);
+                assert!(needed as f64 >= exact, "out = {amount_out}");
+
+                // both directions round for the pool, so the round trip can lose one unit
+                let received = swap_out(x, y, weight_in, weight_out, needed).unwrap();
+                assert!(received + 1 >= amount_out, "out = {amount_out}");
+            }
+        }
+    }
+
+    #[test]
+    fn invariant_matches_f64() {
+        assert!(
+            invariant(10_000, 10_000, 5_000, 5_000)
+                .unwrap()
+                .abs_diff(10_000)
+                <= 1
+        );
+
+        let expected = f64::powf(4e9, 0.8) * f64::powf(1e9, 0.2);
+        let actual = invariant(4_000_000_000, 1_000_000_000, 8_000, 2_000).unwrap();
+        assert!((actual as f64 - expected).abs() <= 2.0);
+        assert!(actual as f64 <= expected);
+    }
+}
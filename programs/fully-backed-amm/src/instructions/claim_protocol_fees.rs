@@ -0,0 +1,149 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::{
+    constant::{GLOBAL_CONFIG, POOL},
+    error::AMMError,
+    events::ProtocolFeesClaimed,
+    state::{GlobalConfig, PoolAction, PoolConfig},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimProtocolFees<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        has_one = mint_a.key(),
+        has_one = mint_b.key(),
+        constraint = pool_config_account.owner == Some(owner.key()) @ AMMError::Unauthorized,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // Treasury Accounts, token accounts of the global fee recipient
+    #[account(
+        mut,
+        token::mint = mint_a,
+        token::token_program = token_program,
+        constraint = treasury_token_a.owner == global_config.fee_recipient @ AMMError::InvalidTreasury,
+    )]
+    pub treasury_token_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        token::mint = mint_b,
+        token::token_program = token_program,
+        constraint = treasury_token_b.owner == global_config.fee_recipient @ AMMError::InvalidTreasury,
+    )]
+    pub treasury_token_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+impl<'info> ClaimProtocolFees<'info> {
+    pub fn claim(&mut self) -> Result<ProtocolFeesClaimed> {
+        // the owed fees are not LP money, they can leave in every status
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Withdraw)?;
+
+        let amount_a = self.pool_config_account.protocol_fees_a;
+        let amount_b = self.pool_config_account.protocol_fees_b;
+
+        if amount_a > 0 {
+            self.transfer_token(true, amount_a)?;
+        }
+        if amount_b > 0 {
+            self.transfer_token(false, amount_b)?;
+        }
+
+        self.pool_config_account.protocol_fees_a = 0;
+        self.pool_config_account.protocol_fees_b = 0;
+
+        Ok(ProtocolFeesClaimed {
+            pool: self.pool_config_account.key(),
+            owner: self.owner.key(),
+
+            amount_a,
+            amount_b,
+        })
+    }
+
+    fn transfer_token(&mut self, is_a: bool, amount: u64) -> Result<()> {
+        let mint: Box<InterfaceAccount<'info, Mint>>;
+
+        let (from, to) = match is_a {
+            true => {
+                mint = self.mint_a.clone();
+                (
+                    self.vault_a.to_account_info(),
+                    self.treasury_token_a.to_account_info(),
+                )
+            }
+            false => {
+                mint = self.mint_b.clone();
+                (
+                    self.vault_b.to_account_info(),
+                    self.treasury_token_b.to_account_info(),
+                )
+            }
+        };
+
+        let accounts = TransferChecked {
+            from,
+            mint: mint.to_account_info(),
+            to,
+            authority: self.pool_config_account.to_account_info(),
+        };
+
+        let secret_seed = self.pool_config_account.seed.to_le_bytes();
+        let seeds = &[
+            POOL,
+            secret_seed.as_ref(),
+            &[self.pool_config_account.pool_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            accounts,
+            signer_seeds,
+        );
+
+        transfer_checked(ctx, amount, mint.decimals)?;
+
+        Ok(())
+    }
+}
+
+// The claim flow
+// - only the pool owner can claim, but the fees always go to the protocol: the treasury token
+//   accounts have to belong to the global config fee_recipient
+// - transfer the owed protocol fees from the vaults to the treasury token accounts
+// - reset the owed amounts
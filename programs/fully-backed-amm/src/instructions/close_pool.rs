@@ -0,0 +1,215 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{
+        close_account, transfer_checked, CloseAccount, Mint, TokenAccount, TokenInterface,
+        TransferChecked,
+    },
+};
+
+use crate::{
+    constant::{GLOBAL_CONFIG, LOCKED_LP, MINT_LP, OBSERVATIONS, POOL, POOL_PAIR},
+    error::AMMError,
+    events::PoolClosed,
+    state::{GlobalConfig, Observations, PoolAction, PoolConfig, PoolPair},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClosePool<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        has_one = mint_a.key(),
+        has_one = mint_b.key(),
+        constraint = pool_config_account.owner == Some(owner.key()) @ AMMError::Unauthorized,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    // closing the registry entry lets the pair, fee tier and curve get a new pool. Pools migrated
+    // from 0.1.0 are not registered and pass None
+    #[account(
+        mut,
+        close = owner,
+        seeds = [
+            POOL_PAIR,
+            mint_a.key().min(mint_b.key()).as_ref(),
+            mint_a.key().max(mint_b.key()).as_ref(),
+            pool_config_account.fee_bps.to_le_bytes().as_ref(),
+            &[pool_config_account.curve.kind()],
+        ],
+        bump = pool_pair.bump,
+        constraint = pool_pair.pool == pool_config_account.key(),
+    )]
+    pub pool_pair: Option<Box<Account<'info, PoolPair>>>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump = observations.bump,
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
+    #[account(
+        seeds = [MINT_LP, pool_config_account.key().as_ref()],
+        bump = pool_config_account.pool_mint_bump,
+    )]
+    pub mint_lp: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: PDA the program never signs for, only used to find the locked lp tokens
+    #[account(
+        seeds = [LOCKED_LP, pool_config_account.key().as_ref()],
+        bump
+    )]
+    pub locked_lp_authority: UncheckedAccount<'info>,
+
+    // created by the first deposit, None only for a pool that never locked any liquidity
+    #[account(
+        associated_token::mint = mint_lp,
+        associated_token::authority = locked_lp_authority,
+    )]
+    pub locked_lp_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // Treasury Accounts, token accounts of the global fee recipient, receive what is left behind
+    // the locked liquidity
+    #[account(
+        mut,
+        token::mint = mint_a,
+        token::token_program = token_program,
+        constraint = treasury_token_a.owner == global_config.fee_recipient @ AMMError::InvalidTreasury,
+    )]
+    pub treasury_token_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        token::mint = mint_b,
+        token::token_program = token_program,
+        constraint = treasury_token_b.owner == global_config.fee_recipient @ AMMError::InvalidTreasury,
+    )]
+    pub treasury_token_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+impl<'info> ClosePool<'info> {
+    pub fn close_pool(&mut self) -> Result<PoolClosed> {
+        let pool = &self.pool_config_account;
+        pool.check_status(&self.global_config, PoolAction::Close)?;
+        pool.check_no_flash_loan()?;
+
+        require!(
+            self.pool_pair.is_some() || !pool.registered,
+            AMMError::PoolPairMissing
+        );
+
+        // every LP is out, the only lp tokens left are the ones locked on the first deposit
+        let locked = match &self.locked_lp_token {
+            Some(locked_lp_token) => locked_lp_token.amount,
+            None => {
+                require!(pool.locked_liquidity == 0, AMMError::PoolNotEmpty);
+                0
+            }
+        };
+        require!(self.mint_lp.supply == locked, AMMError::PoolNotEmpty);
+        require!(
+            pool.protocol_fees_a == 0 && pool.protocol_fees_b == 0,
+            AMMError::UnclaimedProtocolFees
+        );
+
+        // no LP is left to withdraw what is in the vaults: what backs the locked lp tokens, the
+        // fees they earned and any plain transfer into a vault. None of it is the owner's, the
+        // vaults are emptied into the treasury whatever the amount, so a donation can't keep the
+        // pool from closing
+        let (amount_a, amount_b) = (self.vault_a.amount, self.vault_b.amount);
+        for is_a in [true, false] {
+            self.empty_vault(is_a)?;
+        }
+
+        Ok(PoolClosed {
+            pool: self.pool_config_account.key(),
+            owner: self.owner.key(),
+
+            amount_a,
+            amount_b,
+        })
+    }
+
+    fn empty_vault(&self, is_a: bool) -> Result<()> {
+        let (vault, to, mint) = match is_a {
+            true => (&self.vault_a, &self.treasury_token_a, &self.mint_a),
+            false => (&self.vault_b, &self.treasury_token_b, &self.mint_b),
+        };
+
+        let secret_seed = self.pool_config_account.seed.to_le_bytes();
+        let seeds = &[
+            POOL,
+            secret_seed.as_ref(),
+            &[self.pool_config_account.pool_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        if vault.amount > 0 {
+            let accounts = TransferChecked {
+                from: vault.to_account_info(),
+                mint: mint.to_account_info(),
+                to: to.to_account_info(),
+                authority: self.pool_config_account.to_account_info(),
+            };
+            let ctx = CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                accounts,
+                signer_seeds,
+            );
+            transfer_checked(ctx, vault.amount, mint.decimals)?;
+        }
+
+        let accounts = CloseAccount {
+            account: vault.to_account_info(),
+            destination: self.owner.to_account_info(),
+            authority: self.pool_config_account.to_account_info(),
+        };
+        let ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            accounts,
+            signer_seeds,
+        );
+        close_account(ctx)
+    }
+}
+
+// The close flow
+// - only the owner closes an Active pool, once every LP has withdrawn and the protocol fees are
+//   claimed, the lp supply has to be exactly the locked lp tokens
+// - whatever is left in the vaults goes to the treasury of the global fee recipient, then the
+//   empty vaults are closed. The lp supply is what makes a pool drained, not the vault balances,
+//   which anyone can top up
+// - the pool config, its observations and its pool pair entry close on exit, all rent to the owner
+// - pools migrated from 0.1.0 have no pool pair entry, and no locked lp when they had LPs before
+//   the migration; PoolConfig records both so they close the same way
+// - mint_lp stays: an SPL Token mint cannot be closed, and the locked lp tokens still point at it
@@ -7,11 +7,15 @@ use anchor_spl::{
 };
 
 use crate::{
-    constant::{MINT_LP, POOL},
+    constant::{GLOBAL_CONFIG, LOCKED_LP, MINIMUM_LIQUIDITY, MINT_LP, OBSERVATIONS, POOL},
+    deposit_slippage_check,
+    error::AMMError,
+    events::LiquidityAdded,
     helper::LiquidityPool,
-    state::PoolConfig,
+    state::{GlobalConfig, Observations, PoolAction, PoolConfig},
 };
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct DepositAsset<'info> {
     #[account(mut)]
@@ -39,9 +43,27 @@ pub struct DepositAsset<'info> {
     )]
     pub provider_lp_token: Box<InterfaceAccount<'info, TokenAccount>>,
 
+    /// CHECK: PDA the program never signs for, lp tokens sent to it are locked forever
+    #[account(
+        seeds = [LOCKED_LP, pool_config_account.key().as_ref()],
+        bump
+    )]
+    pub locked_lp_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = liquid_provider,
+        associated_token::mint = mint_lp,
+        associated_token::authority = locked_lp_authority,
+    )]
+    pub locked_lp_token: Box<InterfaceAccount<'info, TokenAccount>>,
+
     pub mint_a: Box<InterfaceAccount<'info, Mint>>,
     pub mint_b: Box<InterfaceAccount<'info, Mint>>,
 
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
     #[account(
         mut,
         seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
@@ -51,6 +73,13 @@ pub struct DepositAsset<'info> {
     )]
     pub pool_config_account: Box<Account<'info, PoolConfig>>,
 
+    #[account(
+        mut,
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump = observations.bump,
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
     #[account(
         mut,
         associated_token::mint = mint_a,
@@ -80,19 +109,72 @@ pub struct DepositAsset<'info> {
 }
 
 impl<'info> DepositAsset<'info> {
-    pub fn deposite(&mut self, amount_a: u64, amount_b: u64) -> Result<()> {
+    pub fn deposite(
+        &mut self,
+        max_amount_a: u64,
+        max_amount_b: u64,
+        min_lp_out: u64,
+    ) -> Result<LiquidityAdded> {
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Deposit)?;
+        self.pool_config_account.check_no_flash_loan()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+        let now = Clock::get()?.unix_timestamp;
+        let (price_reserve_a, price_reserve_b) = self
+            .pool_config_account
+            .price_reserves(reserve_a, reserve_b, now)?;
+        self.pool_config_account
+            .update_price_cumulative(price_reserve_a, price_reserve_b, now);
+        self.observations.write(now, price_reserve_a, price_reserve_b);
+        let is_first_deposit = self.mint_lp.supply == 0;
+
+        // the first LP sets the price, whatever was donated to the vaults before is theirs
+        let (amount_a, amount_b) = match is_first_deposit {
+            true => (max_amount_a, max_amount_b),
+            false => {
+                LiquidityPool::optimal_deposit(max_amount_a, max_amount_b, reserve_a, reserve_b)?
+            }
+        };
+
         let lp_token_amount = LiquidityPool::calculate_liquidity(LiquidityPool {
-            total_amount_a: self.vault_a.amount,
-            total_amount_b: self.vault_b.amount,
+            total_amount_a: reserve_a,
+            total_amount_b: reserve_b,
             deposit_amount_a: amount_a,
             deposit_amount_b: amount_b,
             mint_supply: self.mint_lp.supply,
+            curve: self.pool_config_account.current_curve(now),
         })?;
+
+        deposit_slippage_check!(min_lp_out, lp_token_amount);
+
         self.deposite_token(true, amount_a)?;
         self.deposite_token(false, amount_b)?;
-        self.mint_token(lp_token_amount)?;
-
-        Ok(())
+        self.mint_token(self.provider_lp_token.to_account_info(), lp_token_amount)?;
+
+        if is_first_deposit {
+            self.mint_token(self.locked_lp_token.to_account_info(), MINIMUM_LIQUIDITY)?;
+            self.pool_config_account.locked_liquidity = MINIMUM_LIQUIDITY;
+        }
+
+        self.vault_a.reload()?;
+        self.vault_b.reload()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+
+        Ok(LiquidityAdded {
+            pool: self.pool_config_account.key(),
+            user: self.liquid_provider.key(),
+
+            amount_a,
+            amount_b,
+            lp_minted: lp_token_amount,
+
+            reserve_a,
+            reserve_b,
+        })
     }
 
     fn deposite_token(&mut self, is_a: bool, amount: u64) -> Result<()> {
@@ -125,10 +207,10 @@ impl<'info> DepositAsset<'info> {
         Ok(())
     }
 
-    fn mint_token(&mut self, amount: u64) -> Result<()> {
+    fn mint_token(&mut self, to: AccountInfo<'info>, amount: u64) -> Result<()> {
         let accounts = MintTo {
             mint: self.mint_lp.to_account_info(),
-            to: self.provider_lp_token.to_account_info(),
+            to,
             authority: self.pool_config_account.to_account_info(),
         };
 
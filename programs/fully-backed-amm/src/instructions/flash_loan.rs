@@ -0,0 +1,246 @@
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::{
+        self as sysvar_instructions, load_current_index_checked, load_instruction_at_checked,
+    },
+    Discriminator,
+};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::{
+    constant::{FEE_DENOMINATOR, GLOBAL_CONFIG, POOL},
+    error::AMMError,
+    events::{FlashLoanBorrowed, FlashLoanRepaid},
+    helper::mul_div_ceil,
+    state::{GlobalConfig, PoolAction, PoolConfig},
+};
+
+// position of pool_config_account in the FlashLoan accounts, where a flash_repay names its pool
+const FLASH_LOAN_POOL_INDEX: usize = 4;
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct FlashLoan<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        has_one = mint_a.key(),
+        has_one = mint_b.key(),
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = borrower
+    )]
+    pub borrower_token_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = borrower
+    )]
+    pub borrower_token_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: the instructions sysvar, read by flash_borrow to find the flash_repay
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+impl<'info> FlashLoan<'info> {
+    pub fn flash_borrow(&mut self, amount_a: u64, amount_b: u64) -> Result<FlashLoanBorrowed> {
+        require!(amount_a > 0 || amount_b > 0, AMMError::AmountZero);
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Swap)?;
+        self.pool_config_account.check_no_flash_loan()?;
+        self.check_repay_later()?;
+
+        // the owed protocol fees are not lent out
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+        require!(
+            amount_a <= reserve_a && amount_b <= reserve_b,
+            AMMError::InsufficientLiquidity
+        );
+
+        self.pool_config_account.flash_loan_a = amount_a;
+        self.pool_config_account.flash_loan_b = amount_b;
+
+        if amount_a > 0 {
+            self.withdraw_token(true, amount_a)?;
+        }
+        if amount_b > 0 {
+            self.withdraw_token(false, amount_b)?;
+        }
+
+        Ok(FlashLoanBorrowed {
+            pool: self.pool_config_account.key(),
+            borrower: self.borrower.key(),
+
+            amount_a,
+            amount_b,
+        })
+    }
+
+    pub fn flash_repay(&mut self) -> Result<FlashLoanRepaid> {
+        // a loan taken before a pause in the same transaction can still be paid back
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Withdraw)?;
+
+        let pool = &self.pool_config_account;
+        let (amount_a, amount_b) = (pool.flash_loan_a, pool.flash_loan_b);
+        require!(amount_a > 0 || amount_b > 0, AMMError::NoFlashLoan);
+
+        // rounded up, a loan is never free while fee_bps > 0
+        let fee_a = mul_div_ceil(amount_a, pool.fee_bps as u64, FEE_DENOMINATOR)?;
+        let fee_b = mul_div_ceil(amount_b, pool.fee_bps as u64, FEE_DENOMINATOR)?;
+
+        if amount_a > 0 {
+            let owed = amount_a.checked_add(fee_a).ok_or(AMMError::Overflow)?;
+            self.deposit_token(true, owed)?;
+        }
+        if amount_b > 0 {
+            let owed = amount_b.checked_add(fee_b).ok_or(AMMError::Overflow)?;
+            self.deposit_token(false, owed)?;
+        }
+
+        self.pool_config_account.flash_loan_a = 0;
+        self.pool_config_account.flash_loan_b = 0;
+
+        self.vault_a.reload()?;
+        self.vault_b.reload()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+
+        Ok(FlashLoanRepaid {
+            pool: self.pool_config_account.key(),
+            borrower: self.borrower.key(),
+
+            amount_a,
+            amount_b,
+            fee_a,
+            fee_b,
+
+            reserve_a,
+            reserve_b,
+        })
+    }
+
+    // flash_borrow has to be a top-level instruction followed by exactly one flash_repay on the
+    // same pool. The repay itself charges the loan plus the fee, so the transaction fails without
+    // the money
+    fn check_repay_later(&self) -> Result<()> {
+        let instructions = self.instructions.to_account_info();
+        let current_index = load_current_index_checked(&instructions)? as usize;
+
+        let current = load_instruction_at_checked(current_index, &instructions)?;
+        require_keys_eq!(current.program_id, crate::ID, AMMError::FlashLoanCpi);
+
+        // only the pool_config_account slot counts, a pool passed as a remaining account of a
+        // repay on another pool would otherwise pass the check and never be repaid
+        let pool = self.pool_config_account.key();
+        let repay = crate::instruction::FlashRepay::DISCRIMINATOR;
+        let mut repays = 0;
+        let mut index = current_index + 1;
+        while let Ok(instruction) = load_instruction_at_checked(index, &instructions) {
+            if instruction.program_id == crate::ID
+                && instruction.data.starts_with(&repay)
+                && instruction.accounts.len() > FLASH_LOAN_POOL_INDEX
+                && instruction.accounts[FLASH_LOAN_POOL_INDEX].pubkey == pool
+            {
+                repays += 1;
+            }
+            index += 1;
+        }
+
+        match repays {
+            0 => err!(AMMError::FlashRepayMissing),
+            1 => Ok(()),
+            _ => err!(AMMError::DuplicateFlashRepay),
+        }
+    }
+
+    fn deposit_token(&mut self, is_a: bool, amount: u64) -> Result<()> {
+        let (from, to, mint) = match is_a {
+            true => (&self.borrower_token_a, &self.vault_a, &self.mint_a),
+            false => (&self.borrower_token_b, &self.vault_b, &self.mint_b),
+        };
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.borrower.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), accounts);
+        transfer_checked(ctx, amount, mint.decimals)
+    }
+
+    fn withdraw_token(&mut self, is_a: bool, amount: u64) -> Result<()> {
+        let (from, to, mint) = match is_a {
+            true => (&self.vault_a, &self.borrower_token_a, &self.mint_a),
+            false => (&self.vault_b, &self.borrower_token_b, &self.mint_b),
+        };
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.pool_config_account.to_account_info(),
+        };
+
+        let secret_seed = self.pool_config_account.seed.to_le_bytes();
+        let seeds = &[
+            POOL,
+            secret_seed.as_ref(),
+            &[self.pool_config_account.pool_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            accounts,
+            signer_seeds,
+        );
+        transfer_checked(ctx, amount, mint.decimals)
+    }
+}
+
+// The flash loan flow
+// - flash_borrow checks the rest of the transaction for a single flash_repay on the same pool
+//   and sends the tokens out, swaps, deposits and withdrawals on the pool are blocked from here
+// - the borrower uses the tokens in the instructions in between
+// - flash_repay takes back the loan plus fee_bps, the fee stays in the vaults for the LPs
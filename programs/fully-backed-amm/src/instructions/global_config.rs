@@ -0,0 +1,141 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constant::{GLOBAL_CONFIG, MAX_FEE_BPS, MAX_FEE_TIERS, MAX_TIMELOCK_DELAY, MIN_TIMELOCK_DELAY},
+    error::AMMError,
+    program::FullyBackedAmm,
+    state::GlobalConfig,
+};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GlobalConfigParams {
+    pub fee_tiers: Vec<u16>,
+    pub creation_fee: u64,
+    pub creation_fee_mint: Option<Pubkey>,
+    pub fee_recipient: Pubkey,
+    pub permissionless: bool,
+    pub default_protocol_fee_bps: u16,
+    pub guardian: Option<Pubkey>,
+    pub timelock_delay: i64,
+}
+
+// The singleton can only be created by the program upgrade authority, otherwise the first
+// caller after a deploy would become the admin
+#[derive(Accounts)]
+pub struct InitializeGlobalConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + GlobalConfig::INIT_SPACE,
+        seeds = [GLOBAL_CONFIG],
+        bump
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, FullyBackedAmm>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key()) @ AMMError::Unauthorized,
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateGlobalConfig<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_CONFIG],
+        bump = global_config.bump,
+        has_one = admin @ AMMError::Unauthorized,
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+}
+
+impl<'info> InitializeGlobalConfig<'info> {
+    pub fn init_global_config(
+        &mut self,
+        bumps: &InitializeGlobalConfigBumps,
+        params: GlobalConfigParams,
+    ) -> Result<()> {
+        self.global_config.set_inner(GlobalConfig {
+            admin: self.admin.key(),
+
+            fee_tiers: Vec::new(),
+
+            creation_fee: 0,
+            creation_fee_mint: None,
+            fee_recipient: self.admin.key(),
+
+            permissionless: false,
+            default_protocol_fee_bps: 0,
+            timelock_delay: MIN_TIMELOCK_DELAY,
+
+            guardian: None,
+            paused: false,
+
+            bump: bumps.global_config,
+        });
+
+        set_params(&mut self.global_config, params)
+    }
+}
+
+impl<'info> UpdateGlobalConfig<'info> {
+    // admin: hands the config over, the current admin keeps it when None
+    pub fn update_global_config(
+        &mut self,
+        admin: Option<Pubkey>,
+        params: GlobalConfigParams,
+    ) -> Result<()> {
+        if let Some(admin) = admin {
+            self.global_config.admin = admin;
+        }
+
+        set_params(&mut self.global_config, params)
+    }
+}
+
+fn set_params(config: &mut GlobalConfig, params: GlobalConfigParams) -> Result<()> {
+    let tiers = &params.fee_tiers;
+    require!(
+        (1..=MAX_FEE_TIERS).contains(&tiers.len())
+            && tiers.iter().all(|&tier| tier <= MAX_FEE_BPS)
+            && tiers
+                .iter()
+                .enumerate()
+                .all(|(index, tier)| !tiers[..index].contains(tier)),
+        AMMError::InvalidFeeTiers
+    );
+    require!(
+        params.default_protocol_fee_bps <= MAX_FEE_BPS,
+        AMMError::InvalidFee
+    );
+    require!(
+        (MIN_TIMELOCK_DELAY..=MAX_TIMELOCK_DELAY).contains(&params.timelock_delay),
+        AMMError::InvalidTimelockDelay
+    );
+
+    config.fee_tiers = params.fee_tiers;
+    config.creation_fee = params.creation_fee;
+    config.creation_fee_mint = params.creation_fee_mint;
+    config.fee_recipient = params.fee_recipient;
+    config.permissionless = params.permissionless;
+    config.default_protocol_fee_bps = params.default_protocol_fee_bps;
+    config.guardian = params.guardian;
+    config.timelock_delay = params.timelock_delay;
+
+    Ok(())
+}
+
+// The global config flow
+// - the upgrade authority creates the config once, it becomes the admin
+// - the admin updates the fee tiers, creation fee, permissionless switch, protocol fee default,
+//   the guardian and the timelock delay on pool parameter changes
+// - initialize reads the config: allowed fee tier, who may create the pool and the creation fee
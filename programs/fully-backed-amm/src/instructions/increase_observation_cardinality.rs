@@ -0,0 +1,63 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constant::{MAX_OBSERVATION_CARDINALITY, OBSERVATIONS, POOL},
+    error::AMMError,
+    events::ObservationCardinalityIncreased,
+    state::{Observation, Observations, PoolConfig},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(cardinality_next: u16)]
+pub struct IncreaseObservationCardinality<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    #[account(
+        mut,
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump = observations.bump,
+        realloc = Observations::space(cardinality_next),
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> IncreaseObservationCardinality<'info> {
+    pub fn increase_cardinality(
+        &mut self,
+        cardinality_next: u16,
+    ) -> Result<ObservationCardinalityIncreased> {
+        let cardinality_old = self.observations.observations.len();
+        require!(
+            cardinality_next as usize > cardinality_old
+                && cardinality_next <= MAX_OBSERVATION_CARDINALITY,
+            AMMError::InvalidCardinality
+        );
+
+        // the new slots stay empty until the ring index wraps around to them
+        self.observations
+            .observations
+            .resize(cardinality_next as usize, Observation::default());
+
+        Ok(ObservationCardinalityIncreased {
+            pool: self.pool_config_account.key(),
+            payer: self.payer.key(),
+
+            cardinality_old: cardinality_old as u16,
+            cardinality_new: cardinality_next,
+        })
+    }
+}
+
+// Anyone can pay to grow the ring buffer, so the pool keeps a longer price history
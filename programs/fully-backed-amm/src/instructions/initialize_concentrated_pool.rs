@@ -0,0 +1,187 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{Mint, TokenAccount, TokenInterface},
+};
+
+use crate::{
+    constant::{CONCENTRATED_POOL, GLOBAL_CONFIG, MAX_TICK_SPACING, POOL_PAIR},
+    error::AMMError,
+    events::ConcentratedPoolInitialized,
+    helper::{concentrated::tick_at_sqrt_price, CreationFee},
+    state::{ConcentratedPool, GlobalConfig, PoolPair, PoolStatus},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(seed: u64, tick_spacing: u16, fee_bps: u16)]
+pub struct InitializeConcentratedPool<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(constraint = mint_b.key() != mint_a.key() @ AMMError::IdenticalMints)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    // same creation policy as initialize
+    #[account(
+        seeds = [GLOBAL_CONFIG],
+        bump = global_config.bump,
+        constraint = global_config.permissionless || global_config.admin == admin.key() @ AMMError::PoolCreationRestricted,
+        constraint = !global_config.paused @ AMMError::ProtocolPaused,
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    // Creation fee Accounts, see InitializePool
+    /// CHECK: receives the fee in SOL, checked against the global config
+    #[account(mut, address = global_config.fee_recipient @ AMMError::InvalidCreationFee)]
+    pub fee_recipient: UncheckedAccount<'info>,
+    #[account(mint::token_program = fee_token_program)]
+    pub creation_fee_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(mut, token::token_program = fee_token_program)]
+    pub admin_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(mut, token::token_program = fee_token_program)]
+    pub fee_recipient_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub fee_token_program: Interface<'info, TokenInterface>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + ConcentratedPool::INIT_SPACE,
+        seeds = [CONCENTRATED_POOL, seed.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub pool: Box<Account<'info, ConcentratedPool>>,
+
+    // registered like the other pools, one concentrated pool per mint pair and fee tier
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + PoolPair::INIT_SPACE,
+        seeds = [
+            POOL_PAIR,
+            mint_a.key().min(mint_b.key()).as_ref(),
+            mint_a.key().max(mint_b.key()).as_ref(),
+            fee_bps.to_le_bytes().as_ref(),
+            &[ConcentratedPool::PAIR_KIND],
+        ],
+        bump
+    )]
+    pub pool_pair: Box<Account<'info, PoolPair>>,
+
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+impl<'info> InitializeConcentratedPool<'info> {
+    pub fn init_pool(
+        &mut self,
+        bumps: &InitializeConcentratedPoolBumps,
+        seed: u64,
+        tick_spacing: u16,
+        fee_bps: u16,
+        sqrt_price_x64: u128,
+    ) -> Result<ConcentratedPoolInitialized> {
+        require!(
+            tick_spacing > 0 && tick_spacing <= MAX_TICK_SPACING,
+            AMMError::InvalidTickSpacing
+        );
+        require!(
+            self.global_config.allows_fee_tier(fee_bps),
+            AMMError::FeeTierNotAllowed
+        );
+
+        // errors when the price is outside [MIN_TICK, MAX_TICK]
+        let tick_current = tick_at_sqrt_price(sqrt_price_x64)?;
+
+        CreationFee::pay(CreationFee {
+            config: &self.global_config,
+            payer: self.admin.to_account_info(),
+            fee_recipient: self.fee_recipient.to_account_info(),
+
+            fee_mint: self.creation_fee_mint.as_deref(),
+            payer_fee_token: self.admin_fee_token.as_deref(),
+            fee_recipient_token: self.fee_recipient_token.as_deref(),
+
+            system_program: self.system_program.to_account_info(),
+            fee_token_program: self.fee_token_program.to_account_info(),
+        })?;
+
+        self.pool.set_inner(ConcentratedPool {
+            owner: Some(self.admin.key()),
+
+            mint_a: self.mint_a.key(),
+            mint_b: self.mint_b.key(),
+
+            seed,
+            bump: bumps.pool,
+
+            tick_spacing,
+            fee_bps,
+
+            sqrt_price_x64,
+            tick_current,
+            liquidity: 0,
+
+            fee_growth_global_a_x64: 0,
+            fee_growth_global_b_x64: 0,
+
+            status: PoolStatus::Active,
+        });
+
+        let (mint_a, mint_b) = (self.mint_a.key(), self.mint_b.key());
+        self.pool_pair.set_inner(PoolPair {
+            pool: self.pool.key(),
+
+            mint_min: mint_a.min(mint_b),
+            mint_max: mint_a.max(mint_b),
+            fee_bps,
+            curve: ConcentratedPool::PAIR_KIND,
+
+            bump: bumps.pool_pair,
+        });
+
+        Ok(ConcentratedPoolInitialized {
+            pool: self.pool.key(),
+            admin: self.admin.key(),
+
+            mint_a: self.mint_a.key(),
+            mint_b: self.mint_b.key(),
+
+            seed,
+            tick_spacing,
+            fee_bps,
+            sqrt_price_x64,
+            tick_current,
+        })
+    }
+}
+
+// The concentrated pool flow
+// - initialize the pool at a starting sqrt price, with its vaults. Creation follows the
+//   GlobalConfig policy like initialize: admin only unless permissionless, an allowed fee tier
+//   and the creation fee. Distinct mints, and the pool is registered in the pair registry under
+//   ConcentratedPool::PAIR_KIND
+// - anyone initializes the tick arrays that positions and swaps need
+// - LPs open positions between two ticks and add or remove liquidity
+// - swaps move the price through the ticks, fees go to the positions in range
+// - the owner or the guardian sets the pool status with set_concentrated_pool_status, every
+//   instruction on the pool checks it along with the global kill switch
@@ -1,19 +1,48 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::{self, AssociatedToken},
-    token_interface::{Mint, TokenInterface},
+    token_interface::{Mint, TokenAccount, TokenInterface},
 };
 
-use crate::{constant::*, state::PoolConfig};
+use crate::{
+    constant::*,
+    error::AMMError,
+    events::PoolInitialized,
+    helper::CreationFee,
+    state::{CurveType, GlobalConfig, Observation, Observations, PoolConfig, PoolPair, PoolStatus},
+};
+#[event_cpi]
 #[derive(Accounts)]
-#[instruction(seed: u64)]
+#[instruction(seed: u64, fee_bps: u16, protocol_fee_bps: Option<u16>, curve: CurveType)]
 pub struct InitializePool<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
 
     pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(constraint = mint_b.key() != mint_a.key() @ AMMError::IdenticalMints)]
     pub mint_b: Box<InterfaceAccount<'info, Mint>>,
 
+    #[account(
+        seeds = [GLOBAL_CONFIG],
+        bump = global_config.bump,
+        constraint = global_config.permissionless || global_config.admin == admin.key() @ AMMError::PoolCreationRestricted,
+        constraint = !global_config.paused @ AMMError::ProtocolPaused,
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    // Creation fee Accounts, the token ones only when the fee is paid in a token. The fee mint
+    // has its own token program, it may differ from the one of the pool mints
+    /// CHECK: receives the fee in SOL, checked against the global config
+    #[account(mut, address = global_config.fee_recipient @ AMMError::InvalidCreationFee)]
+    pub fee_recipient: UncheckedAccount<'info>,
+    #[account(mint::token_program = fee_token_program)]
+    pub creation_fee_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(mut, token::token_program = fee_token_program)]
+    pub admin_fee_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(mut, token::token_program = fee_token_program)]
+    pub fee_recipient_token: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub fee_token_program: Interface<'info, TokenInterface>,
+
     #[account(
         init,
         payer = admin,
@@ -23,6 +52,31 @@ pub struct InitializePool<'info> {
     )]
     pub pool_config_account: Box<Account<'info, PoolConfig>>,
 
+    // one pool per mint pair, fee tier and curve, a second initialize fails on this init
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + PoolPair::INIT_SPACE,
+        seeds = [
+            POOL_PAIR,
+            mint_a.key().min(mint_b.key()).as_ref(),
+            mint_a.key().max(mint_b.key()).as_ref(),
+            fee_bps.to_le_bytes().as_ref(),
+            &[curve.kind()],
+        ],
+        bump
+    )]
+    pub pool_pair: Box<Account<'info, PoolPair>>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = Observations::space(1),
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
     #[account(
         init,
         payer = admin,
@@ -67,7 +121,58 @@ pub struct InitializePool<'info> {
 }
 
 impl<'info> InitializePool<'info> {
-    pub fn init_pool(&mut self, bumps: InitializePoolBumps, seeds: u64) -> Result<()> {
+    pub fn init_pool(
+        &mut self,
+        bumps: &InitializePoolBumps,
+        seeds: u64,
+        fee_bps: u16,
+        protocol_fee_bps: Option<u16>,
+        curve: CurveType,
+    ) -> Result<PoolInitialized> {
+        let config = &self.global_config;
+        require!(config.allows_fee_tier(fee_bps), AMMError::FeeTierNotAllowed);
+
+        // only the global admin may move a pool off the default protocol fee
+        let protocol_fee_bps = match protocol_fee_bps {
+            Some(protocol_fee_bps) => {
+                require_keys_eq!(self.admin.key(), config.admin, AMMError::Unauthorized);
+                protocol_fee_bps
+            }
+            None => config.default_protocol_fee_bps,
+        };
+        require!(
+            fee_bps as u64 + protocol_fee_bps as u64 <= MAX_FEE_BPS as u64,
+            AMMError::InvalidFee
+        );
+        let target_amp = match curve {
+            CurveType::StableSwap { amp } => {
+                require!((MIN_AMP..=MAX_AMP).contains(&amp), AMMError::InvalidAmp);
+                amp
+            }
+            CurveType::Weighted { weight_a, weight_b } => {
+                require!(
+                    weight_a as u64 + weight_b as u64 == FEE_DENOMINATOR
+                        && weight_a.min(weight_b) >= MIN_WEIGHT_BPS,
+                    AMMError::InvalidWeights
+                );
+                0
+            }
+            CurveType::ConstantProduct => 0,
+        };
+
+        CreationFee::pay(CreationFee {
+            config: &self.global_config,
+            payer: self.admin.to_account_info(),
+            fee_recipient: self.fee_recipient.to_account_info(),
+
+            fee_mint: self.creation_fee_mint.as_deref(),
+            payer_fee_token: self.admin_fee_token.as_deref(),
+            fee_recipient_token: self.fee_recipient_token.as_deref(),
+
+            system_program: self.system_program.to_account_info(),
+            fee_token_program: self.fee_token_program.to_account_info(),
+        })?;
+
         // create Vault ATAs through CPI
         let ctx_a_accounts = associated_token::Create {
             payer: self.admin.to_account_info(),
@@ -96,8 +201,12 @@ impl<'info> InitializePool<'info> {
             ctx_b_accounts,
         ))?;
 
+        let now = Clock::get()?.unix_timestamp;
+
         // Saving the pool config data
         self.pool_config_account.set_inner(PoolConfig {
+            version: POOL_CONFIG_VERSION,
+
             owner: Some(self.admin.key()),
 
             mint_a: self.mint_a.key(),
@@ -108,9 +217,77 @@ impl<'info> InitializePool<'info> {
             pool_bump: bumps.pool_config_account,
             
             vault_a_bump:bumps.vault_a,
-            vault_b_bump:bumps.vault_b
+            vault_b_bump:bumps.vault_b,
+
+            fee_bps,
+            protocol_fee_bps,
+
+            protocol_fees_a: 0,
+            protocol_fees_b: 0,
+
+            curve,
+            target_amp,
+            ramp_start_timestamp: now,
+            ramp_stop_timestamp: now,
+
+            price_a_cumulative: 0,
+            price_b_cumulative: 0,
+            last_update_timestamp: now,
+
+            oracle: None,
+            oracle_max_deviation_bps: 0,
+            oracle_max_confidence_bps: 0,
+            oracle_max_age: 0,
+
+            flash_loan_a: 0,
+            flash_loan_b: 0,
+
+            status: PoolStatus::Active,
+            pending_owner: None,
+
+            locked_liquidity: 0,
+            registered: true,
+
+            reserved: [0; POOL_CONFIG_RESERVED],
         });
 
-        Ok(())
+        let (mint_a, mint_b) = (self.mint_a.key(), self.mint_b.key());
+        self.pool_pair.set_inner(PoolPair {
+            pool: self.pool_config_account.key(),
+
+            mint_min: mint_a.min(mint_b),
+            mint_max: mint_a.max(mint_b),
+            fee_bps,
+            curve: curve.kind(),
+
+            bump: bumps.pool_pair,
+        });
+
+        // first observation, the ring buffer starts with a single slot
+        self.observations.set_inner(Observations {
+            pool: self.pool_config_account.key(),
+            bump: bumps.observations,
+
+            index: 0,
+            cardinality: 1,
+            observations: vec![Observation {
+                timestamp: now,
+                ..Observation::default()
+            }],
+        });
+
+        Ok(PoolInitialized {
+            pool: self.pool_config_account.key(),
+            admin: self.admin.key(),
+
+            mint_a: self.mint_a.key(),
+            mint_b: self.mint_b.key(),
+            mint_lp: self.mint_lp.key(),
+
+            seed: seeds,
+            fee_bps,
+            protocol_fee_bps,
+            curve,
+        })
     }
 }
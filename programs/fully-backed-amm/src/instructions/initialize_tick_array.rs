@@ -0,0 +1,73 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constant::{CONCENTRATED_POOL, GLOBAL_CONFIG, MAX_TICK, MIN_TICK, TICK_ARRAY, TICK_ARRAY_SIZE},
+    error::AMMError,
+    events::TickArrayInitialized,
+    state::{ConcentratedPool, GlobalConfig, PoolAction, Tick, TickArray},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(start_tick_index: i32)]
+pub struct InitializeTickArray<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        seeds = [CONCENTRATED_POOL, pool.seed.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, ConcentratedPool>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = TickArray::space(),
+        seeds = [TICK_ARRAY, pool.key().as_ref(), start_tick_index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub tick_array: Box<Account<'info, TickArray>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitializeTickArray<'info> {
+    pub fn init_tick_array(&mut self, start_tick_index: i32) -> Result<TickArrayInitialized> {
+        // new arrays serve new positions or swaps, so they are refused only once both are
+        self.pool
+            .check_status(&self.global_config, PoolAction::Deposit)
+            .or_else(|_| {
+                self.pool
+                    .check_status(&self.global_config, PoolAction::Swap)
+            })?;
+
+        let tick_spacing = self.pool.tick_spacing;
+        require!(
+            TickArray::start_tick_index(start_tick_index, tick_spacing) == start_tick_index
+                && start_tick_index + TICK_ARRAY_SIZE * tick_spacing as i32 > MIN_TICK
+                && start_tick_index <= MAX_TICK,
+            AMMError::InvalidTickArray
+        );
+
+        self.tick_array.set_inner(TickArray {
+            pool: self.pool.key(),
+            start_tick_index,
+            ticks: vec![Tick::default(); TICK_ARRAY_SIZE as usize],
+        });
+
+        Ok(TickArrayInitialized {
+            pool: self.pool.key(),
+            payer: self.payer.key(),
+
+            tick_array: self.tick_array.key(),
+            start_tick_index,
+        })
+    }
+}
+
+// Anyone can pay for a tick array, a position needs the arrays of its two ticks and a swap
+// the arrays the price moves through
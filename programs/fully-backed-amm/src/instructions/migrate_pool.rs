@@ -0,0 +1,96 @@
+use anchor_lang::{
+    prelude::*,
+    system_program::{transfer, Transfer},
+};
+
+use crate::{
+    constant::{OBSERVATIONS, POOL_CONFIG_VERSION},
+    error::AMMError,
+    events::PoolMigrated,
+    state::{Observation, Observations, PoolConfig, PoolConfigV0},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: still in the 0.1.0 layout, which Account<PoolConfig> can't read. The discriminator
+    /// is checked in migrate_pool
+    #[account(
+        mut,
+        owner = crate::ID,
+        constraint = pool_config_account.data_len() == PoolConfigV0::LEN @ AMMError::InvalidPoolVersion,
+    )]
+    pub pool_config_account: UncheckedAccount<'info>,
+
+    // 0.1.0 had no TWAP, swaps and deposits need the ring buffer from now on
+    #[account(
+        init,
+        payer = payer,
+        space = Observations::space(1),
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> MigratePool<'info> {
+    pub fn migrate_pool(&mut self, bumps: &MigratePoolBumps) -> Result<PoolMigrated> {
+        let pool = self.pool_config_account.to_account_info();
+        let legacy = PoolConfigV0::try_from_account_data(&pool.try_borrow_data()?)?;
+
+        // top the account up to the rent of the new size before growing it
+        let space = 8 + PoolConfig::INIT_SPACE;
+        let rent = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(pool.lamports());
+        if rent > 0 {
+            let accounts = Transfer {
+                from: self.payer.to_account_info(),
+                to: pool.clone(),
+            };
+            let ctx = CpiContext::new(self.system_program.to_account_info(), accounts);
+            transfer(ctx, rent)?;
+        }
+        pool.realloc(space, true)?;
+
+        // the discriminator stays, only the body is rewritten in the new layout
+        let now = Clock::get()?.unix_timestamp;
+        PoolConfig::from_v0(legacy, now).serialize(&mut &mut pool.try_borrow_mut_data()?[8..])?;
+
+        self.observations.set_inner(Observations {
+            pool: pool.key(),
+            bump: bumps.observations,
+
+            index: 0,
+            cardinality: 1,
+            observations: vec![Observation {
+                timestamp: now,
+                ..Observation::default()
+            }],
+        });
+
+        Ok(PoolMigrated {
+            pool: pool.key(),
+            payer: self.payer.key(),
+
+            version: POOL_CONFIG_VERSION,
+        })
+    }
+}
+
+// The migrate flow
+// - anyone can migrate a pool still in the 0.1.0 layout, the payer covers the extra rent
+// - the account grows to the current PoolConfig size, the 0.1.0 fields are kept and the rest
+//   gets the defaults of a fee-less constant product pool (see PoolConfig::from_v0)
+// - the observations account is created, the pool can swap and deposit right after
+// - migrated pools are not in the pool pair registry, 0.1.0 allowed several pools per pair.
+//   PoolConfig::registered is false, so close_pool doesn't ask for an entry and initialize can
+//   still register a pool for the same pair
+// - locked_liquidity stays 0 since the LPs of 0.1.0 never locked any, a migrated pool with no
+//   lp supply locks MINIMUM_LIQUIDITY on its next first deposit like a new pool
+// - later layouts bump POOL_CONFIG_VERSION and take their fields out of the reserved bytes
@@ -1,9 +1,43 @@
+pub mod claim_protocol_fees;
+pub mod close_pool;
 pub mod deposit_asset;
+pub mod flash_loan;
+pub mod global_config;
+pub mod increase_observation_cardinality;
+pub mod initialize_concentrated_pool;
 pub mod initialize_pool;
+pub mod initialize_tick_array;
+pub mod migrate_pool;
+pub mod modify_liquidity;
+pub mod observe;
+pub mod open_position;
+pub mod ownership;
+pub mod pool_status;
+pub mod ramp_amp;
 pub mod swap;
+pub mod swap_concentrated;
+pub mod swap_route;
+pub mod timelock;
 pub mod withdraw_asset;
 
+pub use claim_protocol_fees::*;
+pub use close_pool::*;
 pub use deposit_asset::*;
+pub use flash_loan::*;
+pub use global_config::*;
+pub use increase_observation_cardinality::*;
+pub use initialize_concentrated_pool::*;
 pub use initialize_pool::*;
+pub use initialize_tick_array::*;
+pub use migrate_pool::*;
+pub use modify_liquidity::*;
+pub use observe::*;
+pub use open_position::*;
+pub use ownership::*;
+pub use pool_status::*;
+pub use ramp_amp::*;
 pub use swap::*;
+pub use swap_concentrated::*;
+pub use swap_route::*;
+pub use timelock::*;
 pub use withdraw_asset::*;
@@ -0,0 +1,332 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::{
+    constant::{CONCENTRATED_POOL, GLOBAL_CONFIG, POSITION},
+    error::AMMError,
+    events::{ConcentratedLiquidityAdded, ConcentratedLiquidityRemoved, PositionFeesCollected},
+    helper::concentrated::{amounts_for_liquidity, fee_growth_inside, sqrt_price_at_tick},
+    state::{ConcentratedPool, GlobalConfig, PoolAction, Position, Tick, TickArray},
+    withdraw_slippage_check,
+};
+
+// tick_array_lower and tick_array_upper may be the same account when both ticks are in one array
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ModifyLiquidity<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [CONCENTRATED_POOL, pool.seed.to_le_bytes().as_ref()],
+        bump = pool.bump,
+        has_one = mint_a.key(),
+        has_one = mint_b.key(),
+    )]
+    pub pool: Box<Account<'info, ConcentratedPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            POSITION,
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            position.tick_lower.to_le_bytes().as_ref(),
+            position.tick_upper.to_le_bytes().as_ref(),
+        ],
+        bump = position.bump,
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(
+        mut,
+        constraint = tick_array_lower.pool == pool.key() @ AMMError::InvalidTickArray,
+    )]
+    pub tick_array_lower: Box<Account<'info, TickArray>>,
+    #[account(
+        mut,
+        constraint = tick_array_upper.pool == pool.key() @ AMMError::InvalidTickArray,
+    )]
+    pub tick_array_upper: Box<Account<'info, TickArray>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = owner,
+    )]
+    pub owner_token_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = owner,
+    )]
+    pub owner_token_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool,
+    )]
+    pub vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool,
+    )]
+    pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+impl<'info> ModifyLiquidity<'info> {
+    pub fn increase_liquidity(
+        &mut self,
+        liquidity: u128,
+        max_amount_a: u64,
+        max_amount_b: u64,
+    ) -> Result<ConcentratedLiquidityAdded> {
+        self.pool
+            .check_status(&self.global_config, PoolAction::Deposit)?;
+        let liquidity_delta = i128::try_from(liquidity).map_err(|_| AMMError::Overflow)?;
+        require!(liquidity_delta > 0, AMMError::AmountZero);
+
+        self.modify_position(liquidity_delta)?;
+
+        // rounded up, the provider owes these
+        let (amount_a, amount_b) = self.amounts(liquidity, true)?;
+        require!(
+            amount_a <= max_amount_a && amount_b <= max_amount_b,
+            AMMError::MaxAmountInExceeded
+        );
+
+        if amount_a > 0 {
+            self.deposit_token(true, amount_a)?;
+        }
+        if amount_b > 0 {
+            self.deposit_token(false, amount_b)?;
+        }
+
+        Ok(ConcentratedLiquidityAdded {
+            pool: self.pool.key(),
+            owner: self.owner.key(),
+
+            position: self.position.key(),
+            liquidity,
+            amount_a,
+            amount_b,
+
+            pool_liquidity: self.pool.liquidity,
+        })
+    }
+
+    pub fn decrease_liquidity(
+        &mut self,
+        liquidity: u128,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<ConcentratedLiquidityRemoved> {
+        self.pool
+            .check_status(&self.global_config, PoolAction::Withdraw)?;
+        require!(
+            liquidity > 0 && liquidity <= self.position.liquidity,
+            AMMError::InsufficientLiquidity
+        );
+
+        self.modify_position(-(liquidity as i128))?;
+
+        // rounded down, the pool keeps the dust
+        let (amount_a, amount_b) = self.amounts(liquidity, false)?;
+        withdraw_slippage_check!(min_amount_a, amount_a);
+        withdraw_slippage_check!(min_amount_b, amount_b);
+
+        if amount_a > 0 {
+            self.withdraw_token(true, amount_a)?;
+        }
+        if amount_b > 0 {
+            self.withdraw_token(false, amount_b)?;
+        }
+
+        Ok(ConcentratedLiquidityRemoved {
+            pool: self.pool.key(),
+            owner: self.owner.key(),
+
+            position: self.position.key(),
+            liquidity,
+            amount_a,
+            amount_b,
+
+            pool_liquidity: self.pool.liquidity,
+        })
+    }
+
+    pub fn collect_fees(&mut self) -> Result<PositionFeesCollected> {
+        self.pool
+            .check_status(&self.global_config, PoolAction::Withdraw)?;
+
+        // brings fees_owed up to date without touching the liquidity
+        self.modify_position(0)?;
+
+        let (fees_a, fees_b) = (self.position.fees_owed_a, self.position.fees_owed_b);
+        if fees_a > 0 {
+            self.withdraw_token(true, fees_a)?;
+        }
+        if fees_b > 0 {
+            self.withdraw_token(false, fees_b)?;
+        }
+
+        self.position.fees_owed_a = 0;
+        self.position.fees_owed_b = 0;
+
+        Ok(PositionFeesCollected {
+            pool: self.pool.key(),
+            owner: self.owner.key(),
+
+            position: self.position.key(),
+            fees_a,
+            fees_b,
+        })
+    }
+
+    // Uniswap V3 _updatePosition: ticks, then position fees, then the active liquidity
+    fn modify_position(&mut self, liquidity_delta: i128) -> Result<()> {
+        let (tick_lower, tick_upper) = (self.position.tick_lower, self.position.tick_upper);
+        let tick_spacing = self.pool.tick_spacing;
+        require!(
+            self.tick_array_lower.contains(tick_lower, tick_spacing)
+                && self.tick_array_upper.contains(tick_upper, tick_spacing),
+            AMMError::InvalidTickArray
+        );
+
+        if liquidity_delta != 0 {
+            self.update_tick(tick_lower, liquidity_delta, false)?;
+            self.update_tick(tick_upper, liquidity_delta, true)?;
+        }
+
+        let pool = &self.pool;
+        let (fee_growth_inside_a, fee_growth_inside_b) = fee_growth_inside(
+            self.tick_array_lower.tick(tick_lower, tick_spacing)?,
+            tick_lower,
+            self.tick_array_upper.tick(tick_upper, tick_spacing)?,
+            tick_upper,
+            pool.tick_current,
+            pool.fee_growth_global_a_x64,
+            pool.fee_growth_global_b_x64,
+        );
+        self.position
+            .update_fees(fee_growth_inside_a, fee_growth_inside_b)?;
+        self.position.liquidity = self
+            .position
+            .liquidity
+            .checked_add_signed(liquidity_delta)
+            .ok_or(AMMError::Overflow)?;
+
+        // ticks nobody references anymore go back to uninitialized
+        if liquidity_delta < 0 {
+            for tick in [tick_lower, tick_upper] {
+                for array in [&mut self.tick_array_lower, &mut self.tick_array_upper] {
+                    if array.contains(tick, tick_spacing) {
+                        let tick = array.tick_mut(tick, tick_spacing)?;
+                        if tick.liquidity_gross == 0 {
+                            *tick = Tick::default();
+                        }
+                    }
+                }
+            }
+        }
+
+        if (tick_lower..tick_upper).contains(&self.pool.tick_current) {
+            self.pool.liquidity = self
+                .pool
+                .liquidity
+                .checked_add_signed(liquidity_delta)
+                .ok_or(AMMError::Overflow)?;
+        }
+
+        Ok(())
+    }
+
+    // both accounts are written back on exit, so a shared array gets the update in both copies
+    fn update_tick(&mut self, tick: i32, liquidity_delta: i128, is_upper: bool) -> Result<()> {
+        let tick_spacing = self.pool.tick_spacing;
+        for array in [&mut self.tick_array_lower, &mut self.tick_array_upper] {
+            if array.contains(tick, tick_spacing) {
+                array.tick_mut(tick, tick_spacing)?.update(
+                    tick,
+                    &self.pool,
+                    liquidity_delta,
+                    is_upper,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn amounts(&self, liquidity: u128, round_up: bool) -> Result<(u64, u64)> {
+        amounts_for_liquidity(
+            self.pool.sqrt_price_x64,
+            sqrt_price_at_tick(self.position.tick_lower)?,
+            sqrt_price_at_tick(self.position.tick_upper)?,
+            liquidity,
+            round_up,
+        )
+    }
+
+    fn deposit_token(&mut self, is_a: bool, amount: u64) -> Result<()> {
+        let (from, to, mint) = match is_a {
+            true => (&self.owner_token_a, &self.vault_a, &self.mint_a),
+            false => (&self.owner_token_b, &self.vault_b, &self.mint_b),
+        };
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.owner.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), accounts);
+        transfer_checked(ctx, amount, mint.decimals)
+    }
+
+    fn withdraw_token(&mut self, is_a: bool, amount: u64) -> Result<()> {
+        let (from, to, mint) = match is_a {
+            true => (&self.vault_a, &self.owner_token_a, &self.mint_a),
+            false => (&self.vault_b, &self.owner_token_b, &self.mint_b),
+        };
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.pool.to_account_info(),
+        };
+
+        let seed = self.pool.seed.to_le_bytes();
+        let seeds = &[CONCENTRATED_POOL, seed.as_ref(), &[self.pool.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            accounts,
+            signer_seeds,
+        );
+        transfer_checked(ctx, amount, mint.decimals)
+    }
+}
+
+// The liquidity flow
+// - increase_liquidity adds liquidity to the position, the provider pays the matching amounts
+// - decrease_liquidity removes it and sends the amounts back straight away
+// - collect_fees pays out the fees the position earned while the price was inside its range
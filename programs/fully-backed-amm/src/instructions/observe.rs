@@ -0,0 +1,63 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+
+use crate::{
+    constant::{OBSERVATIONS, POOL},
+    state::{Observation, Observations, PoolConfig},
+};
+
+#[derive(Accounts)]
+pub struct Observe<'info> {
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        has_one = mint_a.key(),
+        has_one = mint_b.key(),
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    #[account(
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump = observations.bump,
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
+    #[account(
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_config_account,
+    )]
+    pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+impl<'info> Observe<'info> {
+    pub fn observe(&self, seconds_ago: Vec<u32>) -> Result<Vec<Observation>> {
+        let now = Clock::get()?.unix_timestamp;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .price_reserves(reserve_a, reserve_b, now)?;
+
+        seconds_ago
+            .into_iter()
+            .map(|seconds_ago| {
+                self.observations
+                    .observe(now, seconds_ago, reserve_a, reserve_b)
+            })
+            .collect()
+    }
+}
+
+// The observe flow (view only, the result comes back as return data)
+// - for every seconds_ago find the observations around now - seconds_ago
+// - interpolate between them, or extend the newest one with the current reserves
+// - TWAP between two results = (cumulative_1 - cumulative_0) / (timestamp_1 - timestamp_0)
@@ -0,0 +1,91 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constant::{CONCENTRATED_POOL, GLOBAL_CONFIG, MAX_TICK, MIN_TICK, POSITION},
+    error::AMMError,
+    events::PositionOpened,
+    state::{ConcentratedPool, GlobalConfig, PoolAction, Position},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(tick_lower: i32, tick_upper: i32)]
+pub struct OpenPosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        seeds = [CONCENTRATED_POOL, pool.seed.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, ConcentratedPool>>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Position::INIT_SPACE,
+        seeds = [
+            POSITION,
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            tick_lower.to_le_bytes().as_ref(),
+            tick_upper.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> OpenPosition<'info> {
+    pub fn open_position(
+        &mut self,
+        bumps: &OpenPositionBumps,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<PositionOpened> {
+        // a position only exists to take liquidity, so it opens under the deposit rules
+        self.pool
+            .check_status(&self.global_config, PoolAction::Deposit)?;
+
+        let tick_spacing = self.pool.tick_spacing as i32;
+        require!(
+            tick_lower < tick_upper
+                && tick_lower >= MIN_TICK
+                && tick_upper <= MAX_TICK
+                && tick_lower % tick_spacing == 0
+                && tick_upper % tick_spacing == 0,
+            AMMError::InvalidTickRange
+        );
+
+        self.position.set_inner(Position {
+            pool: self.pool.key(),
+            owner: self.owner.key(),
+            bump: bumps.position,
+
+            tick_lower,
+            tick_upper,
+            liquidity: 0,
+
+            fee_growth_inside_a_last: 0,
+            fee_growth_inside_b_last: 0,
+            fees_owed_a: 0,
+            fees_owed_b: 0,
+        });
+
+        Ok(PositionOpened {
+            pool: self.pool.key(),
+            owner: self.owner.key(),
+
+            position: self.position.key(),
+            tick_lower,
+            tick_upper,
+        })
+    }
+}
+
+// A position starts empty, liquidity is added with increase_liquidity
@@ -0,0 +1,121 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constant::{OWNER_CHANGE_SLOT, PENDING_CHANGE, POOL},
+    error::AMMError,
+    events::{OwnershipTransferStarted, OwnershipTransferred},
+    state::PoolConfig,
+};
+
+// propose_owner
+#[event_cpi]
+#[derive(Accounts)]
+pub struct TransferOwnership<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        constraint = pool_config_account.owner == Some(owner.key()) @ AMMError::Unauthorized,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RenounceOwnership<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        constraint = pool_config_account.owner == Some(owner.key()) @ AMMError::Unauthorized,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    /// CHECK: only read to see that the owner has no change queued, execute and cancel need the
+    /// owner so a change left behind could never be resolved. A protocol fee change of the global
+    /// admin doesn't hold up the renounce, it can no longer execute and the admin cancels it
+    #[account(
+        seeds = [PENDING_CHANGE, pool_config_account.key().as_ref(), &[OWNER_CHANGE_SLOT]],
+        bump,
+        constraint = pending_change.data_is_empty() @ AMMError::ChangePending,
+    )]
+    pub pending_change: UncheckedAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AcceptOwner<'info> {
+    pub new_owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        constraint = pool_config_account.pending_owner == Some(new_owner.key()) @ AMMError::NotPendingOwner,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+}
+
+impl<'info> TransferOwnership<'info> {
+    // None withdraws a pending proposal
+    pub fn propose_owner(&mut self, new_owner: Option<Pubkey>) -> Result<OwnershipTransferStarted> {
+        self.pool_config_account.pending_owner = new_owner;
+
+        Ok(OwnershipTransferStarted {
+            pool: self.pool_config_account.key(),
+            owner: self.owner.key(),
+
+            pending_owner: new_owner,
+        })
+    }
+}
+
+impl<'info> RenounceOwnership<'info> {
+    // one way: with no owner nothing can sign the owner-only instructions again
+    pub fn renounce_ownership(&mut self) -> Result<OwnershipTransferred> {
+        let pool = &mut self.pool_config_account;
+        // nobody could claim them afterwards, so the owed fees go first and no new ones accrue
+        require!(
+            pool.protocol_fees_a == 0 && pool.protocol_fees_b == 0,
+            AMMError::UnclaimedProtocolFees
+        );
+        pool.protocol_fee_bps = 0;
+
+        pool.owner = None;
+        pool.pending_owner = None;
+
+        Ok(OwnershipTransferred {
+            pool: self.pool_config_account.key(),
+            previous_owner: self.owner.key(),
+
+            new_owner: None,
+        })
+    }
+}
+
+impl<'info> AcceptOwner<'info> {
+    pub fn accept_owner(&mut self) -> Result<OwnershipTransferred> {
+        let pool = &mut self.pool_config_account;
+        let previous_owner = pool.owner.ok_or(AMMError::Unauthorized)?;
+        pool.owner = Some(self.new_owner.key());
+        pool.pending_owner = None;
+
+        Ok(OwnershipTransferred {
+            pool: pool.key(),
+            previous_owner,
+
+            new_owner: pool.owner,
+        })
+    }
+}
+
+// The ownership flow
+// - the owner proposes a new owner, nothing changes until that key signs accept_owner
+// - a typo in the proposal is fixed by proposing again, the pool is never left without a signer
+// - renounce_ownership needs the protocol fees claimed and no queued change, turns the protocol
+//   fee off and clears the owner for good: queue_change, stop_ramp_amp and the other owner-only
+//   instructions can never be called on the pool again
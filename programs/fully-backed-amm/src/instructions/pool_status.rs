@@ -0,0 +1,106 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constant::{CONCENTRATED_POOL, GLOBAL_CONFIG, POOL},
+    error::AMMError,
+    events::{PoolStatusChanged, ProtocolPauseChanged},
+    state::{ConcentratedPool, GlobalConfig, PoolConfig, PoolStatus},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetPoolStatus<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        constraint = pool_config_account.owner == Some(authority.key())
+            || global_config.guardian == Some(authority.key()) @ AMMError::Unauthorized,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetConcentratedPoolStatus<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [CONCENTRATED_POOL, pool.seed.to_le_bytes().as_ref()],
+        bump = pool.bump,
+        constraint = pool.owner == Some(authority.key())
+            || global_config.guardian == Some(authority.key()) @ AMMError::Unauthorized,
+    )]
+    pub pool: Box<Account<'info, ConcentratedPool>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetProtocolPause<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_CONFIG],
+        bump = global_config.bump,
+        constraint = global_config.admin == authority.key()
+            || global_config.guardian == Some(authority.key()) @ AMMError::Unauthorized,
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+}
+
+impl<'info> SetPoolStatus<'info> {
+    pub fn set_pool_status(&mut self, status: PoolStatus) -> Result<PoolStatusChanged> {
+        self.pool_config_account.status = status;
+
+        Ok(PoolStatusChanged {
+            pool: self.pool_config_account.key(),
+            authority: self.authority.key(),
+
+            status,
+        })
+    }
+}
+
+impl<'info> SetConcentratedPoolStatus<'info> {
+    pub fn set_pool_status(&mut self, status: PoolStatus) -> Result<PoolStatusChanged> {
+        self.pool.status = status;
+
+        Ok(PoolStatusChanged {
+            pool: self.pool.key(),
+            authority: self.authority.key(),
+
+            status,
+        })
+    }
+}
+
+impl<'info> SetProtocolPause<'info> {
+    pub fn set_protocol_pause(&mut self, paused: bool) -> Result<ProtocolPauseChanged> {
+        self.global_config.paused = paused;
+
+        Ok(ProtocolPauseChanged {
+            authority: self.authority.key(),
+
+            paused,
+        })
+    }
+}
+
+// The emergency flow
+// - the pool owner or the guardian moves a pool between Active, SwapsPaused, DepositsPaused and
+//   WithdrawOnly, the global admin or the guardian flips the kill switch for every pool at once
+// - swaps, deposits and withdrawals check the pool status and the kill switch before anything else,
+//   concentrated pools have the same status set through set_concentrated_pool_status
+// - withdrawals are never blocked, LPs can always take their share out
+// - owner configuration (queue_change, stop_ramp_amp, observations) stays open, it is how a
+//   paused pool gets fixed before it is set back to Active
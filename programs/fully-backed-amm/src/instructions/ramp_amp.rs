@@ -0,0 +1,37 @@
+use anchor_lang::prelude::*;
+
+use crate::{constant::POOL, error::AMMError, events::AmpRampStopped, state::PoolConfig};
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RampAmp<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+        constraint = pool_config_account.owner == Some(owner.key()) @ AMMError::Unauthorized,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+}
+
+impl<'info> RampAmp<'info> {
+    pub fn stop_ramp_amp(&mut self) -> Result<AmpRampStopped> {
+        let now = Clock::get()?.unix_timestamp;
+        self.pool_config_account.stop_ramp(now)?;
+
+        Ok(AmpRampStopped {
+            pool: self.pool_config_account.key(),
+            owner: self.owner.key(),
+
+            amp: self.pool_config_account.target_amp,
+        })
+    }
+}
+
+// The ramp flow
+// - only the pool owner can ramp, and only StableSwap pools have an amp
+// - a ramp is a ParameterChange::RampAmp, ramp_amp and queue_change put it behind the timelock
+// - A goes linearly from its current value to target_amp by end_timestamp, read from Clock on every swap
+// - stop_ramp_amp freezes A wherever the ramp currently is, it only stops a move so it is instant
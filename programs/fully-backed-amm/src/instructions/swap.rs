@@ -1,12 +1,25 @@
 use crate::error::AMMError;
-use anchor_lang::prelude::*;
+use anchor_lang::{
+    prelude::*,
+    solana_program::{
+        instruction::{AccountMeta, Instruction},
+        program::invoke,
+    },
+};
 use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
 
-use crate::{constant::POOL, helper::SwapToken, state::PoolConfig, swap_slippage_check};
+use crate::{
+    constant::{GLOBAL_CONFIG, OBSERVATIONS, POOL},
+    events::Swapped,
+    helper::{SwapExactOut, SwapToken},
+    state::{GlobalConfig, Observations, PoolAction, PoolConfig},
+    swap_max_input_check, swap_slippage_check,
+};
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct Swap<'info> {
     #[account(mut)]
@@ -29,6 +42,9 @@ pub struct Swap<'info> {
     )]
     pub user_token_b: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
     #[account(
         mut,
         seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
@@ -38,6 +54,13 @@ pub struct Swap<'info> {
     )]
     pub pool_config_account: Box<Account<'info, PoolConfig>>,
 
+    #[account(
+        mut,
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump = observations.bump,
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
     #[account(
         mut,
         associated_token::mint = mint_a,
@@ -52,27 +75,238 @@ pub struct Swap<'info> {
     )]
     pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
+    /// CHECK: Pyth price feed, must be the pool oracle and is parsed in check_oracle_price
+    #[account(
+        address = pool_config_account.oracle.unwrap_or_default() @ AMMError::OracleMismatch
+    )]
+    pub oracle: Option<UncheckedAccount<'info>>,
+
     pub system_program: Program<'info, System>,
     pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 impl<'info> Swap<'info> {
-    pub fn swap(&mut self, is_a: bool, amount: u64, min_slippage: u64) -> Result<()> {
+    pub fn swap(&mut self, is_a: bool, amount: u64, min_slippage: u64) -> Result<Swapped> {
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Swap)?;
+        self.pool_config_account.check_no_flash_loan()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+        let now = Clock::get()?.unix_timestamp;
+        let (price_reserve_a, price_reserve_b) = self
+            .pool_config_account
+            .price_reserves(reserve_a, reserve_b, now)?;
+        self.pool_config_account
+            .update_price_cumulative(price_reserve_a, price_reserve_b, now);
+        self.observations.write(now, price_reserve_a, price_reserve_b);
+        let protocol_fee =
+            SwapToken::protocol_fee(amount, self.pool_config_account.protocol_fee_bps)?;
+
         let send_amount = SwapToken::swap_token(SwapToken {
             is_a,
-            deposit_amount: amount,
-            total_amount_a: self.vault_a.amount,
-            total_amount_b: self.vault_b.amount,
+            deposit_amount: amount - protocol_fee,
+            total_amount_a: reserve_a,
+            total_amount_b: reserve_b,
+            fee_bps: self.pool_config_account.fee_bps,
+            curve: self.pool_config_account.current_curve(now),
         })?;
-        
 
         swap_slippage_check!(min_slippage, send_amount);
 
         self.deposit_tokens(is_a, amount)?;
         self.transfer_user(is_a, send_amount)?;
+        self.pool_config_account
+            .accrue_protocol_fee(is_a, protocol_fee)?;
 
-        Ok(())
+        let event = self.swapped_event(is_a, amount, send_amount, protocol_fee)?;
+        self.check_oracle_price(event.reserve_a, event.reserve_b, now)?;
+
+        Ok(event)
+    }
+
+    // is_a: true means the user pays token A and receives exactly amount_out of token B
+    pub fn swap_exact_out(
+        &mut self,
+        is_a: bool,
+        amount_out: u64,
+        max_amount_in: u64,
+    ) -> Result<Swapped> {
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Swap)?;
+        self.pool_config_account.check_no_flash_loan()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+        let now = Clock::get()?.unix_timestamp;
+        let (price_reserve_a, price_reserve_b) = self
+            .pool_config_account
+            .price_reserves(reserve_a, reserve_b, now)?;
+        self.pool_config_account
+            .update_price_cumulative(price_reserve_a, price_reserve_b, now);
+        self.observations.write(now, price_reserve_a, price_reserve_b);
+
+        let amount_in = SwapExactOut::calculate_amount_in(SwapExactOut {
+            is_a,
+            amount_out,
+            total_amount_a: reserve_a,
+            total_amount_b: reserve_b,
+            fee_bps: self.pool_config_account.fee_bps,
+            protocol_fee_bps: self.pool_config_account.protocol_fee_bps,
+            curve: self.pool_config_account.current_curve(now),
+        })?;
+
+        swap_max_input_check!(max_amount_in, amount_in);
+
+        let protocol_fee =
+            SwapToken::protocol_fee(amount_in, self.pool_config_account.protocol_fee_bps)?;
+
+        self.deposit_tokens(is_a, amount_in)?;
+        self.transfer_user(is_a, amount_out)?;
+        self.pool_config_account
+            .accrue_protocol_fee(is_a, protocol_fee)?;
+
+        let event = self.swapped_event(is_a, amount_in, amount_out, protocol_fee)?;
+        self.check_oracle_price(event.reserve_a, event.reserve_b, now)?;
+
+        Ok(event)
+    }
+
+    // Uniswap V2 style flash swap: amount_out is sent first, then the callback program (the first
+    // remaining account) is invoked with callback_data and the other remaining accounts. By the
+    // time it returns the input vault must hold enough to have bought amount_out with a regular swap
+    pub fn flash_swap(
+        &mut self,
+        remaining_accounts: &[AccountInfo<'info>],
+        is_a: bool,
+        amount_out: u64,
+        callback_data: Vec<u8>,
+    ) -> Result<Swapped> {
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Swap)?;
+        self.pool_config_account.check_no_flash_loan()?;
+        require!(amount_out > 0, AMMError::AmountZero);
+
+        let (callback_program, callback_accounts) = remaining_accounts
+            .split_first()
+            .ok_or(AMMError::InvalidCallback)?;
+        // a callback into this program would run on the pool state before this swap is saved
+        require!(
+            callback_program.executable && *callback_program.key != crate::ID,
+            AMMError::InvalidCallback
+        );
+
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+        let now = Clock::get()?.unix_timestamp;
+        let (price_reserve_a, price_reserve_b) = self
+            .pool_config_account
+            .price_reserves(reserve_a, reserve_b, now)?;
+        self.pool_config_account
+            .update_price_cumulative(price_reserve_a, price_reserve_b, now);
+        self.observations.write(now, price_reserve_a, price_reserve_b);
+
+        self.transfer_user(is_a, amount_out)?;
+
+        let callback = Instruction {
+            program_id: *callback_program.key,
+            accounts: callback_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: *account.key,
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data: callback_data,
+        };
+        invoke(&callback, callback_accounts)?;
+
+        // whatever the callback paid into the input vault is the input of the swap
+        self.vault_a.reload()?;
+        self.vault_b.reload()?;
+        let (reserve_in, reserve_in_after) = match is_a {
+            true => (reserve_a, self.vault_a.amount),
+            false => (reserve_b, self.vault_b.amount),
+        };
+        let amount_in = reserve_in_after
+            .checked_sub(self.protocol_fees(is_a))
+            .and_then(|reserve| reserve.checked_sub(reserve_in))
+            .filter(|amount_in| *amount_in > 0)
+            .ok_or(AMMError::FlashSwapNotRepaid)?;
+
+        // same as the constant product check with the fee included, for every curve
+        let protocol_fee =
+            SwapToken::protocol_fee(amount_in, self.pool_config_account.protocol_fee_bps)?;
+        let amount_out_paid_for = SwapToken::swap_token(SwapToken {
+            is_a,
+            deposit_amount: amount_in - protocol_fee,
+            total_amount_a: reserve_a,
+            total_amount_b: reserve_b,
+            fee_bps: self.pool_config_account.fee_bps,
+            curve: self.pool_config_account.current_curve(now),
+        })?;
+        require!(
+            amount_out_paid_for >= amount_out,
+            AMMError::FlashSwapNotRepaid
+        );
+
+        self.pool_config_account
+            .accrue_protocol_fee(is_a, protocol_fee)?;
+
+        let event = self.swapped_event(is_a, amount_in, amount_out, protocol_fee)?;
+        self.check_oracle_price(event.reserve_a, event.reserve_b, now)?;
+
+        Ok(event)
+    }
+
+    fn protocol_fees(&self, is_a: bool) -> u64 {
+        match is_a {
+            true => self.pool_config_account.protocol_fees_a,
+            false => self.pool_config_account.protocol_fees_b,
+        }
+    }
+
+    // rejects the swap when the post-trade pool price is too far away from the oracle price
+    fn check_oracle_price(&self, reserve_a: u64, reserve_b: u64, now: i64) -> Result<()> {
+        let oracle = self.oracle.as_ref().map(|oracle| oracle.to_account_info());
+        self.pool_config_account.check_oracle_price(
+            oracle.as_ref(),
+            self.mint_a.decimals,
+            self.mint_b.decimals,
+            reserve_a,
+            reserve_b,
+            now,
+        )
+    }
+
+    fn swapped_event(
+        &mut self,
+        is_a: bool,
+        amount_in: u64,
+        amount_out: u64,
+        protocol_fee: u64,
+    ) -> Result<Swapped> {
+        self.vault_a.reload()?;
+        self.vault_b.reload()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+
+        Ok(Swapped {
+            pool: self.pool_config_account.key(),
+            user: self.user.key(),
+
+            is_a,
+            amount_in,
+            amount_out,
+            protocol_fee,
+
+            reserve_a,
+            reserve_b,
+        })
     }
 
     fn deposit_tokens(&mut self, is_a: bool, amount: u64) -> Result<()> {
@@ -0,0 +1,275 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::{
+    constant::{CONCENTRATED_POOL, GLOBAL_CONFIG, MAX_TICK, MIN_TICK},
+    error::AMMError,
+    events::ConcentratedSwapped,
+    helper::concentrated::{full_mul_div, sqrt_price_at_tick, tick_at_sqrt_price, SwapStep, Q64},
+    state::{ConcentratedPool, GlobalConfig, PoolAction, TickArray},
+    swap_slippage_check,
+};
+
+// The tick arrays the price moves through are passed as writable remaining accounts
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SwapConcentrated<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = user
+    )]
+    pub user_token_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = user
+    )]
+    pub user_token_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [CONCENTRATED_POOL, pool.seed.to_le_bytes().as_ref()],
+        bump = pool.bump,
+        has_one = mint_a.key(),
+        has_one = mint_b.key(),
+    )]
+    pub pool: Box<Account<'info, ConcentratedPool>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool,
+    )]
+    pub vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool,
+    )]
+    pub vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+impl<'info> SwapConcentrated<'info> {
+    // is_a: true means the user pays token A, the price moves down
+    pub fn swap(
+        &mut self,
+        tick_array_accounts: &[AccountInfo],
+        is_a: bool,
+        amount: u64,
+        min_slippage: u64,
+    ) -> Result<ConcentratedSwapped> {
+        self.pool
+            .check_status(&self.global_config, PoolAction::Swap)?;
+        require!(amount > 0, AMMError::AmountZero);
+
+        let mut tick_arrays = self.load_tick_arrays(tick_array_accounts)?;
+        let (amount_in, amount_out) = self.swap_through_ticks(&mut tick_arrays, is_a, amount)?;
+
+        swap_slippage_check!(min_slippage, amount_out);
+
+        // only the input the price walk used is taken
+        self.deposit_tokens(is_a, amount_in)?;
+        self.transfer_user(is_a, amount_out)?;
+
+        for (info, tick_array) in tick_array_accounts.iter().zip(tick_arrays.iter()) {
+            tick_array.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        }
+
+        Ok(ConcentratedSwapped {
+            pool: self.pool.key(),
+            user: self.user.key(),
+
+            is_a,
+            amount_in,
+            amount_out,
+
+            sqrt_price_x64: self.pool.sqrt_price_x64,
+            tick_current: self.pool.tick_current,
+            liquidity: self.pool.liquidity,
+        })
+    }
+
+    // Uniswap V3 swap loop, one step per initialized tick or tick array edge
+    fn swap_through_ticks(
+        &mut self,
+        tick_arrays: &mut [TickArray],
+        is_a: bool,
+        amount: u64,
+    ) -> Result<(u64, u64)> {
+        let pool = &mut self.pool;
+        let tick_spacing = pool.tick_spacing;
+        let spacing = tick_spacing as i32;
+        let sqrt_price_limit = match is_a {
+            true => sqrt_price_at_tick(MIN_TICK)?,
+            false => sqrt_price_at_tick(MAX_TICK)?,
+        };
+
+        let mut amount_remaining = amount;
+        let mut amount_out: u64 = 0;
+
+        while amount_remaining > 0 && pool.sqrt_price_x64 != sqrt_price_limit {
+            // going up the search starts at the next initializable tick, which may be in the next array
+            let search_tick = match is_a {
+                true => pool.tick_current,
+                false => (pool.tick_current.div_euclid(spacing) + 1) * spacing,
+            };
+            let start_tick_index = TickArray::start_tick_index(search_tick, tick_spacing);
+            let tick_array = tick_arrays
+                .iter_mut()
+                .find(|tick_array| tick_array.start_tick_index == start_tick_index)
+                .ok_or(AMMError::TickArrayMissing)?;
+
+            let (tick_next, initialized) =
+                tick_array.next_initialized_tick(pool.tick_current, tick_spacing, is_a);
+            let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_target = sqrt_price_at_tick(tick_next)?;
+
+            let step = SwapStep::compute(
+                pool.sqrt_price_x64,
+                sqrt_price_target,
+                pool.liquidity,
+                amount_remaining,
+                pool.fee_bps,
+            )?;
+
+            amount_remaining = step
+                .amount_in
+                .checked_add(step.fee_amount)
+                .and_then(|spent| amount_remaining.checked_sub(spent))
+                .ok_or(AMMError::Overflow)?;
+            amount_out = amount_out
+                .checked_add(step.amount_out)
+                .ok_or(AMMError::Overflow)?;
+
+            // the fee is shared by the liquidity active during this step
+            if pool.liquidity > 0 {
+                let fee_growth = full_mul_div(step.fee_amount as u128, Q64, pool.liquidity, false)?;
+                match is_a {
+                    true => {
+                        pool.fee_growth_global_a_x64 =
+                            pool.fee_growth_global_a_x64.wrapping_add(fee_growth)
+                    }
+                    false => {
+                        pool.fee_growth_global_b_x64 =
+                            pool.fee_growth_global_b_x64.wrapping_add(fee_growth)
+                    }
+                }
+            }
+
+            pool.sqrt_price_x64 = step.sqrt_price_next;
+            if step.sqrt_price_next == sqrt_price_target {
+                if initialized {
+                    let liquidity_net = tick_array
+                        .tick_mut(tick_next, tick_spacing)?
+                        .cross(pool.fee_growth_global_a_x64, pool.fee_growth_global_b_x64);
+                    let liquidity_delta = match is_a {
+                        true => liquidity_net.checked_neg().ok_or(AMMError::Overflow)?,
+                        false => liquidity_net,
+                    };
+                    pool.liquidity = pool
+                        .liquidity
+                        .checked_add_signed(liquidity_delta)
+                        .ok_or(AMMError::Overflow)?;
+                }
+
+                // a price sitting on a tick belongs to the range above it
+                pool.tick_current = match is_a {
+                    true => tick_next - 1,
+                    false => tick_next,
+                };
+            } else {
+                pool.tick_current = tick_at_sqrt_price(step.sqrt_price_next)?;
+            }
+        }
+
+        Ok((amount - amount_remaining, amount_out))
+    }
+
+    fn load_tick_arrays(&self, tick_array_accounts: &[AccountInfo]) -> Result<Vec<TickArray>> {
+        let mut tick_arrays: Vec<TickArray> = Vec::with_capacity(tick_array_accounts.len());
+
+        for info in tick_array_accounts {
+            require_keys_eq!(*info.owner, crate::ID, AMMError::InvalidTickArray);
+            require!(info.is_writable, AMMError::InvalidTickArray);
+
+            let tick_array = TickArray::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            require_keys_eq!(tick_array.pool, self.pool.key(), AMMError::InvalidTickArray);
+
+            // the same array twice would be written back twice, the second copy stale
+            require!(
+                tick_arrays
+                    .iter()
+                    .all(|loaded| loaded.start_tick_index != tick_array.start_tick_index),
+                AMMError::InvalidTickArray
+            );
+
+            tick_arrays.push(tick_array);
+        }
+
+        Ok(tick_arrays)
+    }
+
+    fn deposit_tokens(&mut self, is_a: bool, amount: u64) -> Result<()> {
+        let (from, to, mint) = match is_a {
+            true => (&self.user_token_a, &self.vault_a, &self.mint_a),
+            false => (&self.user_token_b, &self.vault_b, &self.mint_b),
+        };
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.user.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), accounts);
+        transfer_checked(ctx, amount, mint.decimals)
+    }
+
+    fn transfer_user(&mut self, is_a: bool, amount: u64) -> Result<()> {
+        let (from, to, mint) = match is_a {
+            true => (&self.vault_b, &self.user_token_b, &self.mint_b),
+            false => (&self.vault_a, &self.user_token_a, &self.mint_a),
+        };
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.pool.to_account_info(),
+        };
+
+        let seed = self.pool.seed.to_le_bytes();
+        let seeds = &[CONCENTRATED_POOL, seed.as_ref(), &[self.pool.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            accounts,
+            signer_seeds,
+        );
+        transfer_checked(ctx, amount, mint.decimals)
+    }
+}
+
+// The concentrated swap flow
+// - walk the price from tick to tick until the input is used or the price hits the edge
+// - crossing an initialized tick adds or removes the liquidity of the positions starting there
+// - the user pays the input that was used and receives the output
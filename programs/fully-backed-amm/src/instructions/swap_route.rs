@@ -0,0 +1,305 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::{get_associated_token_address_with_program_id, AssociatedToken},
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::{
+    constant::{GLOBAL_CONFIG, MAX_ROUTE_HOPS, POOL, ROUTE_HOP_ACCOUNTS},
+    error::AMMError,
+    events::Swapped,
+    helper::SwapToken,
+    state::{GlobalConfig, Observations, PoolAction, PoolConfig},
+    swap_slippage_check,
+};
+
+// Every hop passes ROUTE_HOP_ACCOUNTS remaining accounts, in this order:
+// pool_config_account (mut), observations (mut), mint_a, mint_b, vault_a (mut), vault_b (mut),
+// oracle (the pool oracle, or any account when the pool has none)
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SwapRoute<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub mint_in: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_out: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_in,
+        associated_token::authority = user
+    )]
+    pub user_token_in: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_out,
+        associated_token::authority = user
+    )]
+    pub user_token_out: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+impl<'info> SwapRoute<'info> {
+    pub fn swap_route(
+        &mut self,
+        hop_accounts: &'info [AccountInfo<'info>],
+        amount: u64,
+        min_amount_out: u64,
+    ) -> Result<Vec<Swapped>> {
+        require!(amount > 0, AMMError::AmountZero);
+        let hop_chunks = hop_accounts.chunks_exact(ROUTE_HOP_ACCOUNTS);
+        require!(
+            hop_chunks.remainder().is_empty() && (1..=MAX_ROUTE_HOPS).contains(&hop_chunks.len()),
+            AMMError::InvalidRoute
+        );
+
+        let token_program = self.token_program.key();
+        let mut hops = hop_chunks
+            .map(|accounts| RouteHop::load(accounts, &token_program))
+            .collect::<Result<Vec<_>>>()?;
+
+        // a pool visited twice would be quoted on its old reserves the second time
+        for (index, hop) in hops.iter().enumerate() {
+            require!(
+                hops[..index]
+                    .iter()
+                    .all(|other| other.pool_config_account.key() != hop.pool_config_account.key()),
+                AMMError::InvalidRoute
+            );
+        }
+
+        // every quote is done on the pre-trade reserves, the output of a hop is the next input
+        let now = Clock::get()?.unix_timestamp;
+        let mut mint = self.mint_in.key();
+        let mut amount_out = amount;
+        for hop in hops.iter_mut() {
+            amount_out = hop.quote(&self.global_config, mint, amount_out, now)?;
+            mint = hop.mint_out().key();
+        }
+        require_keys_eq!(mint, self.mint_out.key(), AMMError::InvalidRoute);
+
+        swap_slippage_check!(min_amount_out, amount_out);
+
+        // intermediate tokens go straight from one pool vault into the next
+        self.deposit_tokens(&hops[0], amount)?;
+        for (index, hop) in hops.iter().enumerate() {
+            let to = match hops.get(index + 1) {
+                Some(next) => next.vault_in().to_account_info(),
+                None => self.user_token_out.to_account_info(),
+            };
+            hop.transfer_out(to, &self.token_program)?;
+        }
+
+        let user = self.user.key();
+        hops.iter_mut().map(|hop| hop.finish(user, now)).collect()
+    }
+
+    fn deposit_tokens(&self, first_hop: &RouteHop<'info>, amount: u64) -> Result<()> {
+        let accounts = TransferChecked {
+            from: self.user_token_in.to_account_info(),
+            mint: self.mint_in.to_account_info(),
+            to: first_hop.vault_in().to_account_info(),
+            authority: self.user.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), accounts);
+        transfer_checked(ctx, amount, self.mint_in.decimals)
+    }
+}
+
+struct RouteHop<'info> {
+    pool_config_account: Account<'info, PoolConfig>,
+    observations: Account<'info, Observations>,
+    mint_a: InterfaceAccount<'info, Mint>,
+    mint_b: InterfaceAccount<'info, Mint>,
+    vault_a: InterfaceAccount<'info, TokenAccount>,
+    vault_b: InterfaceAccount<'info, TokenAccount>,
+    oracle: &'info AccountInfo<'info>,
+
+    // filled in by quote
+    is_a: bool,
+    amount_in: u64,
+    amount_out: u64,
+    protocol_fee: u64,
+}
+
+impl<'info> RouteHop<'info> {
+    // the same checks the Swap accounts struct does through its constraints
+    fn load(accounts: &'info [AccountInfo<'info>], token_program: &Pubkey) -> Result<Self> {
+        let pool_config_account = Account::<PoolConfig>::try_from(&accounts[0])?;
+        let observations = Account::<Observations>::try_from(&accounts[1])?;
+        let mint_a = InterfaceAccount::<Mint>::try_from(&accounts[2])?;
+        let mint_b = InterfaceAccount::<Mint>::try_from(&accounts[3])?;
+        let vault_a = InterfaceAccount::<TokenAccount>::try_from(&accounts[4])?;
+        let vault_b = InterfaceAccount::<TokenAccount>::try_from(&accounts[5])?;
+
+        let pool = pool_config_account.key();
+        require!(
+            observations.pool == pool
+                && mint_a.key() == pool_config_account.mint_a
+                && mint_b.key() == pool_config_account.mint_b
+                && vault_a.key()
+                    == get_associated_token_address_with_program_id(
+                        &pool,
+                        &mint_a.key(),
+                        token_program
+                    )
+                && vault_b.key()
+                    == get_associated_token_address_with_program_id(
+                        &pool,
+                        &mint_b.key(),
+                        token_program
+                    ),
+            AMMError::InvalidRoute
+        );
+
+        Ok(RouteHop {
+            pool_config_account,
+            observations,
+            mint_a,
+            mint_b,
+            vault_a,
+            vault_b,
+            oracle: &accounts[6],
+
+            is_a: true,
+            amount_in: 0,
+            amount_out: 0,
+            protocol_fee: 0,
+        })
+    }
+
+    // same pricing as Swap::swap, without moving any tokens yet
+    fn quote(
+        &mut self,
+        global_config: &GlobalConfig,
+        mint_in: Pubkey,
+        amount_in: u64,
+        now: i64,
+    ) -> Result<u64> {
+        let pool = &mut self.pool_config_account;
+        pool.check_status(global_config, PoolAction::Swap)?;
+        pool.check_no_flash_loan()?;
+        self.is_a = match mint_in {
+            mint if mint == pool.mint_a => true,
+            mint if mint == pool.mint_b => false,
+            _ => return err!(AMMError::InvalidRoute),
+        };
+
+        let (reserve_a, reserve_b) = pool.reserves(self.vault_a.amount, self.vault_b.amount)?;
+        let (price_reserve_a, price_reserve_b) = pool.price_reserves(reserve_a, reserve_b, now)?;
+        pool.update_price_cumulative(price_reserve_a, price_reserve_b, now);
+        self.observations
+            .write(now, price_reserve_a, price_reserve_b);
+
+        let protocol_fee = SwapToken::protocol_fee(amount_in, pool.protocol_fee_bps)?;
+        let amount_out = SwapToken::swap_token(SwapToken {
+            is_a: self.is_a,
+            deposit_amount: amount_in - protocol_fee,
+            total_amount_a: reserve_a,
+            total_amount_b: reserve_b,
+            fee_bps: pool.fee_bps,
+            curve: pool.current_curve(now),
+        })?;
+        pool.accrue_protocol_fee(self.is_a, protocol_fee)?;
+
+        self.amount_in = amount_in;
+        self.amount_out = amount_out;
+        self.protocol_fee = protocol_fee;
+
+        Ok(amount_out)
+    }
+
+    fn vault_in(&self) -> &InterfaceAccount<'info, TokenAccount> {
+        match self.is_a {
+            true => &self.vault_a,
+            false => &self.vault_b,
+        }
+    }
+
+    fn mint_out(&self) -> &InterfaceAccount<'info, Mint> {
+        match self.is_a {
+            true => &self.mint_b,
+            false => &self.mint_a,
+        }
+    }
+
+    fn transfer_out(
+        &self,
+        to: AccountInfo<'info>,
+        token_program: &Interface<'info, TokenInterface>,
+    ) -> Result<()> {
+        let (from, mint) = match self.is_a {
+            true => (&self.vault_b, &self.mint_b),
+            false => (&self.vault_a, &self.mint_a),
+        };
+
+        let accounts = TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to,
+            authority: self.pool_config_account.to_account_info(),
+        };
+
+        let secret_seed = self.pool_config_account.seed.to_le_bytes();
+        let seeds = &[
+            POOL,
+            secret_seed.as_ref(),
+            &[self.pool_config_account.pool_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let ctx =
+            CpiContext::new_with_signer(token_program.to_account_info(), accounts, signer_seeds);
+        transfer_checked(ctx, self.amount_out, mint.decimals)
+    }
+
+    // once the tokens moved: oracle check on the new price, persist the pool and build the event
+    fn finish(&mut self, user: Pubkey, now: i64) -> Result<Swapped> {
+        self.vault_a.reload()?;
+        self.vault_b.reload()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+
+        self.pool_config_account.check_oracle_price(
+            Some(self.oracle),
+            self.mint_a.decimals,
+            self.mint_b.decimals,
+            reserve_a,
+            reserve_b,
+            now,
+        )?;
+
+        // loaded by hand, so nothing writes them back on exit
+        self.pool_config_account.exit(&crate::ID)?;
+        self.observations.exit(&crate::ID)?;
+
+        Ok(Swapped {
+            pool: self.pool_config_account.key(),
+            user,
+
+            is_a: self.is_a,
+            amount_in: self.amount_in,
+            amount_out: self.amount_out,
+            protocol_fee: self.protocol_fee,
+
+            reserve_a,
+            reserve_b,
+        })
+    }
+}
+
+// The route flow
+// - quote every hop in order on its pre-trade reserves, the output of one hop is the next input
+// - check the final output against min_amount_out once
+// - the user pays the first vault, each pool pays the next one and the last pays the user
+// - every hop is a regular swap for its pool: TWAP, observations, protocol fee, oracle and event
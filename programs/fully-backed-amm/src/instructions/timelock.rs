@@ -0,0 +1,256 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constant::{GLOBAL_CONFIG, OWNER_CHANGE_SLOT, PENDING_CHANGE, POOL},
+    error::AMMError,
+    events::{ChangeCancelled, ChangeExecuted, ChangeQueued},
+    state::{GlobalConfig, ParameterChange, PendingChange, PoolConfig},
+};
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(change: ParameterChange)]
+pub struct QueueChange<'info> {
+    // checked against the change in queue_change, see ParameterChange::authority
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingChange::INIT_SPACE,
+        seeds = [PENDING_CHANGE, pool_config_account.key().as_ref(), &[change.slot()]],
+        bump
+    )]
+    pub pending_change: Box<Account<'info, PendingChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// set_oracle and ramp_amp, the same accounts as QueueChange with the pool owner's slot
+#[event_cpi]
+#[derive(Accounts)]
+pub struct QueueOwnerChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingChange::INIT_SPACE,
+        seeds = [PENDING_CHANGE, pool_config_account.key().as_ref(), &[OWNER_CHANGE_SLOT]],
+        bump
+    )]
+    pub pending_change: Box<Account<'info, PendingChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// execute_change and cancel_change, both close the pending change back to the authority
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ResolveChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
+        bump = pool_config_account.pool_bump,
+    )]
+    pub pool_config_account: Box<Account<'info, PoolConfig>>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [
+            PENDING_CHANGE,
+            pool_config_account.key().as_ref(),
+            &[pending_change.change.slot()],
+        ],
+        bump = pending_change.bump,
+    )]
+    pub pending_change: Box<Account<'info, PendingChange>>,
+}
+
+// shared by queue_change, set_oracle and ramp_amp
+fn queue(
+    authority: &Signer,
+    global_config: &GlobalConfig,
+    pool_config_account: &Account<PoolConfig>,
+    pending_change: &mut Account<PendingChange>,
+    bump: u8,
+    change: ParameterChange,
+) -> Result<ChangeQueued> {
+    change.check_authority(authority.key(), pool_config_account, global_config)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let executable_at = now
+        .checked_add(global_config.timelock_delay)
+        .ok_or(AMMError::Overflow)?;
+
+    // checked on a copy now so a bad change fails here and not after the delay. A ramp is
+    // measured from the earliest execution, it has to still be valid when it can land
+    let mut preview = PoolConfig::clone(pool_config_account);
+    change.apply(&mut preview, executable_at)?;
+
+    pending_change.set_inner(PendingChange {
+        pool: pool_config_account.key(),
+        change,
+
+        queued_at: now,
+        executable_at,
+
+        bump,
+    });
+
+    Ok(ChangeQueued {
+        pool: pool_config_account.key(),
+        authority: authority.key(),
+
+        change,
+        executable_at,
+    })
+}
+
+impl<'info> QueueChange<'info> {
+    pub fn queue_change(
+        &mut self,
+        bumps: &QueueChangeBumps,
+        change: ParameterChange,
+    ) -> Result<ChangeQueued> {
+        queue(
+            &self.authority,
+            &self.global_config,
+            &self.pool_config_account,
+            &mut self.pending_change,
+            bumps.pending_change,
+            change,
+        )
+    }
+}
+
+// set_oracle and ramp_amp were instant before the timelock, they now only queue their change so
+// existing clients keep working with the same accounts
+impl<'info> QueueOwnerChange<'info> {
+    pub fn set_oracle(
+        &mut self,
+        bumps: &QueueOwnerChangeBumps,
+        oracle: Option<Pubkey>,
+        max_deviation_bps: u16,
+        max_confidence_bps: u16,
+        max_age: u64,
+    ) -> Result<ChangeQueued> {
+        let change = ParameterChange::Oracle {
+            oracle,
+            max_deviation_bps,
+            max_confidence_bps,
+            max_age,
+        };
+        self.queue_change(bumps, change)
+    }
+
+    pub fn ramp_amp(
+        &mut self,
+        bumps: &QueueOwnerChangeBumps,
+        target_amp: u64,
+        end_timestamp: i64,
+    ) -> Result<ChangeQueued> {
+        let change = ParameterChange::RampAmp {
+            target_amp,
+            end_timestamp,
+        };
+        self.queue_change(bumps, change)
+    }
+
+    fn queue_change(
+        &mut self,
+        bumps: &QueueOwnerChangeBumps,
+        change: ParameterChange,
+    ) -> Result<ChangeQueued> {
+        queue(
+            &self.authority,
+            &self.global_config,
+            &self.pool_config_account,
+            &mut self.pending_change,
+            bumps.pending_change,
+            change,
+        )
+    }
+}
+
+impl<'info> ResolveChange<'info> {
+    pub fn execute_change(&mut self) -> Result<ChangeExecuted> {
+        let now = Clock::get()?.unix_timestamp;
+        let change = self.pending_change.change;
+        change.check_authority(
+            self.authority.key(),
+            &self.pool_config_account,
+            &self.global_config,
+        )?;
+        require!(
+            now >= self.pending_change.executable_at,
+            AMMError::ChangeNotReady
+        );
+
+        // checked again, a ramp is measured from the execution time
+        change.apply(&mut self.pool_config_account, now)?;
+
+        Ok(ChangeExecuted {
+            pool: self.pool_config_account.key(),
+            authority: self.authority.key(),
+
+            change,
+        })
+    }
+
+    pub fn cancel_change(&mut self) -> Result<ChangeCancelled> {
+        let change = self.pending_change.change;
+        change.check_authority(
+            self.authority.key(),
+            &self.pool_config_account,
+            &self.global_config,
+        )?;
+
+        Ok(ChangeCancelled {
+            pool: self.pool_config_account.key(),
+            authority: self.authority.key(),
+
+            change,
+        })
+    }
+}
+
+// The timelock flow
+// - the global admin queues a protocol fee change, the pool owner an oracle or amp ramp change.
+//   It is checked against the pool right away and the ChangeQueued event tells users when it
+//   can land
+// - users who disagree withdraw through withdraw_asset during the global timelock_delay
+// - after the delay the same authority executes it, or cancels it at any time; both close the
+//   account
+// - one change per authority and pool at a time: the global admin and the pool owner queue in
+//   their own pending change slot, so neither holds up the other. A new one needs the pending
+//   one in the same slot executed or cancelled first
+// - set_oracle and ramp_amp keep their arguments but take the QueueChange accounts now, the
+//   change only lands with execute_change after the delay
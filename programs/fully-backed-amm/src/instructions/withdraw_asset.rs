@@ -3,8 +3,16 @@ use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{burn, transfer_checked, Burn, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
-use crate::{constant::{MINT_LP, POOL}, helper::WithdrawAsset, state::PoolConfig};
+use crate::{
+    constant::{GLOBAL_CONFIG, MINT_LP, OBSERVATIONS, POOL},
+    error::AMMError,
+    events::LiquidityRemoved,
+    helper::WithdrawAsset,
+    state::{GlobalConfig, Observations, PoolAction, PoolConfig},
+    withdraw_slippage_check,
+};
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     #[account(mut)]
@@ -26,6 +34,9 @@ pub struct Withdraw<'info> {
     )]
     pub user_token_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
+    #[account(seeds = [GLOBAL_CONFIG], bump = global_config.bump)]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
     #[account(
         mut,
         seeds = [POOL, pool_config_account.seed.to_le_bytes().as_ref()],
@@ -35,6 +46,13 @@ pub struct Withdraw<'info> {
     )]
     pub pool_config_account: Box<Account<'info, PoolConfig>>,
 
+    #[account(
+        mut,
+        seeds = [OBSERVATIONS, pool_config_account.key().as_ref()],
+        bump = observations.bump,
+    )]
+    pub observations: Box<Account<'info, Observations>>,
+
     #[account(
         mut,
         associated_token::mint = mint_a,
@@ -70,24 +88,63 @@ pub struct Withdraw<'info> {
 }
 
 impl<'info> Withdraw<'info> {
-    pub fn withdraw(&mut self, lp_amount: u64) -> Result<()> {
+    pub fn withdraw(
+        &mut self,
+        lp_amount: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<LiquidityRemoved> {
+        // open in every status, this is the way out during an emergency
+        self.pool_config_account
+            .check_status(&self.global_config, PoolAction::Withdraw)?;
+        self.pool_config_account.check_no_flash_loan()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+        let now = Clock::get()?.unix_timestamp;
+        let (price_reserve_a, price_reserve_b) = self
+            .pool_config_account
+            .price_reserves(reserve_a, reserve_b, now)?;
+        self.pool_config_account
+            .update_price_cumulative(price_reserve_a, price_reserve_b, now);
+        self.observations.write(now, price_reserve_a, price_reserve_b);
+
         let amount_a = WithdrawAsset::calculate_token(WithdrawAsset {
             mint_supply: self.mint_lp.supply,
             lp_share_amount: lp_amount,
-            total_amount_vault: self.vault_a.amount,
+            total_amount_vault: reserve_a,
         })?;
 
         let amount_b = WithdrawAsset::calculate_token(WithdrawAsset {
             mint_supply: self.mint_lp.supply,
             lp_share_amount: lp_amount,
-            total_amount_vault: self.vault_b.amount,
+            total_amount_vault: reserve_b,
         })?;
 
+        withdraw_slippage_check!(min_amount_a, amount_a);
+        withdraw_slippage_check!(min_amount_b, amount_b);
+
         self.transfer_token(true, amount_a)?;
         self.transfer_token(false, amount_b)?;
         self.burn_token(lp_amount)?;
 
-        Ok(())
+        self.vault_a.reload()?;
+        self.vault_b.reload()?;
+        let (reserve_a, reserve_b) = self
+            .pool_config_account
+            .reserves(self.vault_a.amount, self.vault_b.amount)?;
+
+        Ok(LiquidityRemoved {
+            pool: self.pool_config_account.key(),
+            user: self.user.key(),
+
+            amount_a,
+            amount_b,
+            lp_burned: lp_amount,
+
+            reserve_a,
+            reserve_b,
+        })
     }
 
     fn transfer_token(&mut self, is_a: bool, amount: u64) -> Result<()> {
@@ -151,6 +208,8 @@ impl<'info> Withdraw<'info> {
 }
 
 // The withdraw flow
-// - calculate the withdraw amount based on the lp token given from user
+// - calculate the withdraw amount based on the lp token given from user (pro rata, for every curve)
+// - check the amounts against the user's minimums
 // - transfer from the pool to user wallet
 // - burn the lp tokens
+// - emit the LiquidityRemoved event with the post-withdraw reserves
@@ -4,40 +4,361 @@ declare_id!("AYSuzxZZj6aPpSMwHAUTGoKzG3TdroXG2TvvYyfkE2Qx");
 
 pub mod constant;
 pub mod error;
+pub mod events;
 pub mod helper;
 pub mod instructions;
 pub mod state;
 
 use instructions::*;
+use state::{CurveType, Observation, ParameterChange, PoolStatus};
 
 #[program]
 pub mod fully_backed_amm {
     use super::*;
 
-    pub fn initialize(ctx: Context<InitializePool>, seed: u64) -> Result<()> {
-        ctx.accounts.init_pool(ctx.bumps, seed)?;
+    pub fn initialize_global_config(
+        ctx: Context<InitializeGlobalConfig>,
+        params: GlobalConfigParams,
+    ) -> Result<()> {
+        ctx.accounts.init_global_config(&ctx.bumps, params)?;
         Ok(())
     }
 
-    pub fn deposite_asset(ctx: Context<DepositAsset>, amount_a: u64, amount_b: u64) -> Result<()> {
-        ctx.accounts.deposite(amount_a, amount_b)?;
+    pub fn update_global_config(
+        ctx: Context<UpdateGlobalConfig>,
+        admin: Option<Pubkey>,
+        params: GlobalConfigParams,
+    ) -> Result<()> {
+        ctx.accounts.update_global_config(admin, params)?;
+        Ok(())
+    }
+
+    pub fn initialize(
+        ctx: Context<InitializePool>,
+        seed: u64,
+        fee_bps: u16,
+        protocol_fee_bps: Option<u16>,
+        curve: CurveType,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .init_pool(&ctx.bumps, seed, fee_bps, protocol_fee_bps, curve)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn deposite_asset(
+        ctx: Context<DepositAsset>,
+        max_amount_a: u64,
+        max_amount_b: u64,
+        min_lp_out: u64,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .deposite(max_amount_a, max_amount_b, min_lp_out)?;
+        emit_cpi!(event);
         Ok(())
     }
 
     pub fn swap(ctx: Context<Swap>, is_a: bool, amount: u64, min_slippage: u64) -> Result<()> {
-        ctx.accounts.swap(is_a, amount, min_slippage)?;
+        let event = ctx.accounts.swap(is_a, amount, min_slippage)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn swap_exact_out(
+        ctx: Context<Swap>,
+        is_a: bool,
+        amount_out: u64,
+        max_amount_in: u64,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .swap_exact_out(is_a, amount_out, max_amount_in)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn flash_swap<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Swap<'info>>,
+        is_a: bool,
+        amount_out: u64,
+        callback_data: Vec<u8>,
+    ) -> Result<()> {
+        let event =
+            ctx.accounts
+                .flash_swap(ctx.remaining_accounts, is_a, amount_out, callback_data)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn swap_route<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapRoute<'info>>,
+        amount: u64,
+        min_amount_out: u64,
+    ) -> Result<()> {
+        let events = ctx
+            .accounts
+            .swap_route(ctx.remaining_accounts, amount, min_amount_out)?;
+        for event in events {
+            emit_cpi!(event);
+        }
+        Ok(())
+    }
+
+    pub fn withdraw_asset(
+        ctx: Context<Withdraw>,
+        lp_amount: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .withdraw(lp_amount, min_amount_a, min_amount_b)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn flash_borrow(ctx: Context<FlashLoan>, amount_a: u64, amount_b: u64) -> Result<()> {
+        let event = ctx.accounts.flash_borrow(amount_a, amount_b)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn flash_repay(ctx: Context<FlashLoan>) -> Result<()> {
+        let event = ctx.accounts.flash_repay()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn propose_owner(ctx: Context<TransferOwnership>, new_owner: Option<Pubkey>) -> Result<()> {
+        let event = ctx.accounts.propose_owner(new_owner)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn accept_owner(ctx: Context<AcceptOwner>) -> Result<()> {
+        let event = ctx.accounts.accept_owner()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn renounce_ownership(ctx: Context<RenounceOwnership>) -> Result<()> {
+        let event = ctx.accounts.renounce_ownership()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn set_pool_status(ctx: Context<SetPoolStatus>, status: PoolStatus) -> Result<()> {
+        let event = ctx.accounts.set_pool_status(status)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn set_concentrated_pool_status(
+        ctx: Context<SetConcentratedPoolStatus>,
+        status: PoolStatus,
+    ) -> Result<()> {
+        let event = ctx.accounts.set_pool_status(status)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn set_protocol_pause(ctx: Context<SetProtocolPause>, paused: bool) -> Result<()> {
+        let event = ctx.accounts.set_protocol_pause(paused)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn claim_protocol_fees(ctx: Context<ClaimProtocolFees>) -> Result<()> {
+        let event = ctx.accounts.claim()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn close_pool(ctx: Context<ClosePool>) -> Result<()> {
+        let event = ctx.accounts.close_pool()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+        let event = ctx.accounts.migrate_pool(&ctx.bumps)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn increase_observation_cardinality(
+        ctx: Context<IncreaseObservationCardinality>,
+        cardinality_next: u16,
+    ) -> Result<()> {
+        let event = ctx.accounts.increase_cardinality(cardinality_next)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn queue_change(ctx: Context<QueueChange>, change: ParameterChange) -> Result<()> {
+        let event = ctx.accounts.queue_change(&ctx.bumps, change)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn execute_change(ctx: Context<ResolveChange>) -> Result<()> {
+        let event = ctx.accounts.execute_change()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn cancel_change(ctx: Context<ResolveChange>) -> Result<()> {
+        let event = ctx.accounts.cancel_change()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn set_oracle(
+        ctx: Context<QueueOwnerChange>,
+        oracle: Option<Pubkey>,
+        max_deviation_bps: u16,
+        max_confidence_bps: u16,
+        max_age: u64,
+    ) -> Result<()> {
+        let event = ctx.accounts.set_oracle(
+            &ctx.bumps,
+            oracle,
+            max_deviation_bps,
+            max_confidence_bps,
+            max_age,
+        )?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn ramp_amp(ctx: Context<QueueOwnerChange>, target_amp: u64, end_timestamp: i64) -> Result<()> {
+        let event = ctx
+            .accounts
+            .ramp_amp(&ctx.bumps, target_amp, end_timestamp)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn stop_ramp_amp(ctx: Context<RampAmp>) -> Result<()> {
+        let event = ctx.accounts.stop_ramp_amp()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn observe(ctx: Context<Observe>, seconds_ago: Vec<u32>) -> Result<Vec<Observation>> {
+        ctx.accounts.observe(seconds_ago)
+    }
+
+    pub fn initialize_concentrated_pool(
+        ctx: Context<InitializeConcentratedPool>,
+        seed: u64,
+        tick_spacing: u16,
+        fee_bps: u16,
+        sqrt_price_x64: u128,
+    ) -> Result<()> {
+        let event =
+            ctx.accounts
+                .init_pool(&ctx.bumps, seed, tick_spacing, fee_bps, sqrt_price_x64)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn initialize_tick_array(
+        ctx: Context<InitializeTickArray>,
+        start_tick_index: i32,
+    ) -> Result<()> {
+        let event = ctx.accounts.init_tick_array(start_tick_index)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn open_position(
+        ctx: Context<OpenPosition>,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .open_position(&ctx.bumps, tick_lower, tick_upper)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn increase_liquidity(
+        ctx: Context<ModifyLiquidity>,
+        liquidity: u128,
+        max_amount_a: u64,
+        max_amount_b: u64,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .increase_liquidity(liquidity, max_amount_a, max_amount_b)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn decrease_liquidity(
+        ctx: Context<ModifyLiquidity>,
+        liquidity: u128,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .decrease_liquidity(liquidity, min_amount_a, min_amount_b)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn collect_fees(ctx: Context<ModifyLiquidity>) -> Result<()> {
+        let event = ctx.accounts.collect_fees()?;
+        emit_cpi!(event);
         Ok(())
     }
 
-    pub fn withdraw_asset(ctx: Context<Withdraw>, lp_amount: u64) -> Result<()> {
-        ctx.accounts.withdraw(lp_amount)?;
+    pub fn swap_concentrated(
+        ctx: Context<SwapConcentrated>,
+        is_a: bool,
+        amount: u64,
+        min_slippage: u64,
+    ) -> Result<()> {
+        let event = ctx
+            .accounts
+            .swap(ctx.remaining_accounts, is_a, amount, min_slippage)?;
+        emit_cpi!(event);
         Ok(())
     }
 }
 
 // ++++++++++++++ AMM Workflow ++++++++++++++
-// - Initialize the AMM Pool
+// - The upgrade authority creates the GlobalConfig once: fee tiers, creation fee, permissionless
+//   switch and the default protocol fee every initialize is checked against
+// - Initialize the AMM Pool, registered under its (mint_min, mint_max, fee_bps, curve)
+//   pair address
 // - Deposite assets into the AMM Pool(For first time calculate Liquidity k = Sqaure Root of XY)
-// - Swap tokens
+// - Swap tokens, or route through several pools with swap_route and a single min_amount_out
 // - Withdraw assets from the AMM Pool.
-// - There is no fees in swapping in this program
+// - Flash swaps send the output first and call back the caller program, which pays the input
+// - Flash loans: flash_borrow and a later flash_repay in the same transaction, the fee goes to the LPs
+// - Owner claims the protocol fees (protocol_fee_bps) to the global fee recipient
+// - Ownership moves in two steps (propose_owner, accept_owner), or is renounced for good
+// - A drained pool is closed by its owner, the vaults, config, observations and pair entry give
+//   their rent back
+// - Pools written by 0.1.0 are upgraded in place with migrate_pool, PoolConfig carries a version
+//   byte and reserved bytes so later fields fit without another realloc
+// - Owner or guardian pauses swaps or deposits on a pool, or makes it withdraw-only; the guardian
+//   or the global admin can also stop every pool at once, withdrawals always stay open
+// - Every state change writes an observation into the ring buffer, read back with observe
+// - Swaps are rejected when the pool moves too far from the optional Pyth oracle price
+// - Every state change emits an event through emit_cpi! for the indexers
+// - Every swap pays a fee (fee_bps) on the input amount, which stays in the vaults for the LPs
+// - Pools pick their curve at initialize: constant product, StableSwap with an amp coefficient,
+//   or weighted (Balancer style) with the token weights stored in the config
+// - Owner can ramp the StableSwap amp linearly over time, or stop the ramp where it is
+// - Protocol fee changes (by the global admin), oracle and amp ramp changes (by the owner) are
+//   queued behind the global timelock_delay, the ChangeQueued event gives users the time to
+//   withdraw before execute_change. set_oracle and ramp_amp queue their change the same way
+// - Concentrated pools are separate: positions add liquidity between two ticks, swaps cross the
+//   ticks and the fees accrue per position instead of through mint_lp
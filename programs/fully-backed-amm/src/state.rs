@@ -1,8 +1,26 @@
-use anchor_lang::prelude::*;
+use anchor_lang::{prelude::*, Discriminator};
+use pyth_sdk_solana::state::SolanaPriceAccount;
+
+use crate::{
+    constant::{
+        ADMIN_CHANGE_SLOT, FEE_DENOMINATOR, MAX_AMP, MAX_AMP_CHANGE, MAX_FEE_BPS, MAX_FEE_TIERS,
+        MAX_RAMP_DURATION, MIN_AMP, MIN_RAMP_DURATION, OWNER_CHANGE_SLOT, POOL_CONFIG_RESERVED,
+        POOL_CONFIG_VERSION, TICK_ARRAY_SIZE,
+    },
+    error::AMMError,
+    helper::{
+        concentrated::full_mul_div,
+        integer_sqrt,
+        stable_swap::{self, AmpRamp},
+        OracleGuard, PriceCumulative,
+    },
+};
 
 #[account]
 #[derive(InitSpace)]
 pub struct PoolConfig {
+    pub version: u8, // layout version, first so it sits at a fixed offset after the discriminator
+
     pub owner: Option<Pubkey>,
 
     pub mint_a: Pubkey,
@@ -14,4 +32,1242 @@ pub struct PoolConfig {
     pub seed: u64,
     pub pool_mint_bump: u8,
     pub pool_bump: u8,
+
+    pub fee_bps: u16, // swap fee charged on the input amount, stays in the vault for LPs
+    pub protocol_fee_bps: u16, // protocol fee charged on the input amount, owed to the treasury
+
+    // accrued but unclaimed protocol fees, still sitting in the vaults
+    pub protocol_fees_a: u64,
+    pub protocol_fees_b: u64,
+
+    pub curve: CurveType,
+
+    // StableSwap amp ramp, A moves linearly from the curve amp to target_amp between the timestamps
+    pub target_amp: u64,
+    pub ramp_start_timestamp: i64,
+    pub ramp_stop_timestamp: i64,
+
+    // TWAP accumulators, I64F64 bits of the price times seconds (price_a is A in terms of B)
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+    pub last_update_timestamp: i64,
+
+    // optional Pyth feed (price of A in B) used as a circuit breaker on swaps
+    pub oracle: Option<Pubkey>,
+    pub oracle_max_deviation_bps: u16,
+    pub oracle_max_confidence_bps: u16,
+    pub oracle_max_age: u64, // seconds
+
+    // outstanding flash loan principal, zero outside of a flash_borrow/flash_repay transaction
+    pub flash_loan_a: u64,
+    pub flash_loan_b: u64,
+
+    pub status: PoolStatus, // set by the owner or the global guardian
+
+    // proposed by the owner, becomes the owner once it signs accept_owner
+    pub pending_owner: Option<Pubkey>,
+
+    // lp tokens locked in the locked_lp account, MINIMUM_LIQUIDITY from the first deposit on.
+    // Zero for pools migrated from 0.1.0 with LPs already in, they never locked any
+    pub locked_liquidity: u64,
+    // false for pools migrated from 0.1.0, which allowed several pools per pair and so have no
+    // PoolPair entry
+    pub registered: bool,
+
+    // room for the next fields, so they don't need a realloc of every pool
+    pub reserved: [u8; POOL_CONFIG_RESERVED],
+}
+
+// PoolConfig as 0.1.0 wrote it, before the version byte. Only read by migrate_pool
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PoolConfigV0 {
+    pub owner: Option<Pubkey>,
+
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+
+    pub vault_a_bump: u8,
+    pub vault_b_bump: u8,
+
+    pub seed: u64,
+    pub pool_mint_bump: u8,
+    pub pool_bump: u8,
+}
+
+impl PoolConfigV0 {
+    pub const LEN: usize = 8 + 33 + 32 + 32 + 1 + 1 + 8 + 1 + 1;
+
+    // same discriminator as PoolConfig, the account size is what tells the layouts apart
+    pub fn try_from_account_data(data: &[u8]) -> Result<Self> {
+        require!(
+            data.len() == Self::LEN && data[..8] == PoolConfig::DISCRIMINATOR,
+            AMMError::InvalidPoolVersion
+        );
+        Ok(Self::deserialize(&mut &data[8..])?)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum CurveType {
+    ConstantProduct, // x * y = k
+    StableSwap { amp: u64 }, // Curve invariant, amp is the amplification coefficient A
+    Weighted { weight_a: u16, weight_b: u16 }, // Balancer invariant, weights in bps summing to 10_000
+}
+
+impl CurveType {
+    // one byte per variant, the pool pair seeds use it so the amp or the weights don't matter
+    pub fn kind(&self) -> u8 {
+        match self {
+            CurveType::ConstantProduct => 0,
+            CurveType::StableSwap { .. } => 1,
+            CurveType::Weighted { .. } => 2,
+        }
+    }
+}
+
+// Emergency modes, from least to most restrictive. Withdrawals stay open in every state
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum PoolStatus {
+    Active,
+    SwapsPaused,    // swaps, flash swaps and flash loans are rejected
+    DepositsPaused, // deposits are rejected
+    WithdrawOnly,   // only withdrawals and the protocol fee claim go through
+}
+
+// What an instruction does to a pool, checked against its status and the global kill switch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolAction {
+    Swap,
+    Deposit,
+    Withdraw,
+    Close, // only an Active pool is closed, never in the middle of an emergency
+}
+
+impl PoolStatus {
+    pub fn check(self, action: PoolAction) -> Result<()> {
+        match (self, action) {
+            (_, PoolAction::Withdraw) | (PoolStatus::Active, _) => Ok(()),
+            (_, PoolAction::Close) => err!(AMMError::PoolNotActive),
+            (PoolStatus::WithdrawOnly, _) => err!(AMMError::WithdrawOnly),
+            (PoolStatus::SwapsPaused, PoolAction::Swap) => err!(AMMError::SwapsPaused),
+            (PoolStatus::DepositsPaused, PoolAction::Deposit) => err!(AMMError::DepositsPaused),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PoolConfig {
+    // 0.1.0 pools had no fees, no curve choice and no oracle, so they come out as a fee-less
+    // constant product pool with every newer field at its default
+    pub fn from_v0(legacy: PoolConfigV0, now: i64) -> Self {
+        Self {
+            version: POOL_CONFIG_VERSION,
+
+            owner: legacy.owner,
+
+            mint_a: legacy.mint_a,
+            mint_b: legacy.mint_b,
+
+            vault_a_bump: legacy.vault_a_bump,
+            vault_b_bump: legacy.vault_b_bump,
+
+            seed: legacy.seed,
+            pool_mint_bump: legacy.pool_mint_bump,
+            pool_bump: legacy.pool_bump,
+
+            fee_bps: 0,
+            protocol_fee_bps: 0,
+
+            protocol_fees_a: 0,
+            protocol_fees_b: 0,
+
+            curve: CurveType::ConstantProduct,
+            target_amp: 0,
+            ramp_start_timestamp: now,
+            ramp_stop_timestamp: now,
+
+            price_a_cumulative: 0,
+            price_b_cumulative: 0,
+            last_update_timestamp: now,
+
+            oracle: None,
+            oracle_max_deviation_bps: 0,
+            oracle_max_confidence_bps: 0,
+            oracle_max_age: 0,
+
+            flash_loan_a: 0,
+            flash_loan_b: 0,
+
+            status: PoolStatus::Active,
+            pending_owner: None,
+
+            locked_liquidity: 0,
+            registered: false,
+
+            reserved: [0; POOL_CONFIG_RESERVED],
+        }
+    }
+
+    pub fn check_status(&self, global_config: &GlobalConfig, action: PoolAction) -> Result<()> {
+        global_config.check_not_paused(action)?;
+        self.status.check(action)
+    }
+
+    // vault balances minus the protocol fees owed, this is what belongs to the LPs
+    pub fn reserves(&self, vault_a_amount: u64, vault_b_amount: u64) -> Result<(u64, u64)> {
+        let reserve_a = vault_a_amount
+            .checked_sub(self.protocol_fees_a)
+            .ok_or(AMMError::Overflow)?;
+        let reserve_b = vault_b_amount
+            .checked_sub(self.protocol_fees_b)
+            .ok_or(AMMError::Overflow)?;
+
+        Ok((reserve_a, reserve_b))
+    }
+
+    // curve to quote with at `now`, with the StableSwap amp interpolated along the ramp
+    pub fn current_curve(&self, now: i64) -> CurveType {
+        match self.curve {
+            CurveType::StableSwap { amp } => CurveType::StableSwap {
+                amp: AmpRamp::current_amp(AmpRamp {
+                    initial_amp: amp,
+                    target_amp: self.target_amp,
+                    start_timestamp: self.ramp_start_timestamp,
+                    stop_timestamp: self.ramp_stop_timestamp,
+                    now,
+                }),
+            },
+            curve => curve,
+        }
+    }
+
+    // reserves whose ratio is the spot price, a weighted pool prices (B_b / w_b) / (B_a / w_a)
+    // scaled by the larger weight so a 50/50 pool gets its reserves back unchanged. A stable pool
+    // is priced by the slope of its invariant at the amp of `now`
+    pub fn price_reserves(&self, reserve_a: u64, reserve_b: u64, now: i64) -> Result<(u64, u64)> {
+        match self.current_curve(now) {
+            CurveType::Weighted { weight_a, weight_b } => {
+                let max_weight = weight_a.max(weight_b) as u128;
+                Ok((
+                    (reserve_a as u128 * weight_b as u128 / max_weight) as u64,
+                    (reserve_b as u128 * weight_a as u128 / max_weight) as u64,
+                ))
+            }
+            CurveType::StableSwap { amp } => stable_swap::price_reserves(amp, reserve_a, reserve_b),
+            CurveType::ConstantProduct => Ok((reserve_a, reserve_b)),
+        }
+    }
+
+    // the vaults are short while a flash loan is out, nothing may price off them until it is repaid
+    pub fn check_no_flash_loan(&self) -> Result<()> {
+        require!(
+            self.flash_loan_a == 0 && self.flash_loan_b == 0,
+            AMMError::FlashLoanOutstanding
+        );
+        Ok(())
+    }
+
+    // the protocol fee stays in the input vault until it is claimed
+    pub fn accrue_protocol_fee(&mut self, is_a: bool, protocol_fee: u64) -> Result<()> {
+        let owed = if is_a {
+            &mut self.protocol_fees_a
+        } else {
+            &mut self.protocol_fees_b
+        };
+        *owed = owed.checked_add(protocol_fee).ok_or(AMMError::Overflow)?;
+
+        Ok(())
+    }
+
+    // must run before the reserves change, so the elapsed time is priced at the old reserves
+    pub fn update_price_cumulative(&mut self, reserve_a: u64, reserve_b: u64, now: i64) {
+        let time_elapsed = now.saturating_sub(self.last_update_timestamp).max(0) as u64;
+
+        (self.price_a_cumulative, self.price_b_cumulative) =
+            PriceCumulative::accumulate(PriceCumulative {
+                price_a_cumulative: self.price_a_cumulative,
+                price_b_cumulative: self.price_b_cumulative,
+                reserve_a,
+                reserve_b,
+                time_elapsed,
+            });
+        self.last_update_timestamp = now;
+    }
+
+    pub fn set_protocol_fee(&mut self, protocol_fee_bps: u16) -> Result<()> {
+        // renounce_ownership turned it off for good
+        require!(self.owner.is_some(), AMMError::Unauthorized);
+        require!(
+            self.fee_bps as u64 + protocol_fee_bps as u64 <= MAX_FEE_BPS as u64,
+            AMMError::InvalidFee
+        );
+        self.protocol_fee_bps = protocol_fee_bps;
+        Ok(())
+    }
+
+    // passing None as the oracle turns the circuit breaker off
+    pub fn set_oracle(
+        &mut self,
+        oracle: Option<Pubkey>,
+        max_deviation_bps: u16,
+        max_confidence_bps: u16,
+        max_age: u64,
+    ) -> Result<()> {
+        require!(
+            max_deviation_bps as u64 <= FEE_DENOMINATOR
+                && max_confidence_bps as u64 <= FEE_DENOMINATOR,
+            AMMError::InvalidFee
+        );
+
+        self.oracle = oracle;
+        self.oracle_max_deviation_bps = max_deviation_bps;
+        self.oracle_max_confidence_bps = max_confidence_bps;
+        self.oracle_max_age = max_age;
+        Ok(())
+    }
+
+    pub fn current_amp(&self, now: i64) -> Result<u64> {
+        match self.current_curve(now) {
+            CurveType::StableSwap { amp } => Ok(amp),
+            _ => err!(AMMError::NotStableSwap),
+        }
+    }
+
+    // A goes linearly from its current value to target_amp by end_timestamp
+    pub fn start_ramp(&mut self, target_amp: u64, end_timestamp: i64, now: i64) -> Result<()> {
+        let current_amp = self.current_amp(now)?;
+
+        require!(
+            (MIN_AMP..=MAX_AMP).contains(&target_amp),
+            AMMError::InvalidAmp
+        );
+
+        let duration = end_timestamp.saturating_sub(now);
+        require!(
+            (MIN_RAMP_DURATION..=MAX_RAMP_DURATION).contains(&duration),
+            AMMError::InvalidRamp
+        );
+        require!(
+            target_amp <= current_amp.saturating_mul(MAX_AMP_CHANGE)
+                && target_amp.saturating_mul(MAX_AMP_CHANGE) >= current_amp,
+            AMMError::InvalidRamp
+        );
+
+        // the new ramp starts from wherever the previous one had got to
+        self.set_ramp(current_amp, target_amp, now, end_timestamp);
+        Ok(())
+    }
+
+    // freezes A at its current value
+    pub fn stop_ramp(&mut self, now: i64) -> Result<()> {
+        let current_amp = self.current_amp(now)?;
+        self.set_ramp(current_amp, current_amp, now, now);
+        Ok(())
+    }
+
+    fn set_ramp(&mut self, initial_amp: u64, target_amp: u64, start: i64, stop: i64) {
+        self.curve = CurveType::StableSwap { amp: initial_amp };
+        self.target_amp = target_amp;
+        self.ramp_start_timestamp = start;
+        self.ramp_stop_timestamp = stop;
+    }
+
+    // rejects the post-trade pool price when it is too far away from the oracle price,
+    // a pool without an oracle always passes
+    pub fn check_oracle_price(
+        &self,
+        oracle: Option<&AccountInfo>,
+        decimals_a: u8,
+        decimals_b: u8,
+        reserve_a: u64,
+        reserve_b: u64,
+        now: i64,
+    ) -> Result<()> {
+        let Some(oracle_key) = self.oracle else {
+            return Ok(());
+        };
+
+        let oracle = oracle
+            .filter(|oracle| *oracle.key == oracle_key)
+            .ok_or(AMMError::OracleMismatch)?;
+        let price_feed = SolanaPriceAccount::account_info_to_feed(oracle)
+            .map_err(|_| AMMError::OracleInvalid)?;
+        let price = price_feed
+            .get_price_no_older_than(now, self.oracle_max_age)
+            .ok_or(AMMError::OracleStale)?;
+
+        let (reserve_a, reserve_b) = self.price_reserves(reserve_a, reserve_b, now)?;
+        OracleGuard::check_price(OracleGuard {
+            reserve_a,
+            reserve_b,
+            decimals_a,
+            decimals_b,
+
+            oracle_price: price.price,
+            oracle_conf: price.conf,
+            oracle_expo: price.expo,
+
+            max_deviation_bps: self.oracle_max_deviation_bps,
+            max_confidence_bps: self.oracle_max_confidence_bps,
+        })
+    }
+}
+
+// Canonical pool of a mint pair, fee tier and curve, created with the pool. The mints are sorted
+// so A/B and B/A resolve to the same address. The curve is part of the key, a stable and a
+// constant product pool can share a pair and fee tier. Concentrated pools register too, under
+// ConcentratedPool::PAIR_KIND
+#[account]
+#[derive(InitSpace)]
+pub struct PoolPair {
+    pub pool: Pubkey,
+
+    pub mint_min: Pubkey,
+    pub mint_max: Pubkey,
+    pub fee_bps: u16,
+    pub curve: u8, // CurveType::kind or ConcentratedPool::PAIR_KIND
+
+    pub bump: u8,
+}
+
+// Program-wide singleton, sets who may create pools and on what terms
+#[account]
+#[derive(InitSpace)]
+pub struct GlobalConfig {
+    pub admin: Pubkey,
+
+    #[max_len(MAX_FEE_TIERS)]
+    pub fee_tiers: Vec<u16>, // the only fee_bps a new pool may use
+
+    // charged to the pool creator, in lamports while creation_fee_mint is None
+    pub creation_fee: u64,
+    pub creation_fee_mint: Option<Pubkey>,
+    pub fee_recipient: Pubkey, // wallet or token account owner receiving creation and protocol fees
+
+    pub permissionless: bool, // when false only the admin creates pools
+    pub default_protocol_fee_bps: u16,
+    pub timelock_delay: i64, // seconds between queue_change and execute_change on every pool
+
+    // the guardian can pause any pool or the whole program, but cannot change anything else
+    pub guardian: Option<Pubkey>,
+    pub paused: bool, // kill switch, every pool is WithdrawOnly while it is on
+
+    pub bump: u8,
+}
+
+impl GlobalConfig {
+    pub fn allows_fee_tier(&self, fee_bps: u16) -> bool {
+        self.fee_tiers.contains(&fee_bps)
+    }
+
+    pub fn check_not_paused(&self, action: PoolAction) -> Result<()> {
+        require!(
+            !self.paused || action == PoolAction::Withdraw,
+            AMMError::ProtocolPaused
+        );
+        Ok(())
+    }
+}
+
+// A pool parameter update waiting out the global timelock, one per pool at a time
+#[account]
+#[derive(InitSpace)]
+pub struct PendingChange {
+    pub pool: Pubkey,
+    pub change: ParameterChange,
+
+    pub queued_at: i64,
+    pub executable_at: i64,
+
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum ParameterChange {
+    ProtocolFee {
+        protocol_fee_bps: u16,
+    },
+    Oracle {
+        oracle: Option<Pubkey>,
+        max_deviation_bps: u16,
+        max_confidence_bps: u16,
+        max_age: u64,
+    },
+    RampAmp {
+        target_amp: u64,
+        end_timestamp: i64,
+    },
+}
+
+impl ParameterChange {
+    // who queues, executes and cancels the change: the protocol fee is the global admin's, like
+    // at initialize, the oracle and the amp ramp are the pool owner's. A renounced pool has no
+    // owner, and apply keeps its protocol fee off
+    pub fn authority(&self, pool: &PoolConfig, global_config: &GlobalConfig) -> Option<Pubkey> {
+        match self {
+            ParameterChange::ProtocolFee { .. } => Some(global_config.admin),
+            ParameterChange::Oracle { .. } | ParameterChange::RampAmp { .. } => pool.owner,
+        }
+    }
+
+    // pending change slot of the authority, see ADMIN_CHANGE_SLOT
+    pub fn slot(&self) -> u8 {
+        match self {
+            ParameterChange::ProtocolFee { .. } => ADMIN_CHANGE_SLOT,
+            ParameterChange::Oracle { .. } | ParameterChange::RampAmp { .. } => OWNER_CHANGE_SLOT,
+        }
+    }
+
+    pub fn check_authority(
+        &self,
+        authority: Pubkey,
+        pool: &PoolConfig,
+        global_config: &GlobalConfig,
+    ) -> Result<()> {
+        require!(
+            self.authority(pool, global_config) == Some(authority),
+            AMMError::Unauthorized
+        );
+        Ok(())
+    }
+
+    pub fn apply(self, pool: &mut PoolConfig, now: i64) -> Result<()> {
+        match self {
+            ParameterChange::ProtocolFee { protocol_fee_bps } => {
+                pool.set_protocol_fee(protocol_fee_bps)
+            }
+            ParameterChange::Oracle {
+                oracle,
+                max_deviation_bps,
+                max_confidence_bps,
+                max_age,
+            } => pool.set_oracle(oracle, max_deviation_bps, max_confidence_bps, max_age),
+            ParameterChange::RampAmp {
+                target_amp,
+                end_timestamp,
+            } => pool.start_ramp(target_amp, end_timestamp, now),
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct Observation {
+    pub timestamp: i64, // zero while the slot has never been written
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+    pub liquidity_cumulative: u128, // sqrt(reserve_a * reserve_b) times seconds
+}
+
+impl Observation {
+    // moves the observation forward to `timestamp`, the reserves are the ones since self.timestamp
+    pub fn transform(&self, timestamp: i64, reserve_a: u64, reserve_b: u64) -> Observation {
+        let time_elapsed = (timestamp - self.timestamp) as u64;
+        let (price_a_cumulative, price_b_cumulative) =
+            PriceCumulative::accumulate(PriceCumulative {
+                price_a_cumulative: self.price_a_cumulative,
+                price_b_cumulative: self.price_b_cumulative,
+                reserve_a,
+                reserve_b,
+                time_elapsed,
+            });
+        let liquidity = integer_sqrt(reserve_a as u128 * reserve_b as u128);
+
+        Observation {
+            timestamp,
+            price_a_cumulative,
+            price_b_cumulative,
+            liquidity_cumulative: self
+                .liquidity_cumulative
+                .wrapping_add(liquidity.wrapping_mul(time_elapsed as u128)),
+        }
+    }
+
+    // linear interpolation between self and a later observation, the delta is multiplied by the
+    // offset before the division so the remainder of delta / span is not lost
+    pub fn interpolate(&self, after: &Observation, timestamp: i64) -> Result<Observation> {
+        let span = (after.timestamp - self.timestamp) as u128;
+        let offset = (timestamp - self.timestamp) as u128;
+        let lerp = |before: u128, after: u128| -> Result<u128> {
+            let delta = full_mul_div(after.wrapping_sub(before), offset, span, false)?;
+            Ok(before.wrapping_add(delta))
+        };
+
+        Ok(Observation {
+            timestamp,
+            price_a_cumulative: lerp(self.price_a_cumulative, after.price_a_cumulative)?,
+            price_b_cumulative: lerp(self.price_b_cumulative, after.price_b_cumulative)?,
+            liquidity_cumulative: lerp(self.liquidity_cumulative, after.liquidity_cumulative)?,
+        })
+    }
+}
+
+// Ring buffer of observations, written on every state change of the pool
+#[account]
+pub struct Observations {
+    pub pool: Pubkey,
+    pub bump: u8,
+
+    pub index: u16,       // slot of the most recent observation
+    pub cardinality: u16, // slots in use, grows up to observations.len()
+    pub observations: Vec<Observation>,
+}
+
+impl Observations {
+    pub fn space(cardinality: u16) -> usize {
+        8 + 32 + 1 + 2 + 2 + 4 + cardinality as usize * Observation::INIT_SPACE
+    }
+
+    pub fn write(&mut self, timestamp: i64, reserve_a: u64, reserve_b: u64) {
+        let last = self.observations[self.index as usize];
+        if last.timestamp == timestamp {
+            return;
+        }
+
+        // new slots only join the ring once the index wraps to them, like Uniswap V3
+        let cardinality_next = self.observations.len() as u16;
+        if cardinality_next > self.cardinality && self.index == self.cardinality - 1 {
+            self.cardinality = cardinality_next;
+        }
+
+        self.index = (self.index + 1) % self.cardinality;
+        self.observations[self.index as usize] = last.transform(timestamp, reserve_a, reserve_b);
+    }
+
+    pub fn observe(
+        &self,
+        now: i64,
+        seconds_ago: u32,
+        reserve_a: u64,
+        reserve_b: u64,
+    ) -> Result<Observation> {
+        let target = now - seconds_ago as i64;
+        let last = self.observations[self.index as usize];
+        if target >= last.timestamp {
+            // the reserves haven't changed since the last write
+            return Ok(last.transform(target, reserve_a, reserve_b));
+        }
+
+        let mut oldest = self.observations[((self.index + 1) % self.cardinality) as usize];
+        if oldest.timestamp == 0 {
+            oldest = self.observations[0];
+        }
+        require!(target >= oldest.timestamp, AMMError::ObservationTooOld);
+
+        // walk back from the newest observation to the first one at or before the target
+        for step in 1..self.cardinality {
+            let index = (self.index + self.cardinality - step) % self.cardinality;
+            let before = self.observations[index as usize];
+            if before.timestamp == 0 || before.timestamp > target {
+                continue;
+            }
+            if before.timestamp == target {
+                return Ok(before);
+            }
+
+            let after = self.observations[((index + 1) % self.cardinality) as usize];
+            return before.interpolate(&after, target);
+        }
+
+        err!(AMMError::ObservationTooOld)
+    }
+}
+
+// Concentrated liquidity pool, positions provide liquidity between two ticks instead of mint_lp
+#[account]
+#[derive(InitSpace)]
+pub struct ConcentratedPool {
+    pub owner: Option<Pubkey>,
+
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+
+    pub seed: u64,
+    pub bump: u8,
+
+    pub tick_spacing: u16,
+    pub fee_bps: u16, // swap fee on the input amount, shared by the in-range positions
+
+    pub sqrt_price_x64: u128, // Q64.64 sqrt of the price of A in B
+    pub tick_current: i32,
+    pub liquidity: u128, // liquidity of the positions whose range contains the price
+
+    // fees earned per unit of liquidity since the pool was created, Q64.64 and wrapping
+    pub fee_growth_global_a_x64: u128,
+    pub fee_growth_global_b_x64: u128,
+
+    pub status: PoolStatus, // set by the owner or the global guardian
+}
+
+impl ConcentratedPool {
+    // last seed of its pool pair, after the CurveType kinds so it never shares an entry with a
+    // PoolConfig of the same mints and fee tier
+    pub const PAIR_KIND: u8 = 3;
+
+    pub fn check_status(&self, global_config: &GlobalConfig, action: PoolAction) -> Result<()> {
+        global_config.check_not_paused(action)?;
+        self.status.check(action)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct Tick {
+    pub liquidity_net: i128,   // added when the price crosses the tick going up
+    pub liquidity_gross: u128, // total liquidity referencing the tick, zero means uninitialized
+
+    // fee growth on the other side of the tick from the current price
+    pub fee_growth_outside_a: u128,
+    pub fee_growth_outside_b: u128,
+}
+
+impl Tick {
+    pub fn update(
+        &mut self,
+        tick_index: i32,
+        pool: &ConcentratedPool,
+        liquidity_delta: i128,
+        is_upper: bool,
+    ) -> Result<()> {
+        // by convention all the fee growth so far happened below a newly initialized tick
+        if self.liquidity_gross == 0 && tick_index <= pool.tick_current {
+            self.fee_growth_outside_a = pool.fee_growth_global_a_x64;
+            self.fee_growth_outside_b = pool.fee_growth_global_b_x64;
+        }
+
+        self.liquidity_gross = self
+            .liquidity_gross
+            .checked_add_signed(liquidity_delta)
+            .ok_or(AMMError::Overflow)?;
+        self.liquidity_net = match is_upper {
+            true => self.liquidity_net.checked_sub(liquidity_delta),
+            false => self.liquidity_net.checked_add(liquidity_delta),
+        }
+        .ok_or(AMMError::Overflow)?;
+
+        Ok(())
+    }
+
+    // flips the fee growth outside to the other side and returns liquidity_net
+    pub fn cross(&mut self, fee_growth_global_a: u128, fee_growth_global_b: u128) -> i128 {
+        self.fee_growth_outside_a = fee_growth_global_a.wrapping_sub(self.fee_growth_outside_a);
+        self.fee_growth_outside_b = fee_growth_global_b.wrapping_sub(self.fee_growth_outside_b);
+        self.liquidity_net
+    }
+}
+
+// TICK_ARRAY_SIZE consecutive initializable ticks, tick_spacing apart
+#[account]
+pub struct TickArray {
+    pub pool: Pubkey,
+    pub start_tick_index: i32,
+    pub ticks: Vec<Tick>,
+}
+
+impl TickArray {
+    pub fn space() -> usize {
+        8 + 32 + 4 + 4 + TICK_ARRAY_SIZE as usize * Tick::INIT_SPACE
+    }
+
+    pub fn start_tick_index(tick: i32, tick_spacing: u16) -> i32 {
+        let ticks_in_array = TICK_ARRAY_SIZE * tick_spacing as i32;
+        tick.div_euclid(ticks_in_array) * ticks_in_array
+    }
+
+    fn offset(&self, tick: i32, tick_spacing: u16) -> Result<usize> {
+        require!(
+            tick % tick_spacing as i32 == 0
+                && Self::start_tick_index(tick, tick_spacing) == self.start_tick_index,
+            AMMError::InvalidTickArray
+        );
+        Ok(((tick - self.start_tick_index) / tick_spacing as i32) as usize)
+    }
+
+    pub fn contains(&self, tick: i32, tick_spacing: u16) -> bool {
+        self.offset(tick, tick_spacing).is_ok()
+    }
+
+    pub fn tick(&self, tick: i32, tick_spacing: u16) -> Result<&Tick> {
+        Ok(&self.ticks[self.offset(tick, tick_spacing)?])
+    }
+
+    pub fn tick_mut(&mut self, tick: i32, tick_spacing: u16) -> Result<&mut Tick> {
+        let offset = self.offset(tick, tick_spacing)?;
+        Ok(&mut self.ticks[offset])
+    }
+
+    // next initialized tick in this array in the swap direction, or the array edge when there is
+    // none. Going down it is the greatest tick <= tick_current, going up the smallest > tick_current
+    pub fn next_initialized_tick(
+        &self,
+        tick_current: i32,
+        tick_spacing: u16,
+        a_to_b: bool,
+    ) -> (i32, bool) {
+        let spacing = tick_spacing as i32;
+        let tick_at = |offset: i32| self.start_tick_index + offset * spacing;
+        let initialized = |offset: i32| self.ticks[offset as usize].liquidity_gross > 0;
+
+        match a_to_b {
+            true => {
+                let start =
+                    (tick_current.div_euclid(spacing) * spacing - self.start_tick_index) / spacing;
+                (0..=start.min(TICK_ARRAY_SIZE - 1))
+                    .rev()
+                    .find(|offset| initialized(*offset))
+                    .map_or((tick_at(0), false), |offset| (tick_at(offset), true))
+            }
+            false => {
+                let start = ((tick_current.div_euclid(spacing) + 1) * spacing
+                    - self.start_tick_index)
+                    / spacing;
+                (start.max(0)..TICK_ARRAY_SIZE)
+                    .find(|offset| initialized(*offset))
+                    .map_or((tick_at(TICK_ARRAY_SIZE - 1), false), |offset| {
+                        (tick_at(offset), true)
+                    })
+            }
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Position {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub bump: u8,
+
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: u128,
+
+    // fee growth inside the range at the last update, and the fees earned up to then
+    pub fee_growth_inside_a_last: u128,
+    pub fee_growth_inside_b_last: u128,
+    pub fees_owed_a: u64,
+    pub fees_owed_b: u64,
+}
+
+impl Position {
+    pub fn update_fees(
+        &mut self,
+        fee_growth_inside_a: u128,
+        fee_growth_inside_b: u128,
+    ) -> Result<()> {
+        let earned = |inside: u128, last: u128| -> Result<u64> {
+            let earned = full_mul_div(self.liquidity, inside.wrapping_sub(last), 1 << 64, false)?;
+            u64::try_from(earned).map_err(|_| AMMError::Overflow.into())
+        };
+
+        let earned_a = earned(fee_growth_inside_a, self.fee_growth_inside_a_last)?;
+        let earned_b = earned(fee_growth_inside_b, self.fee_growth_inside_b_last)?;
+        self.fees_owed_a = self
+            .fees_owed_a
+            .checked_add(earned_a)
+            .ok_or(AMMError::Overflow)?;
+        self.fees_owed_b = self
+            .fees_owed_b
+            .checked_add(earned_b)
+            .ok_or(AMMError::Overflow)?;
+
+        self.fee_growth_inside_a_last = fee_growth_inside_a;
+        self.fee_growth_inside_b_last = fee_growth_inside_b;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_observations(timestamp: i64) -> Observations {
+        Observations {
+            pool: Pubkey::default(),
+            bump: 0,
+            index: 0,
+            cardinality: 1,
+            observations: vec![Observation {
+                timestamp,
+                ..Observation::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn single_slot_keeps_only_the_latest_observation() {
+        let mut observations = new_observations(100);
+        observations.write(110, 1_000, 1_000);
+        observations.write(120, 1_000, 1_000);
+
+        assert_eq!(observations.index, 0);
+        assert_eq!(observations.observations[0].timestamp, 120);
+        assert!(observations.observe(120, 5, 1_000, 1_000).is_err());
+    }
+
+    #[test]
+    fn new_slots_join_when_the_index_wraps() {
+        let mut observations = new_observations(100);
+        observations
+            .observations
+            .resize(3, Observation::default());
+
+        observations.write(110, 1_000, 1_000);
+        assert_eq!(observations.cardinality, 3);
+        assert_eq!(observations.index, 1);
+
+        observations.write(120, 1_000, 1_000);
+        observations.write(130, 1_000, 1_000);
+        assert_eq!(observations.index, 0);
+        assert_eq!(observations.observations[0].timestamp, 130);
+
+        // the oldest one is now at 110
+        assert!(observations.observe(130, 20, 1_000, 1_000).is_ok());
+        assert!(observations.observe(130, 21, 1_000, 1_000).is_err());
+    }
+
+    #[test]
+    fn same_timestamp_is_written_once() {
+        let mut observations = new_observations(100);
+        observations
+            .observations
+            .resize(2, Observation::default());
+
+        observations.write(100, 1_000, 1_000);
+        assert_eq!(observations.index, 0);
+        assert_eq!(observations.cardinality, 1);
+    }
+
+    #[test]
+    fn observe_interpolates_between_observations() {
+        let mut observations = new_observations(100);
+        observations
+            .observations
+            .resize(4, Observation::default());
+
+        // liquidity 1_000 from 100 to 110, then 4_000 from 110 to 130
+        observations.write(110, 1_000, 1_000);
+        observations.write(130, 4_000, 4_000);
+
+        let at_105 = observations.observe(130, 25, 4_000, 4_000).unwrap();
+        assert_eq!(at_105.timestamp, 105);
+        assert_eq!(at_105.liquidity_cumulative, 5 * 1_000);
+
+        let at_120 = observations.observe(130, 10, 4_000, 4_000).unwrap();
+        assert_eq!(at_120.liquidity_cumulative, 10 * 1_000 + 10 * 4_000);
+
+        // past the newest observation it is extended with the current reserves
+        let at_140 = observations.observe(140, 0, 9_000, 9_000).unwrap();
+        assert_eq!(
+            at_140.liquidity_cumulative,
+            10 * 1_000 + 20 * 4_000 + 10 * 9_000
+        );
+    }
+
+    #[test]
+    fn interpolate_keeps_the_remainder_of_the_span() {
+        let before = Observation {
+            timestamp: 100,
+            price_a_cumulative: u128::MAX - 4,
+            price_b_cumulative: 0,
+            liquidity_cumulative: 0,
+        };
+        let after = Observation {
+            timestamp: 400,
+            price_a_cumulative: 995, // wrapped, 1_000 after before
+            price_b_cumulative: 1_000,
+            liquidity_cumulative: 7,
+        };
+
+        // 1_000 * 299 / 300, dividing first would give 3 * 299
+        let at_399 = before.interpolate(&after, 399).unwrap();
+        assert_eq!(at_399.price_b_cumulative, 996);
+        assert_eq!(at_399.price_a_cumulative, 991);
+        assert_eq!(at_399.liquidity_cumulative, 6);
+
+        let at_250 = before.interpolate(&after, 250).unwrap();
+        assert_eq!(at_250.price_b_cumulative, 500);
+        assert_eq!(at_250.liquidity_cumulative, 3);
+    }
+
+    fn new_tick_array(start_tick_index: i32, initialized: &[i32]) -> TickArray {
+        let mut tick_array = TickArray {
+            pool: Pubkey::default(),
+            start_tick_index,
+            ticks: vec![Tick::default(); TICK_ARRAY_SIZE as usize],
+        };
+        for tick in initialized {
+            tick_array.tick_mut(*tick, 10).unwrap().liquidity_gross = 1;
+        }
+        tick_array
+    }
+
+    #[test]
+    fn tick_array_start_rounds_towards_negative_infinity() {
+        assert_eq!(TickArray::start_tick_index(0, 10), 0);
+        assert_eq!(TickArray::start_tick_index(319, 10), 0);
+        assert_eq!(TickArray::start_tick_index(320, 10), 320);
+        assert_eq!(TickArray::start_tick_index(-1, 10), -320);
+        assert_eq!(TickArray::start_tick_index(-320, 10), -320);
+    }
+
+    #[test]
+    fn next_initialized_tick_searches_in_the_swap_direction() {
+        let tick_array = new_tick_array(0, &[50, 100]);
+
+        // going down the tick the price sits on counts
+        assert_eq!(tick_array.next_initialized_tick(100, 10, true), (100, true));
+        assert_eq!(tick_array.next_initialized_tick(99, 10, true), (50, true));
+        assert_eq!(tick_array.next_initialized_tick(49, 10, true), (0, false));
+
+        // going up it doesn't
+        assert_eq!(tick_array.next_initialized_tick(50, 10, false), (100, true));
+        assert_eq!(tick_array.next_initialized_tick(-5, 10, false), (50, true));
+        assert_eq!(tick_array.next_initialized_tick(100, 10, false), (310, false));
+    }
+
+    #[test]
+    fn ticks_outside_the_array_are_rejected() {
+        let mut tick_array = new_tick_array(0, &[]);
+        assert!(tick_array.tick(320, 10).is_err());
+        assert!(tick_array.tick(-10, 10).is_err());
+        assert!(tick_array.tick_mut(15, 10).is_err());
+        assert!(tick_array.contains(310, 10));
+    }
+
+    #[test]
+    fn position_earns_fee_growth_times_liquidity() {
+        let mut position = Position {
+            pool: Pubkey::default(),
+            owner: Pubkey::default(),
+            bump: 0,
+            tick_lower: -10,
+            tick_upper: 10,
+            liquidity: 1_000,
+            fee_growth_inside_a_last: 0,
+            fee_growth_inside_b_last: u128::MAX,
+            fees_owed_a: 0,
+            fees_owed_b: 0,
+        };
+
+        // 0.5 per unit of liquidity in A, and 2 in B across the wrap
+        position.update_fees(1 << 63, 1 << 65).unwrap();
+        assert_eq!(position.fees_owed_a, 500);
+        assert_eq!(position.fees_owed_b, 2_000);
+
+        // nothing new since the last update
+        position.update_fees(1 << 63, 1 << 65).unwrap();
+        assert_eq!(position.fees_owed_a, 500);
+        assert_eq!(position.fees_owed_b, 2_000);
+    }
+
+    #[test]
+    fn withdrawals_stay_open_in_every_status() {
+        let statuses = [
+            PoolStatus::Active,
+            PoolStatus::SwapsPaused,
+            PoolStatus::DepositsPaused,
+            PoolStatus::WithdrawOnly,
+        ];
+        for status in statuses {
+            assert!(status.check(PoolAction::Withdraw).is_ok());
+        }
+
+        assert!(PoolStatus::Active.check(PoolAction::Swap).is_ok());
+        assert!(PoolStatus::SwapsPaused.check(PoolAction::Swap).is_err());
+        assert!(PoolStatus::SwapsPaused.check(PoolAction::Deposit).is_ok());
+        assert!(PoolStatus::DepositsPaused.check(PoolAction::Swap).is_ok());
+        assert!(PoolStatus::DepositsPaused.check(PoolAction::Deposit).is_err());
+        assert!(PoolStatus::WithdrawOnly.check(PoolAction::Swap).is_err());
+        assert!(PoolStatus::WithdrawOnly.check(PoolAction::Deposit).is_err());
+    }
+
+    #[test]
+    fn only_an_active_pool_closes() {
+        assert!(PoolStatus::Active.check(PoolAction::Close).is_ok());
+        for status in [
+            PoolStatus::SwapsPaused,
+            PoolStatus::DepositsPaused,
+            PoolStatus::WithdrawOnly,
+        ] {
+            assert!(status.check(PoolAction::Close).is_err());
+        }
+    }
+
+    #[test]
+    fn concentrated_pools_have_their_own_pair_kind() {
+        for curve in [
+            CurveType::ConstantProduct,
+            CurveType::StableSwap { amp: 100 },
+            CurveType::Weighted {
+                weight_a: 8_000,
+                weight_b: 2_000,
+            },
+        ] {
+            assert_ne!(curve.kind(), ConcentratedPool::PAIR_KIND);
+        }
+    }
+
+    // what 0.1.0 wrote: discriminator and borsh body in an account of PoolConfigV0::LEN bytes
+    fn legacy_account(legacy: &PoolConfigV0) -> Vec<u8> {
+        let mut data = PoolConfig::DISCRIMINATOR.to_vec();
+        legacy.serialize(&mut data).unwrap();
+        data.resize(PoolConfigV0::LEN, 0);
+        data
+    }
+
+    fn legacy_pool(owner: Option<Pubkey>) -> PoolConfigV0 {
+        PoolConfigV0 {
+            owner,
+            mint_a: Pubkey::new_unique(),
+            mint_b: Pubkey::new_unique(),
+            vault_a_bump: 254,
+            vault_b_bump: 253,
+            seed: 42,
+            pool_mint_bump: 252,
+            pool_bump: 251,
+        }
+    }
+
+    #[test]
+    fn migrates_a_pool_written_in_the_0_1_0_layout() {
+        for owner in [Some(Pubkey::new_unique()), None] {
+            let legacy = legacy_pool(owner);
+            let read = PoolConfigV0::try_from_account_data(&legacy_account(&legacy)).unwrap();
+            assert_eq!(read, legacy);
+
+            // rewrite the body in place after the realloc, then read it back like Account<PoolConfig>
+            let mut data = legacy_account(&legacy);
+            data.resize(8 + PoolConfig::INIT_SPACE, 0);
+            PoolConfig::from_v0(read, 1_000)
+                .serialize(&mut &mut data[8..])
+                .unwrap();
+            let pool = PoolConfig::try_deserialize(&mut data.as_slice()).unwrap();
+
+            assert_eq!(pool.version, POOL_CONFIG_VERSION);
+            assert_eq!(pool.owner, legacy.owner);
+            assert_eq!((pool.mint_a, pool.mint_b), (legacy.mint_a, legacy.mint_b));
+            assert_eq!((pool.vault_a_bump, pool.vault_b_bump), (254, 253));
+            assert_eq!(
+                (pool.seed, pool.pool_mint_bump, pool.pool_bump),
+                (42, 252, 251)
+            );
+
+            assert_eq!((pool.fee_bps, pool.protocol_fee_bps), (0, 0));
+            assert_eq!(pool.curve, CurveType::ConstantProduct);
+            assert_eq!(pool.last_update_timestamp, 1_000);
+            assert_eq!(pool.status, PoolStatus::Active);
+            assert_eq!(pool.oracle, None);
+            assert_eq!(pool.locked_liquidity, 0);
+            assert!(!pool.registered);
+            assert!(pool.check_no_flash_loan().is_ok());
+        }
+    }
+
+    #[test]
+    fn only_0_1_0_pool_accounts_are_migrated() {
+        let legacy = legacy_account(&legacy_pool(None));
+
+        // already in the current layout
+        let mut current = legacy.clone();
+        current.resize(8 + PoolConfig::INIT_SPACE, 0);
+        assert!(PoolConfigV0::try_from_account_data(&current).is_err());
+
+        // another account type of the same size
+        let mut other = legacy;
+        other[..8].copy_from_slice(&PoolPair::DISCRIMINATOR);
+        assert!(PoolConfigV0::try_from_account_data(&other).is_err());
+    }
+
+    fn global_config(admin: Pubkey) -> GlobalConfig {
+        GlobalConfig {
+            admin,
+            fee_tiers: vec![30],
+            creation_fee: 0,
+            creation_fee_mint: None,
+            fee_recipient: Pubkey::new_unique(),
+            permissionless: true,
+            default_protocol_fee_bps: 20,
+            timelock_delay: 60,
+            guardian: None,
+            paused: false,
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn only_the_global_admin_changes_the_protocol_fee() {
+        let (admin, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let config = global_config(admin);
+        let mut pool = PoolConfig::from_v0(legacy_pool(Some(owner)), 0);
+
+        let protocol_fee = ParameterChange::ProtocolFee {
+            protocol_fee_bps: 900,
+        };
+        assert!(protocol_fee.check_authority(owner, &pool, &config).is_err());
+        assert!(protocol_fee.check_authority(admin, &pool, &config).is_ok());
+
+        // the pool owner keeps the oracle and the amp ramp
+        let oracle = ParameterChange::Oracle {
+            oracle: None,
+            max_deviation_bps: 0,
+            max_confidence_bps: 0,
+            max_age: 0,
+        };
+        let ramp = ParameterChange::RampAmp {
+            target_amp: 100,
+            end_timestamp: 86_400,
+        };
+        for change in [oracle, ramp] {
+            assert!(change.check_authority(owner, &pool, &config).is_ok());
+            assert!(change.check_authority(admin, &pool, &config).is_err());
+        }
+
+        // nothing changes on a renounced pool, the protocol fee stays off. The global admin can
+        // still cancel a protocol fee change it had queued before
+        pool.owner = None;
+        for change in [oracle, ramp] {
+            assert!(change.check_authority(owner, &pool, &config).is_err());
+        }
+        assert!(protocol_fee.check_authority(admin, &pool, &config).is_ok());
+        assert!(protocol_fee.apply(&mut pool, 0).is_err());
+
+        // each authority queues in its own slot
+        assert_eq!(protocol_fee.slot(), ADMIN_CHANGE_SLOT);
+        assert_eq!(oracle.slot(), OWNER_CHANGE_SLOT);
+        assert_eq!(ramp.slot(), OWNER_CHANGE_SLOT);
+    }
+
+    #[test]
+    fn a_full_route_of_full_observation_buffers_fits_in_the_heap() {
+        use crate::constant::{MAX_OBSERVATION_CARDINALITY, MAX_ROUTE_HOPS};
+        use anchor_lang::solana_program::entrypoint::HEAP_LENGTH;
+
+        // deserialized as a Vec, so at the in-memory size of an observation
+        let buffer = MAX_OBSERVATION_CARDINALITY as usize * std::mem::size_of::<Observation>();
+        assert!(MAX_ROUTE_HOPS * buffer <= HEAP_LENGTH / 2);
+    }
+
+    #[test]
+    fn imbalanced_stable_pool_passes_a_one_to_one_oracle() {
+        let mut pool = PoolConfig::from_v0(legacy_pool(None), 0);
+        pool.curve = CurveType::StableSwap { amp: 100 };
+        pool.target_amp = 100;
+
+        let guard = |(reserve_a, reserve_b)| OracleGuard {
+            reserve_a,
+            reserve_b,
+            decimals_a: 6,
+            decimals_b: 6,
+            oracle_price: 100_000_000, // 1.0
+            oracle_conf: 0,
+            oracle_expo: -8,
+            max_deviation_bps: 500,
+            max_confidence_bps: 100,
+        };
+
+        // 70/30 is a healthy stable pool, only its raw reserve ratio is far from the peg
+        let (reserve_a, reserve_b) = (7_000_000_000, 3_000_000_000);
+        let price_reserves = pool.price_reserves(reserve_a, reserve_b, 0).unwrap();
+        assert!(OracleGuard::check_price(guard(price_reserves)).is_ok());
+        assert!(OracleGuard::check_price(guard((reserve_a, reserve_b))).is_err());
+    }
 }
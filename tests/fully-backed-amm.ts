@@ -7,6 +7,7 @@ import {
   getOrCreateAssociatedTokenAccount,
   getMint,
   mintToChecked,
+  createTransferInstruction,
   TOKEN_PROGRAM_ID,
   getAccount,
   Account,
@@ -130,13 +131,168 @@ describe("fully-backed-amm", () => {
   let userTokenA: anchor.web3.PublicKey;
   let userTokenB: anchor.web3.PublicKey;
 
-  let lqAmountA = 1000;
-  let lqAmountB = 1000;
+  let lqAmountA = 10_000;
+  let lqAmountB = 10_000;
+
+  const MINIMUM_LIQUIDITY = 1000; // locked forever on the first deposit
+  const MAX_OBSERVATION_CARDINALITY = 64; // slots the observation ring buffer can grow to
+
+  let feeBps = 30; // 0.3% swap fee
+  let stableFeeBps = 5; // stable pairs use a lower fee tier
+  let weightedFeeBps = 100;
+  let protocolFeeBps = 20; // 0.2% protocol fee, the global config default
 
   let vaultA: anchor.web3.PublicKey;
   let vaultB: anchor.web3.PublicKey;
 
   let poolConfigPDA: anchor.web3.PublicKey;
+  let observationsPDA: anchor.web3.PublicKey;
+
+  // emit_cpi! signs the self-CPI with this PDA
+  const eventAuthority = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("__event_authority")],
+    program.programId
+  )[0];
+
+  // program-wide config every initialize is checked against
+  const globalConfigPDA = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("global_config")],
+    program.programId
+  )[0];
+  const programData = anchor.web3.PublicKey.findProgramAddressSync(
+    [program.programId.toBuffer()],
+    new anchor.web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+  )[0];
+  const creationFeeRecipient = anchor.web3.Keypair.generate().publicKey;
+  const guardian = anchor.web3.Keypair.generate(); // can pause pools and the protocol
+  const creationFee = anchor.web3.LAMPORTS_PER_SOL / 100; // paid in SOL by the pool creator
+  const timelockDelay = 60; // seconds before a queued pool change can execute, the program minimum
+
+  // accounts every initialize passes for the global config, the fee is paid in SOL
+  const creationFeeAccounts = {
+    globalConfig: globalConfigPDA,
+    feeRecipient: creationFeeRecipient,
+    creationFeeMint: null,
+    adminFeeToken: null,
+    feeRecipientToken: null,
+    feeTokenProgram: TOKEN_PROGRAM_ID,
+  };
+
+  const globalConfigParams = (permissionless: boolean) => ({
+    feeTiers: [stableFeeBps, feeBps, weightedFeeBps],
+    creationFee: new BN(creationFee),
+    creationFeeMint: null,
+    feeRecipient: creationFeeRecipient,
+    permissionless,
+    defaultProtocolFeeBps: protocolFeeBps,
+    guardian: guardian.publicKey,
+    timelockDelay: new BN(timelockDelay),
+  });
+
+  // last seed of a pending change: the global admin and the pool owner each queue in their own slot
+  const CHANGE_SLOT = { admin: 0, owner: 1 };
+
+  const pendingChangePDA = (pool: anchor.web3.PublicKey, slot = CHANGE_SLOT.owner) =>
+    anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pending_change"), pool.toBuffer(), Buffer.from([slot])],
+      program.programId
+    )[0];
+
+  // the pool owner queues oracle and amp ramp changes, the global admin protocol fee changes
+  const queueAccounts = (
+    pool: anchor.web3.PublicKey,
+    authority: anchor.web3.PublicKey = admin.publicKey,
+    slot = CHANGE_SLOT.owner
+  ) => ({
+    authority,
+    globalConfig: globalConfigPDA,
+    poolConfigAccount: pool,
+    pendingChange: pendingChangePDA(pool, slot),
+    systemProgram: anchor.web3.SystemProgram.programId,
+    eventAuthority: eventAuthority,
+    program: program.programId,
+  });
+
+  const resolveAccounts = (
+    pool: anchor.web3.PublicKey,
+    authority: anchor.web3.PublicKey = admin.publicKey,
+    slot = CHANGE_SLOT.owner
+  ) => ({
+    authority,
+    globalConfig: globalConfigPDA,
+    poolConfigAccount: pool,
+    pendingChange: pendingChangePDA(pool, slot),
+    eventAuthority: eventAuthority,
+    program: program.programId,
+  });
+
+  // pool parameters only change through the timelock: queue, wait it out, execute. A null signer
+  // is the provider wallet, the global admin
+  const applyChange = async (
+    pool: anchor.web3.PublicKey,
+    change: any,
+    signer: anchor.web3.Keypair | null = admin
+  ) => {
+    const authority = signer ? signer.publicKey : provider.wallet.publicKey;
+    const signers = signer ? [signer] : [];
+    const slot = change.protocolFee ? CHANGE_SLOT.admin : CHANGE_SLOT.owner;
+
+    await program.methods
+      .queueChange(change)
+      .accountsStrict(queueAccounts(pool, authority, slot))
+      .signers(signers)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, (timelockDelay + 1) * 1000));
+    await program.methods
+      .executeChange()
+      .accountsStrict(resolveAccounts(pool, authority, slot))
+      .signers(signers)
+      .rpc();
+  };
+
+  // CurveType::kind, the last seed of the pool pair. Concentrated pools use ConcentratedPool::PAIR_KIND
+  const CURVE_KIND = { constantProduct: 0, stableSwap: 1, weighted: 2, concentrated: 3 };
+
+  // canonical pool address of a mint pair, fee tier and curve, the mints sorted by their bytes
+  const poolPairPDA = (
+    mintX: anchor.web3.PublicKey,
+    mintY: anchor.web3.PublicKey,
+    fee: number,
+    curve: number = CURVE_KIND.constantProduct
+  ) => {
+    const [mintMin, mintMax] =
+      Buffer.compare(mintX.toBuffer(), mintY.toBuffer()) < 0
+        ? [mintX, mintY]
+        : [mintY, mintX];
+    const feeBuffer = Buffer.alloc(2);
+    feeBuffer.writeUInt16LE(fee);
+
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("pool_pair"),
+        mintMin.toBuffer(),
+        mintMax.toBuffer(),
+        feeBuffer,
+        Buffer.from([curve]),
+      ],
+      program.programId
+    )[0];
+  };
+
+  // second pool on the same mints using the StableSwap curve
+  const stableSeed = new BN(randomBytes(8));
+  const stablePool = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("liquid_pool"), stableSeed.toArrayLike(Buffer, "le", 8)],
+    program.programId
+  )[0];
+  const stableObservations = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("observations"), stablePool.toBuffer()],
+    program.programId
+  )[0];
+  const stableMintLp = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("mint_lp"), stablePool.toBuffer()],
+    program.programId
+  )[0];
 
   before("Setup for testing", async () => {
     try {
@@ -151,6 +307,11 @@ describe("fully-backed-amm", () => {
         program.programId
       )[0];
 
+      observationsPDA = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("observations"), poolConfigPDA.toBuffer()],
+        program.programId
+      )[0];
+
       // airdrop sol for each Keyapir accounts
       await airdropSOL(admin.publicKey, provider, 10);
       await airdropSOL(lqProvider.publicKey, provider, 10);
@@ -178,10 +339,125 @@ describe("fully-backed-amm", () => {
     }
   });
 
+  it("global config sets the pool creation policy", async () => {
+    await program.methods
+      .initializeGlobalConfig(globalConfigParams(false))
+      .accountsStrict({
+        admin: provider.wallet.publicKey,
+        globalConfig: globalConfigPDA,
+        program: program.programId,
+        programData: programData,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const initAccounts = (fee: number) => ({
+      admin: admin.publicKey,
+
+      mintA: mintA,
+      mintB: mintB,
+      mintLp: mintLP,
+
+      vaultA: vaultA,
+      vaultB: vaultB,
+      poolConfigAccount: poolConfigPDA,
+      poolPair: poolPairPDA(mintA, mintB, fee),
+      ...creationFeeAccounts,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: observationsPDA,
+      program: program.programId,
+    });
+
+    try {
+      // only the global admin creates pools while permissionless is off
+      await program.methods
+        .initialize(secretSeed, feeBps, null, { constantProduct: {} })
+        .accountsStrict(initAccounts(feeBps))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "PoolCreationRestricted");
+    }
+
+    await program.methods
+      .updateGlobalConfig(null, globalConfigParams(true))
+      .accountsStrict({
+        admin: provider.wallet.publicKey,
+        globalConfig: globalConfigPDA,
+      })
+      .rpc();
+
+    try {
+      await program.methods
+        .initialize(secretSeed, 31, null, { constantProduct: {} })
+        .accountsStrict(initAccounts(31))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "FeeTierNotAllowed");
+    }
+
+    try {
+      // a protocol fee other than the default is for the global admin only
+      await program.methods
+        .initialize(secretSeed, feeBps, 50, { constantProduct: {} })
+        .accountsStrict(initAccounts(feeBps))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "Unauthorized");
+    }
+
+    try {
+      await program.methods
+        .updateGlobalConfig(null, globalConfigParams(true))
+        .accountsStrict({
+          admin: admin.publicKey,
+          globalConfig: globalConfigPDA,
+        })
+        .signers([admin])
+        .rpc();
+
+      assert.fail("update should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "Unauthorized");
+    }
+
+    try {
+      // without a delay a change could be queued and executed in the same transaction
+      await program.methods
+        .updateGlobalConfig(null, { ...globalConfigParams(true), timelockDelay: new BN(0) })
+        .accountsStrict({
+          admin: provider.wallet.publicKey,
+          globalConfig: globalConfigPDA,
+        })
+        .rpc();
+
+      assert.fail("update should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidTimelockDelay");
+    }
+  });
+
   it("Is initialized! (1nd instruction)", async () => {
     try {
       await program.methods
-        .initialize(secretSeed)
+        .initialize(secretSeed, feeBps, null, { constantProduct: {} })
         .accountsStrict({
           admin: admin.publicKey,
 
@@ -192,15 +468,25 @@ describe("fully-backed-amm", () => {
           vaultA: vaultA,
           vaultB: vaultB,
           poolConfigAccount: poolConfigPDA,
+          poolPair: poolPairPDA(mintA, mintB, feeBps),
+          ...creationFeeAccounts,
 
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
           associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          program: program.programId,
         })
         .signers([admin])
         .rpc();
 
       let account = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(account.protocolFeeBps, protocolFeeBps);
+      assert.equal(
+        await provider.connection.getBalance(creationFeeRecipient),
+        creationFee
+      );
       // console.log(`✅✅✅ Yey setup has done ${admin.publicKey} ✅✅✅`);
       // console.log(`🦄🦄🦄 The account details are ${account.owner} 🦄🦄🦄`);
     } catch (e) {
@@ -208,6 +494,102 @@ describe("fully-backed-amm", () => {
     }
   });
 
+  it("pool pair registry points at the pool and blocks duplicates", async () => {
+    try {
+      // the same address whichever way round the mints are given
+      const pair = await program.account.poolPair.fetch(
+        poolPairPDA(mintB, mintA, feeBps)
+      );
+      assert.equal(pair.pool.toString(), poolConfigPDA.toString());
+      assert.equal(pair.feeBps, feeBps);
+      assert.equal(pair.curve, CURVE_KIND.constantProduct);
+    } catch (error) {
+      throw new Error(`Error while fetching the pool pair ${error}`);
+    }
+
+    const duplicateSeed = new BN(randomBytes(8));
+    const duplicatePool = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("liquid_pool"), duplicateSeed.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+    const duplicateAccounts = (
+      mintX: anchor.web3.PublicKey,
+      mintY: anchor.web3.PublicKey,
+      curve: number = CURVE_KIND.constantProduct
+    ) => ({
+      admin: admin.publicKey,
+
+      mintA: mintX,
+      mintB: mintY,
+      mintLp: anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("mint_lp"), duplicatePool.toBuffer()],
+        program.programId
+      )[0],
+
+      vaultA: getAssociatedTokenAddressSync(mintX, duplicatePool, true),
+      vaultB: getAssociatedTokenAddressSync(mintY, duplicatePool, true),
+      poolConfigAccount: duplicatePool,
+      poolPair: poolPairPDA(mintX, mintY, feeBps, curve),
+      ...creationFeeAccounts,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("observations"), duplicatePool.toBuffer()],
+        program.programId
+      )[0],
+      program: program.programId,
+    });
+
+    try {
+      await program.methods
+        .initialize(duplicateSeed, feeBps, null, { constantProduct: {} })
+        .accountsStrict(duplicateAccounts(mintA, mintA))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "IdenticalMints");
+    }
+
+    try {
+      // B/A at the same fee tier is the pool that already exists
+      await program.methods
+        .initialize(duplicateSeed, feeBps, null, { constantProduct: {} })
+        .accountsStrict(duplicateAccounts(mintB, mintA))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "already in use");
+    }
+
+    try {
+      // another curve on the same pair and fee tier gets its own entry
+      await program.methods
+        .initialize(duplicateSeed, feeBps, null, {
+          stableSwap: { amp: new BN(100) },
+        })
+        .accountsStrict(duplicateAccounts(mintB, mintA, CURVE_KIND.stableSwap))
+        .signers([admin])
+        .rpc();
+
+      const pair = await program.account.poolPair.fetch(
+        poolPairPDA(mintA, mintB, feeBps, CURVE_KIND.stableSwap)
+      );
+      assert.equal(pair.pool.toString(), duplicatePool.toString());
+      assert.equal(pair.curve, CURVE_KIND.stableSwap);
+    } catch (error) {
+      throw new Error(`Error while registering a second curve ${error}`);
+    }
+  });
+
   it("Is adding liquidity first time (2nd instruction)", async () => {
     try {
       const LPMintAccount: Mint = await getMint(provider.connection, mintLP);
@@ -218,7 +600,11 @@ describe("fully-backed-amm", () => {
       );
 
       let tx = await program.methods
-        .depositeAsset(new anchor.BN(lqAmountA), new anchor.BN(lqAmountB))
+        .depositeAsset(
+          new anchor.BN(lqAmountA),
+          new anchor.BN(lqAmountB),
+          new anchor.BN(0)
+        )
         .accountsPartial({
           liquidProvider: lqProvider.publicKey,
 
@@ -249,6 +635,29 @@ describe("fully-backed-amm", () => {
       //   `🔥 After 1st adding liquidity Checking Mint supply ${Number(lpMintAccount.supply)} 🔥`
       // );
 
+      const lockedLpAuthority = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("locked_lp"), poolConfigPDA.toBuffer()],
+        program.programId
+      )[0];
+      const lockedLp = await getAccount(
+        provider.connection,
+        getAssociatedTokenAddressSync(mintLP, lockedLpAuthority, true)
+      );
+      const providerLp = await getAccount(
+        provider.connection,
+        getAssociatedTokenAddressSync(mintLP, lqProvider.publicKey)
+      );
+
+      assert.equal(Number(lockedLp.amount), MINIMUM_LIQUIDITY);
+      assert.equal(
+        Number(providerLp.amount),
+        Math.sqrt(lqAmountA * lqAmountB) - MINIMUM_LIQUIDITY
+      );
+      assert.equal(
+        Number(lpMintAccount.supply),
+        Math.sqrt(lqAmountA * lqAmountB)
+      );
+
       // console.log(
       //   `✨ setting-up test-case worked like a charm ✨ the vaultA owner is ${vaultAPDA.owner.toString()} the vaultA owner is ${vaultBPDA.owner.toString()} and the configPDA is ${poolConfigPDA.toString()}`
       // );
@@ -260,7 +669,7 @@ describe("fully-backed-amm", () => {
   it("Is adding liquidity (2nd instruction)", async () => {
     try {
       let tx = await program.methods
-        .depositeAsset(new anchor.BN(100), new anchor.BN(100))
+        .depositeAsset(new anchor.BN(100), new anchor.BN(100), new anchor.BN(0))
         .accountsPartial({
           liquidProvider: lqProvider.publicKey,
 
@@ -327,7 +736,10 @@ describe("fully-backed-amm", () => {
       let lpTokens =
         (amountA / Number(vaultAPDA.amount)) * Number(lpMintAccount.supply); // s = (dx/X)T: for cal lp shares
 
-      assert.equal(Number(lqProviderLPAccount.amount), lpTokens + 1000);
+      assert.equal(
+        Number(lqProviderLPAccount.amount),
+        lpTokens + Math.sqrt(lqAmountA * lqAmountB) - MINIMUM_LIQUIDITY
+      );
       console.log(
         `🦄🦄🦄🦄 The no.of minted lp tokens are perfect ${lpTokens} 🦄🦄🦄🦄`
       );
@@ -336,10 +748,11 @@ describe("fully-backed-amm", () => {
     }
   });
 
-  it("Is adding liquidity should fail (2nd instruction)", async () => {
+  it("Is adding liquidity should fail when min lp out is not met (2nd instruction)", async () => {
     try {
-      let tx = await program.methods
-        .depositeAsset(new anchor.BN(10), new anchor.BN(100))
+      // only 10 of each token can be taken at the current ratio, way below 1000 lp tokens
+      await program.methods
+        .depositeAsset(new anchor.BN(10), new anchor.BN(100), new anchor.BN(1000))
         .accountsPartial({
           liquidProvider: lqProvider.publicKey,
 
@@ -362,10 +775,10 @@ describe("fully-backed-amm", () => {
         .signers([lqProvider])
         .rpc();
 
-      const vaultAPDA: Account = await getAccount(provider.connection, vaultA);
-      const vaultBPDA = await getAccount(provider.connection, vaultB);
+      assert.fail("min lp out should have failed");
     } catch (error) {
       console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "MinLpOutNotMet");
     }
   });
 
@@ -374,8 +787,13 @@ describe("fully-backed-amm", () => {
       const vaultAPDA: Account = await getAccount(provider.connection, vaultA);
       const vaultBPDA = await getAccount(provider.connection, vaultB);
 
+      const amountIn = 10 - Math.floor((10 * protocolFeeBps) / 10_000);
+      const amountInAfterFee = Math.floor(
+        (amountIn * (10_000 - feeBps)) / 10_000
+      );
       const reqAmount =
-        (Number(vaultBPDA.amount) * 10) / (10 + Number(vaultAPDA.amount));
+        (Number(vaultBPDA.amount) * amountInAfterFee) /
+        (amountInAfterFee + Number(vaultAPDA.amount));
       const amountBefore = (await getAccount(provider.connection, userTokenB))
         .amount;
 
@@ -387,6 +805,7 @@ describe("fully-backed-amm", () => {
           user: user.publicKey,
           userTokenA: userTokenA,
           userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
           poolConfigAccount: poolConfigPDA,
 
           mintA: mintA,
@@ -398,6 +817,10 @@ describe("fully-backed-amm", () => {
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
           associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          oracle: null,
+          program: program.programId,
         })
         .signers([user])
         .rpc({ skipPreflight: true });
@@ -406,44 +829,88 @@ describe("fully-backed-amm", () => {
         .amount;
 
       console.log(`Amount after trx ${Number(amountAfter)}`);
-      console.log(`Requierd amount ${reqAmount.toFixed()}`);
-      assert.equal(Number(reqAmount.toFixed()), Number(amountAfter));
+      console.log(`Requierd amount ${Math.floor(reqAmount)}`);
+      assert.equal(Math.floor(reqAmount), Number(amountAfter));
     } catch (error) {
       console.log(`You got error while trying to swap a token ${error}`);
     }
   });
 
-  it("withdraw asset", async () => {
+  it("Swap token for an exact output", async () => {
+    const swapAccounts = {
+      user: user.publicKey,
+      userTokenA: userTokenA,
+      userTokenB: userTokenB,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: poolConfigPDA,
+
+      mintA: mintA,
+      mintB: mintB,
+
+      vaultA: vaultA,
+      vaultB: vaultB,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: observationsPDA,
+      oracle: null,
+      program: program.programId,
+    };
+
     try {
-      let lqProviderLPAccount: Account =
-        await getOrCreateAssociatedTokenAccount(
-          provider.connection,
-          lqProvider,
-          mintLP,
-          lqProvider.publicKey
-        );
+      const userTokenABefore = await getAccount(provider.connection, userTokenA);
+      const userTokenBBefore = await getAccount(provider.connection, userTokenB);
 
-      let mintLPAccount: Mint = await getMint(provider.connection, mintLP);
+      await program.methods
+        .swapExactOut(true, new anchor.BN(5), new anchor.BN(100))
+        .accountsStrict(swapAccounts)
+        .signers([user])
+        .rpc();
 
-      console.log(
-        `The mint supply of token mint account is ${Number(
-          mintLPAccount.supply
-        )}`
-      );
+      const userTokenAAfter = await getAccount(provider.connection, userTokenA);
+      const userTokenBAfter = await getAccount(provider.connection, userTokenB);
+
+      const paid =
+        Number(userTokenABefore.amount) - Number(userTokenAAfter.amount);
+      const received =
+        Number(userTokenBAfter.amount) - Number(userTokenBBefore.amount);
 
+      assert.equal(received, 5);
+      assert.isAtMost(paid, 100);
+    } catch (error) {
+      throw new Error(`Error occured while testing swap exact out ${error}`);
+    }
+
+    try {
+      // 5 tokens of B can't be bought with a single token of A
       await program.methods
-        .withdrawAsset(new anchor.BN(Number(100)))
+        .swapExactOut(true, new anchor.BN(5), new anchor.BN(1))
+        .accountsStrict(swapAccounts)
+        .signers([user])
+        .rpc();
+
+      assert.fail("swap exact out should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "MaxAmountInExceeded");
+    }
+  });
+
+  it("Swap emits a Swapped event", async () => {
+    try {
+      const signature = await program.methods
+        .swap(true, new anchor.BN(10), new anchor.BN(0))
         .accountsStrict({
-          user: lqProvider.publicKey,
+          user: user.publicKey,
+          userTokenA: userTokenA,
+          userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
           poolConfigAccount: poolConfigPDA,
 
           mintA: mintA,
           mintB: mintB,
-          mintLp: mintLP,
-
-          userTokenA: lqProviderA,
-          userTokenB: lqProviderB,
-          userTokenLp: lqProviderLPAccount.address,
 
           vaultA: vaultA,
           vaultB: vaultB,
@@ -451,25 +918,2591 @@ describe("fully-backed-amm", () => {
           tokenProgram: TOKEN_PROGRAM_ID,
           systemProgram: anchor.web3.SystemProgram.programId,
           associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          oracle: null,
+          program: program.programId,
         })
-        .signers([lqProvider])
-        .rpc({
-          skipPreflight: true,
-        });
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+
+      // emit_cpi! events are the data of the self-CPI, after the 8 byte event ix tag
+      const events = tx.meta.innerInstructions
+        .flatMap((inner) => inner.instructions)
+        .map((ix) => {
+          const data = anchor.utils.bytes.bs58.decode(ix.data);
+          return program.coder.events.decode(
+            anchor.utils.bytes.base64.encode(data.subarray(8))
+          );
+        })
+        .filter((event) => event !== null);
 
       const vaultAPDA: Account = await getAccount(provider.connection, vaultA);
-      const vaultBPDA = await getAccount(provider.connection, vaultB);
+      const pool = await program.account.poolConfig.fetch(poolConfigPDA);
+
+      assert.equal(events.length, 1);
+      assert.equal(events[0].name, "swapped");
+      assert.equal(events[0].data.pool.toString(), poolConfigPDA.toString());
+      assert.equal(events[0].data.user.toString(), user.publicKey.toString());
+      assert.equal(events[0].data.amountIn.toNumber(), 10);
+      assert.equal(
+        events[0].data.reserveA.toNumber(),
+        Number(vaultAPDA.amount) - pool.protocolFeesA.toNumber()
+      );
+    } catch (error) {
+      throw new Error(`Error occured while testing swap event ${error}`);
+    }
+  });
+
+  it("Swap is guarded by the pool oracle", async () => {
+    const oracle = anchor.web3.Keypair.generate().publicKey;
+    const swapAccounts = {
+      user: user.publicKey,
+      userTokenA: userTokenA,
+      userTokenB: userTokenB,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: poolConfigPDA,
+
+      mintA: mintA,
+      mintB: mintB,
+
+      vaultA: vaultA,
+      vaultB: vaultB,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: observationsPDA,
+    };
+
+    try {
+      // set_oracle only queues the change, it lands with execute_change after the delay
+      await program.methods
+        .setOracle(oracle, 100, 50, new anchor.BN(60))
+        .accountsStrict(queueAccounts(poolConfigPDA))
+        .signers([admin])
+        .rpc();
+      const pending = await program.account.pendingChange.fetch(pendingChangePDA(poolConfigPDA));
+      assert.isDefined(pending.change.oracle);
+
+      await new Promise((resolve) => setTimeout(resolve, (timelockDelay + 1) * 1000));
+      await program.methods
+        .executeChange()
+        .accountsStrict(resolveAccounts(poolConfigPDA))
+        .signers([admin])
+        .rpc();
+
+      const pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(pool.oracle.toString(), oracle.toString());
+    } catch (error) {
+      throw new Error(`Error occured while setting the oracle ${error}`);
+    }
+
+    try {
+      // the oracle can't be skipped once the pool has one
+      await program.methods
+        .swap(true, new anchor.BN(10), new anchor.BN(0))
+        .accountsStrict({ ...swapAccounts, oracle: null, program: program.programId })
+        .signers([user])
+        .rpc();
+
+      assert.fail("swap without the oracle should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "OracleMismatch");
+    }
+
+    try {
+      // an account that is not a Pyth price feed
+      await program.methods
+        .swap(true, new anchor.BN(10), new anchor.BN(0))
+        .accountsStrict({ ...swapAccounts, oracle: oracle, program: program.programId })
+        .signers([user])
+        .rpc();
+
+      assert.fail("swap with an invalid oracle should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "OracleInvalid");
+    }
+
+    try {
+      await applyChange(poolConfigPDA, {
+        oracle: {
+          oracle: null,
+          maxDeviationBps: 0,
+          maxConfidenceBps: 0,
+          maxAge: new anchor.BN(0),
+        },
+      });
+    } catch (error) {
+      throw new Error(`Error occured while removing the oracle ${error}`);
+    }
+  });
+
+  it("LP share value goes up after swaps", async () => {
+    try {
+      const lpValue = async () => {
+        const vaultAPDA: Account = await getAccount(provider.connection, vaultA);
+        const vaultBPDA: Account = await getAccount(provider.connection, vaultB);
+        const lpMintAccount: Mint = await getMint(provider.connection, mintLP);
+
+        // sqrt(XY)/T: value of one lp token measured in pool liquidity
+        return (
+          Math.sqrt(Number(vaultAPDA.amount) * Number(vaultBPDA.amount)) /
+          Number(lpMintAccount.supply)
+        );
+      };
+
+      const valueBefore = await lpValue();
+
+      for (let i = 0; i < 3; i++) {
+        const userTokenBBefore = (
+          await getAccount(provider.connection, userTokenB)
+        ).amount;
+
+        await program.methods
+          .swap(true, new anchor.BN(100), new anchor.BN(0))
+          .accountsStrict({
+            user: user.publicKey,
+            userTokenA: userTokenA,
+            userTokenB: userTokenB,
+            globalConfig: globalConfigPDA,
+            poolConfigAccount: poolConfigPDA,
+
+            mintA: mintA,
+            mintB: mintB,
+
+            vaultA: vaultA,
+            vaultB: vaultB,
+
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+            eventAuthority: eventAuthority,
+            observations: observationsPDA,
+            oracle: null,
+            program: program.programId,
+          })
+          .signers([user])
+          .rpc();
+
+        const received =
+          Number((await getAccount(provider.connection, userTokenB)).amount) -
+          Number(userTokenBBefore);
+
+        // swap back everything we just received
+        await program.methods
+          .swap(false, new anchor.BN(received), new anchor.BN(0))
+          .accountsStrict({
+            user: user.publicKey,
+            userTokenA: userTokenA,
+            userTokenB: userTokenB,
+            globalConfig: globalConfigPDA,
+            poolConfigAccount: poolConfigPDA,
+
+            mintA: mintA,
+            mintB: mintB,
+
+            vaultA: vaultA,
+            vaultB: vaultB,
+
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+            eventAuthority: eventAuthority,
+            observations: observationsPDA,
+            oracle: null,
+            program: program.programId,
+          })
+          .signers([user])
+          .rpc();
+      }
+
+      const valueAfter = await lpValue();
 
-      console.log(`✨ setting-up test-case worked like a charm ✨`);
       console.log(
-        `🥳 The amount in vaultA is ${Number(
-          vaultAPDA.amount
-        )} and vaultB ${Number(vaultBPDA.amount)} 🥳`
+        `LP share value before swaps ${valueBefore} and after swaps ${valueAfter}`
       );
+      assert.isAbove(valueAfter, valueBefore);
     } catch (error) {
-      console.log(
-        `You got error while trying to test the withdraw asset ${error}`
+      throw new Error(`Error occured while testing lp share value ${error}`);
+    }
+  });
+
+  it("claim protocol fees to the treasury", async () => {
+    try {
+      // a big enough swap so the protocol share is not rounded away
+      await program.methods
+        .swap(true, new anchor.BN(1000), new anchor.BN(0))
+        .accountsStrict({
+          user: user.publicKey,
+          userTokenA: userTokenA,
+          userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: poolConfigPDA,
+
+          mintA: mintA,
+          mintB: mintB,
+
+          vaultA: vaultA,
+          vaultB: vaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          oracle: null,
+          program: program.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      // the protocol fees go to the global fee recipient, whoever owns the pool
+      const treasuryA = await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin,
+        mintA,
+        creationFeeRecipient
+      );
+      const treasuryB = await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin,
+        mintB,
+        creationFeeRecipient
+      );
+
+      const poolBefore = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.isAbove(poolBefore.protocolFeesA.toNumber(), 0);
+
+      const claimAccounts = {
+        mintA: mintA,
+        mintB: mintB,
+        globalConfig: globalConfigPDA,
+        poolConfigAccount: poolConfigPDA,
+
+        vaultA: vaultA,
+        vaultB: vaultB,
+
+        treasuryTokenA: treasuryA.address,
+        treasuryTokenB: treasuryB.address,
+
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+        eventAuthority: eventAuthority,
+        program: program.programId,
+      };
+
+      // only the pool owner is allowed to claim
+      let failed = false;
+      try {
+        await program.methods
+          .claimProtocolFees()
+          .accountsStrict({ owner: user.publicKey, ...claimAccounts })
+          .signers([user])
+          .rpc();
+      } catch (e) {
+        failed = true;
+      }
+      assert.isTrue(failed);
+
+      // nor can the owner send them to its own token accounts
+      const ownerTokenA = await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin,
+        mintA,
+        admin.publicKey
+      );
+      try {
+        await program.methods
+          .claimProtocolFees()
+          .accountsStrict({
+            owner: admin.publicKey,
+            ...claimAccounts,
+            treasuryTokenA: ownerTokenA.address,
+          })
+          .signers([admin])
+          .rpc();
+
+        assert.fail("claim to the owner should have failed");
+      } catch (e) {
+        console.log(`Dont worry this is suppoused to fail ${e}`);
+        assert.include(`${e}`, "InvalidTreasury");
+      }
+
+      await program.methods
+        .claimProtocolFees()
+        .accountsStrict({ owner: admin.publicKey, ...claimAccounts })
+        .signers([admin])
+        .rpc();
+
+      const poolAfter = await program.account.poolConfig.fetch(poolConfigPDA);
+      const treasuryAAfter = await getAccount(
+        provider.connection,
+        treasuryA.address
+      );
+      const treasuryBAfter = await getAccount(
+        provider.connection,
+        treasuryB.address
       );
+
+      assert.equal(
+        Number(treasuryAAfter.amount),
+        poolBefore.protocolFeesA.toNumber()
+      );
+      assert.equal(
+        Number(treasuryBAfter.amount),
+        poolBefore.protocolFeesB.toNumber()
+      );
+      assert.equal(poolAfter.protocolFeesA.toNumber(), 0);
+      assert.equal(poolAfter.protocolFeesB.toNumber(), 0);
+    } catch (error) {
+      throw new Error(`Error occured while testing protocol fee claim ${error}`);
+    }
+  });
+
+  it("grow the observation ring buffer and observe", async () => {
+    try {
+      await program.methods
+        .increaseObservationCardinality(10)
+        .accountsStrict({
+          payer: user.publicKey,
+          poolConfigAccount: poolConfigPDA,
+          observations: observationsPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const observations = await program.account.observations.fetch(
+        observationsPDA
+      );
+      assert.equal(observations.observations.length, 10);
+
+      const [now] = await program.methods
+        .observe([0])
+        .accountsStrict({
+          mintA: mintA,
+          mintB: mintB,
+          poolConfigAccount: poolConfigPDA,
+          observations: observationsPDA,
+          vaultA: vaultA,
+          vaultB: vaultB,
+        })
+        .view();
+
+      const latest = observations.observations[observations.index];
+      assert.isAtLeast(now.timestamp.toNumber(), latest.timestamp.toNumber());
+      assert.isTrue(now.liquidityCumulative.gte(latest.liquidityCumulative));
+    } catch (error) {
+      throw new Error(`Error occured while testing observations ${error}`);
+    }
+
+    const growAccounts = {
+      payer: user.publicKey,
+      poolConfigAccount: poolConfigPDA,
+      observations: observationsPDA,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    };
+
+    try {
+      await program.methods
+        .increaseObservationCardinality(MAX_OBSERVATION_CARDINALITY + 1)
+        .accountsStrict(growAccounts)
+        .signers([user])
+        .rpc();
+
+      assert.fail("growing past the max cardinality should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidCardinality");
+    }
+
+    try {
+      // anyone can grow the buffer to the max, withdrawals still have to fit in the heap
+      await program.methods
+        .increaseObservationCardinality(MAX_OBSERVATION_CARDINALITY)
+        .accountsStrict(growAccounts)
+        .signers([user])
+        .rpc();
+
+      const lqProviderLP = getAssociatedTokenAddressSync(mintLP, lqProvider.publicKey);
+      const lpBefore = (await getAccount(provider.connection, lqProviderLP)).amount;
+      await program.methods
+        .withdrawAsset(new anchor.BN(100), new anchor.BN(0), new anchor.BN(0))
+        .accountsStrict({
+          user: lqProvider.publicKey,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: poolConfigPDA,
+
+          mintA: mintA,
+          mintB: mintB,
+          mintLp: mintLP,
+
+          userTokenA: lqProviderA,
+          userTokenB: lqProviderB,
+          userTokenLp: lqProviderLP,
+
+          vaultA: vaultA,
+          vaultB: vaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          program: program.programId,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      const observations = await program.account.observations.fetch(observationsPDA);
+      assert.equal(observations.observations.length, MAX_OBSERVATION_CARDINALITY);
+      const lpAfter = (await getAccount(provider.connection, lqProviderLP)).amount;
+      assert.equal(Number(lpBefore - lpAfter), 100);
+    } catch (error) {
+      throw new Error(`Error occured while withdrawing from a full ring buffer ${error}`);
+    }
+  });
+
+  it("withdraw asset should fail below the minimum amounts", async () => {
+    try {
+      let lqProviderLPAccount: Account =
+        await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          lqProvider,
+          mintLP,
+          lqProvider.publicKey
+        );
+
+      // 100 lp tokens can never be worth the whole vault
+      await program.methods
+        .withdrawAsset(
+          new anchor.BN(100),
+          new anchor.BN(1_000_000),
+          new anchor.BN(0)
+        )
+        .accountsStrict({
+          user: lqProvider.publicKey,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: poolConfigPDA,
+
+          mintA: mintA,
+          mintB: mintB,
+          mintLp: mintLP,
+
+          userTokenA: lqProviderA,
+          userTokenB: lqProviderB,
+          userTokenLp: lqProviderLPAccount.address,
+
+          vaultA: vaultA,
+          vaultB: vaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          program: program.programId,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      assert.fail("withdraw should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "WithdrawSlippageExceeded");
+    }
+  });
+
+  it("withdraw asset", async () => {
+    try {
+      let lqProviderLPAccount: Account =
+        await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          lqProvider,
+          mintLP,
+          lqProvider.publicKey
+        );
+
+      let mintLPAccount: Mint = await getMint(provider.connection, mintLP);
+
+      console.log(
+        `The mint supply of token mint account is ${Number(
+          mintLPAccount.supply
+        )}`
+      );
+
+      await program.methods
+        .withdrawAsset(new anchor.BN(100), new anchor.BN(0), new anchor.BN(0))
+        .accountsStrict({
+          user: lqProvider.publicKey,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: poolConfigPDA,
+
+          mintA: mintA,
+          mintB: mintB,
+          mintLp: mintLP,
+
+          userTokenA: lqProviderA,
+          userTokenB: lqProviderB,
+          userTokenLp: lqProviderLPAccount.address,
+
+          vaultA: vaultA,
+          vaultB: vaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          program: program.programId,
+        })
+        .signers([lqProvider])
+        .rpc({
+          skipPreflight: true,
+        });
+
+      const vaultAPDA: Account = await getAccount(provider.connection, vaultA);
+      const vaultBPDA = await getAccount(provider.connection, vaultB);
+
+      console.log(`✨ setting-up test-case worked like a charm ✨`);
+      console.log(
+        `🥳 The amount in vaultA is ${Number(
+          vaultAPDA.amount
+        )} and vaultB ${Number(vaultBPDA.amount)} 🥳`
+      );
+    } catch (error) {
+      console.log(
+        `You got error while trying to test the withdraw asset ${error}`
+      );
+    }
+  });
+
+  it("StableSwap pool quotes close to 1:1", async () => {
+    const stableVaultA = getAssociatedTokenAddressSync(mintA, stablePool, true);
+    const stableVaultB = getAssociatedTokenAddressSync(mintB, stablePool, true);
+
+    const initAccounts = {
+      admin: admin.publicKey,
+
+      mintA: mintA,
+      mintB: mintB,
+      mintLp: stableMintLp,
+
+      vaultA: stableVaultA,
+      vaultB: stableVaultB,
+      poolConfigAccount: stablePool,
+      poolPair: poolPairPDA(mintA, mintB, stableFeeBps, CURVE_KIND.stableSwap),
+      ...creationFeeAccounts,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: stableObservations,
+      program: program.programId,
+    };
+
+    try {
+      await program.methods
+        .initialize(stableSeed, stableFeeBps, null, {
+          stableSwap: { amp: new BN(0) },
+        })
+        .accountsStrict(initAccounts)
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidAmp");
+    }
+
+    try {
+      await program.methods
+        .initialize(stableSeed, stableFeeBps, null, {
+          stableSwap: { amp: new BN(100) },
+        })
+        .accountsStrict(initAccounts)
+        .signers([admin])
+        .rpc();
+
+      await program.methods
+        .depositeAsset(
+          new anchor.BN(1_000_000),
+          new anchor.BN(1_000_000),
+          new anchor.BN(0)
+        )
+        .accountsPartial({
+          liquidProvider: lqProvider.publicKey,
+
+          poolConfigAccount: stablePool,
+
+          mintA: mintA,
+          mintB: mintB,
+          mintLp: stableMintLp,
+
+          providerTokenA: lqProviderA,
+          providerTokenB: lqProviderB,
+
+          vaultA: stableVaultA,
+          vaultB: stableVaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      const amountIn = 10_000;
+      const amountInAfterFee = Math.floor(
+        ((amountIn - Math.floor((amountIn * protocolFeeBps) / 10_000)) *
+          (10_000 - stableFeeBps)) /
+          10_000
+      );
+      // what the constant product curve would pay for the same trade
+      const constantProductOut = Math.floor(
+        (1_000_000 * amountInAfterFee) / (1_000_000 + amountInAfterFee)
+      );
+
+      const userTokenBBefore = await getAccount(provider.connection, userTokenB);
+
+      await program.methods
+        .swap(true, new anchor.BN(amountIn), new anchor.BN(0))
+        .accountsStrict({
+          user: user.publicKey,
+          userTokenA: userTokenA,
+          userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: stablePool,
+
+          mintA: mintA,
+          mintB: mintB,
+
+          vaultA: stableVaultA,
+          vaultB: stableVaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: stableObservations,
+          oracle: null,
+          program: program.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const userTokenBAfter = await getAccount(provider.connection, userTokenB);
+      const received =
+        Number(userTokenBAfter.amount) - Number(userTokenBBefore.amount);
+
+      const pool = await program.account.poolConfig.fetch(stablePool);
+      assert.equal(pool.curve.stableSwap.amp.toNumber(), 100);
+      assert.isAbove(received, constantProductOut);
+      assert.isAtMost(received, amountInAfterFee);
+    } catch (error) {
+      throw new Error(`Error while testing the StableSwap pool ${error}`);
+    }
+  });
+
+  it("ramp the StableSwap amp", async () => {
+    const rampAccounts = {
+      owner: admin.publicKey,
+      poolConfigAccount: stablePool,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    };
+    const now = Math.floor(Date.now() / 1000);
+
+    try {
+      // shorter than a day, the change is checked when it is queued
+      await program.methods
+        .queueChange({ rampAmp: { targetAmp: new BN(200), endTimestamp: new BN(now + 3_600) } })
+        .accountsStrict(queueAccounts(stablePool))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("ramp should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidRamp");
+    }
+
+    try {
+      // a day long when queued, but shorter than a day by the time the timelock lets it execute
+      await program.methods
+        .queueChange({
+          rampAmp: { targetAmp: new BN(200), endTimestamp: new BN(now + 86_400 + 30) },
+        })
+        .accountsStrict(queueAccounts(stablePool))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("ramp should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidRamp");
+    }
+
+    try {
+      // more than 10x the current amp, ramp_amp queues through the same checks
+      await program.methods
+        .rampAmp(new BN(1_001), new BN(now + 2 * 86_400))
+        .accountsStrict(queueAccounts(stablePool))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("ramp should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidRamp");
+    }
+
+    try {
+      // the constant product pool has no amp
+      await program.methods
+        .queueChange({
+          rampAmp: { targetAmp: new BN(200), endTimestamp: new BN(now + 2 * 86_400) },
+        })
+        .accountsStrict(queueAccounts(poolConfigPDA))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("ramp should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "NotStableSwap");
+    }
+
+    try {
+      await applyChange(stablePool, {
+        rampAmp: { targetAmp: new BN(1_000), endTimestamp: new BN(now + 2 * 86_400) },
+      });
+
+      let pool = await program.account.poolConfig.fetch(stablePool);
+      assert.equal(pool.curve.stableSwap.amp.toNumber(), 100);
+      assert.equal(pool.targetAmp.toNumber(), 1_000);
+      assert.equal(pool.rampStopTimestamp.toNumber(), now + 2 * 86_400);
+
+      await program.methods
+        .stopRampAmp()
+        .accountsStrict(rampAccounts)
+        .signers([admin])
+        .rpc();
+
+      // seconds into a two day ramp, A is frozen close to where it started
+      pool = await program.account.poolConfig.fetch(stablePool);
+      const frozenAmp = pool.curve.stableSwap.amp.toNumber();
+      assert.isAtLeast(frozenAmp, 100);
+      assert.isBelow(frozenAmp, 110);
+      assert.equal(pool.targetAmp.toNumber(), frozenAmp);
+      assert.equal(
+        pool.rampStartTimestamp.toNumber(),
+        pool.rampStopTimestamp.toNumber()
+      );
+    } catch (error) {
+      throw new Error(`Error while ramping the amp ${error}`);
+    }
+  });
+
+  it("weighted 80/20 pool prices by the weights", async () => {
+    const weightedSeed = new BN(randomBytes(8));
+    const weightedPool = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("liquid_pool"), weightedSeed.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+    const weightedObservations = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("observations"), weightedPool.toBuffer()],
+      program.programId
+    )[0];
+    const weightedMintLp = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_lp"), weightedPool.toBuffer()],
+      program.programId
+    )[0];
+    const weightedVaultA = getAssociatedTokenAddressSync(mintA, weightedPool, true);
+    const weightedVaultB = getAssociatedTokenAddressSync(mintB, weightedPool, true);
+
+    const initAccounts = {
+      admin: admin.publicKey,
+
+      mintA: mintA,
+      mintB: mintB,
+      mintLp: weightedMintLp,
+
+      vaultA: weightedVaultA,
+      vaultB: weightedVaultB,
+      poolConfigAccount: weightedPool,
+      poolPair: poolPairPDA(mintA, mintB, weightedFeeBps, CURVE_KIND.weighted),
+      ...creationFeeAccounts,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: weightedObservations,
+      program: program.programId,
+    };
+
+    try {
+      // weights must add up to 100%
+      await program.methods
+        .initialize(weightedSeed, weightedFeeBps, null, {
+          weighted: { weightA: 8_000, weightB: 1_000 },
+        })
+        .accountsStrict(initAccounts)
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidWeights");
+    }
+
+    try {
+      await program.methods
+        .initialize(weightedSeed, weightedFeeBps, null, {
+          weighted: { weightA: 8_000, weightB: 2_000 },
+        })
+        .accountsStrict(initAccounts)
+        .signers([admin])
+        .rpc();
+
+      // 80% of the value in A and 20% in B, so one A is worth one B
+      await program.methods
+        .depositeAsset(
+          new anchor.BN(4_000_000),
+          new anchor.BN(1_000_000),
+          new anchor.BN(0)
+        )
+        .accountsPartial({
+          liquidProvider: lqProvider.publicKey,
+
+          poolConfigAccount: weightedPool,
+
+          mintA: mintA,
+          mintB: mintB,
+          mintLp: weightedMintLp,
+
+          providerTokenA: lqProviderA,
+          providerTokenB: lqProviderB,
+
+          vaultA: weightedVaultA,
+          vaultB: weightedVaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      const amountIn = 1_000;
+      const amountInAfterFee = Math.floor(
+        ((amountIn - Math.floor((amountIn * protocolFeeBps) / 10_000)) *
+          (10_000 - weightedFeeBps)) /
+          10_000
+      );
+
+      const userTokenBBefore = await getAccount(provider.connection, userTokenB);
+
+      await program.methods
+        .swap(true, new anchor.BN(amountIn), new anchor.BN(0))
+        .accountsStrict({
+          user: user.publicKey,
+          userTokenA: userTokenA,
+          userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: weightedPool,
+
+          mintA: mintA,
+          mintB: mintB,
+
+          vaultA: weightedVaultA,
+          vaultB: weightedVaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: weightedObservations,
+          oracle: null,
+          program: program.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const userTokenBAfter = await getAccount(provider.connection, userTokenB);
+      const received =
+        Number(userTokenBAfter.amount) - Number(userTokenBBefore.amount);
+
+      // a 50/50 pool with these reserves would pay about a quarter of this
+      assert.isAtMost(received, amountInAfterFee);
+      assert.isAtLeast(received, amountInAfterFee - 2);
+    } catch (error) {
+      throw new Error(`Error while testing the weighted pool ${error}`);
+    }
+  });
+
+  it("concentrated pool swaps inside a position and pays it the fee", async () => {
+    const clSeed = new BN(randomBytes(8));
+    const clPool = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("concentrated_pool"), clSeed.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+    const clVaultA = getAssociatedTokenAddressSync(mintA, clPool, true);
+    const clVaultB = getAssociatedTokenAddressSync(mintB, clPool, true);
+
+    const tickBytes = (tick: number) => {
+      const buffer = Buffer.alloc(4);
+      buffer.writeInt32LE(tick);
+      return buffer;
+    };
+    const tickArrayPDA = (startTickIndex: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("tick_array"), clPool.toBuffer(), tickBytes(startTickIndex)],
+        program.programId
+      )[0];
+    const positionPDA = (tickLower: number, tickUpper: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("position"),
+          clPool.toBuffer(),
+          lqProvider.publicKey.toBuffer(),
+          tickBytes(tickLower),
+          tickBytes(tickUpper),
+        ],
+        program.programId
+      )[0];
+
+    // tick spacing 10, 32 ticks per array
+    const tickArrayLower = tickArrayPDA(-320);
+    const tickArrayUpper = tickArrayPDA(0);
+    const position = positionPDA(-100, 100);
+
+    const liquidityAccounts = {
+      owner: lqProvider.publicKey,
+      mintA: mintA,
+      mintB: mintB,
+      globalConfig: globalConfigPDA,
+      pool: clPool,
+      position: position,
+      tickArrayLower: tickArrayLower,
+      tickArrayUpper: tickArrayUpper,
+      ownerTokenA: lqProviderA,
+      ownerTokenB: lqProviderB,
+      vaultA: clVaultA,
+      vaultB: clVaultB,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    };
+
+    const initAccounts = (
+      fee: number = feeBps,
+      pool: anchor.web3.PublicKey = clPool,
+      mintY: anchor.web3.PublicKey = mintB
+    ) => ({
+      admin: admin.publicKey,
+      mintA: mintA,
+      mintB: mintY,
+      ...creationFeeAccounts,
+      pool,
+      poolPair: poolPairPDA(mintA, mintY, fee, CURVE_KIND.concentrated),
+      vaultA: getAssociatedTokenAddressSync(mintA, pool, true),
+      vaultB: getAssociatedTokenAddressSync(mintY, pool, true),
+      systemProgram: anchor.web3.SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+    const setPermissionless = (permissionless: boolean) =>
+      program.methods
+        .updateGlobalConfig(null, globalConfigParams(permissionless))
+        .accountsStrict({
+          admin: provider.wallet.publicKey,
+          globalConfig: globalConfigPDA,
+        })
+        .rpc();
+
+    // concentrated pools follow the same creation policy as initialize
+    try {
+      await program.methods
+        .initializeConcentratedPool(clSeed, 10, 31, new BN(1).shln(64))
+        .accountsStrict(initAccounts(31))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize concentrated pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "FeeTierNotAllowed");
+    }
+
+    try {
+      await program.methods
+        .initializeConcentratedPool(clSeed, 10, feeBps, new BN(1).shln(64))
+        .accountsStrict(initAccounts(feeBps, clPool, mintA))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize concentrated pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "IdenticalMints");
+    }
+
+    try {
+      await setPermissionless(false);
+
+      await program.methods
+        .initializeConcentratedPool(clSeed, 10, feeBps, new BN(1).shln(64))
+        .accountsStrict(initAccounts())
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize concentrated pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "PoolCreationRestricted");
+    }
+
+    try {
+      await setPermissionless(true);
+      const recipientBefore = await provider.connection.getBalance(
+        creationFeeRecipient
+      );
+
+      // price 1, sqrt price 2^64
+      await program.methods
+        .initializeConcentratedPool(clSeed, 10, feeBps, new BN(1).shln(64))
+        .accountsStrict(initAccounts())
+        .signers([admin])
+        .rpc();
+
+      assert.equal(
+        await provider.connection.getBalance(creationFeeRecipient),
+        recipientBefore + creationFee
+      );
+
+      const pair = await program.account.poolPair.fetch(
+        poolPairPDA(mintB, mintA, feeBps, CURVE_KIND.concentrated)
+      );
+      assert.equal(pair.pool.toString(), clPool.toString());
+      assert.equal(pair.curve, CURVE_KIND.concentrated);
+
+      for (const startTickIndex of [-320, 0]) {
+        await program.methods
+          .initializeTickArray(startTickIndex)
+          .accountsStrict({
+            payer: admin.publicKey,
+            globalConfig: globalConfigPDA,
+            pool: clPool,
+            tickArray: tickArrayPDA(startTickIndex),
+            systemProgram: anchor.web3.SystemProgram.programId,
+            eventAuthority: eventAuthority,
+            program: program.programId,
+          })
+          .signers([admin])
+          .rpc();
+      }
+    } catch (error) {
+      throw new Error(`Error while creating the concentrated pool ${error}`);
+    }
+
+    try {
+      // one concentrated pool per mint pair and fee tier, whatever the seed
+      const otherSeed = new BN(randomBytes(8));
+      const otherPool = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("concentrated_pool"), otherSeed.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      )[0];
+
+      await program.methods
+        .initializeConcentratedPool(otherSeed, 10, feeBps, new BN(1).shln(64))
+        .accountsStrict(initAccounts(feeBps, otherPool))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("initialize concentrated pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "already in use");
+    }
+
+    try {
+      // ticks must be multiples of the tick spacing
+      await program.methods
+        .openPosition(-105, 100)
+        .accountsStrict({
+          owner: lqProvider.publicKey,
+          globalConfig: globalConfigPDA,
+          pool: clPool,
+          position: positionPDA(-105, 100),
+          systemProgram: anchor.web3.SystemProgram.programId,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      assert.fail("open position should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidTickRange");
+    }
+
+    try {
+      await program.methods
+        .openPosition(-100, 100)
+        .accountsStrict({
+          owner: lqProvider.publicKey,
+          globalConfig: globalConfigPDA,
+          pool: clPool,
+          position: position,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      // about 0.5% of L on each side of price 1 for +-100 ticks
+      await program.methods
+        .increaseLiquidity(
+          new BN(1_000_000_000),
+          new BN(5_000_000),
+          new BN(5_000_000)
+        )
+        .accountsStrict(liquidityAccounts)
+        .signers([lqProvider])
+        .rpc();
+
+      let pool = await program.account.concentratedPool.fetch(clPool);
+      assert.equal(pool.liquidity.toString(), "1000000000");
+      const vaultA = await getAccount(provider.connection, clVaultA);
+      assert.isAtLeast(Number(vaultA.amount), 4_980_000);
+      assert.isAtMost(Number(vaultA.amount), 5_000_000);
+
+      const amountIn = 10_000;
+      const userTokenBBefore = await getAccount(provider.connection, userTokenB);
+
+      // the price starts on tick 0, so the walk down also needs the array below
+      const signature = await program.methods
+        .swapConcentrated(true, new BN(amountIn), new BN(0))
+        .accountsStrict({
+          user: user.publicKey,
+          mintA: mintA,
+          mintB: mintB,
+          userTokenA: userTokenA,
+          userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
+          pool: clPool,
+          vaultA: clVaultA,
+          vaultB: clVaultB,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .remainingAccounts(
+          [tickArrayUpper, tickArrayLower].map((pubkey) => ({
+            pubkey,
+            isSigner: false,
+            isWritable: true,
+          }))
+        )
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+      const userTokenBAfter = await getAccount(provider.connection, userTokenB);
+      const received =
+        Number(userTokenBAfter.amount) - Number(userTokenBBefore.amount);
+      const amountInAfterFee = (amountIn * (10_000 - feeBps)) / 10_000;
+      assert.isAtMost(received, amountInAfterFee);
+      assert.isAtLeast(received, amountInAfterFee - 100);
+
+      pool = await program.account.concentratedPool.fetch(clPool);
+      assert.isBelow(pool.tickCurrent, 0);
+
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const events = tx.meta.innerInstructions
+        .flatMap((inner) => inner.instructions)
+        .map((ix) => {
+          const data = anchor.utils.bytes.bs58.decode(ix.data);
+          return program.coder.events.decode(
+            anchor.utils.bytes.base64.encode(data.subarray(8))
+          );
+        })
+        .filter((event) => event !== null);
+
+      assert.equal(events.length, 1);
+      assert.equal(events[0].name, "concentratedSwapped");
+      assert.equal(events[0].data.amountOut.toNumber(), received);
+      assert.equal(events[0].data.tickCurrent, pool.tickCurrent);
+      assert.equal(
+        events[0].data.sqrtPriceX64.toString(),
+        pool.sqrtPriceX64.toString()
+      );
+
+      // the only position in range earned the whole fee
+      const providerABefore = await getAccount(provider.connection, lqProviderA);
+      await program.methods
+        .collectFees()
+        .accountsStrict(liquidityAccounts)
+        .signers([lqProvider])
+        .rpc();
+      const providerAAfter = await getAccount(provider.connection, lqProviderA);
+      const fees =
+        Number(providerAAfter.amount) - Number(providerABefore.amount);
+      const expectedFee = (amountIn * feeBps) / 10_000;
+      assert.isAtLeast(fees, expectedFee - 1);
+      assert.isAtMost(fees, expectedFee + 1);
+    } catch (error) {
+      throw new Error(`Error while testing the concentrated pool ${error}`);
+    }
+
+    const setStatus = (status: any) =>
+      program.methods
+        .setConcentratedPoolStatus(status)
+        .accountsStrict({
+          authority: guardian.publicKey,
+          globalConfig: globalConfigPDA,
+          pool: clPool,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([guardian])
+        .rpc();
+
+    try {
+      await setStatus({ swapsPaused: {} });
+
+      await program.methods
+        .swapConcentrated(true, new BN(1_000), new BN(0))
+        .accountsStrict({
+          user: user.publicKey,
+          mintA: mintA,
+          mintB: mintB,
+          userTokenA: userTokenA,
+          userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
+          pool: clPool,
+          vaultA: clVaultA,
+          vaultB: clVaultB,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .remainingAccounts(
+          [tickArrayLower, tickArrayUpper].map((pubkey) => ({
+            pubkey,
+            isSigner: false,
+            isWritable: true,
+          }))
+        )
+        .signers([user])
+        .rpc();
+
+      assert.fail("swap on a paused concentrated pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "SwapsPaused");
+    }
+
+    try {
+      await setStatus({ withdrawOnly: {} });
+
+      await program.methods
+        .openPosition(-200, 200)
+        .accountsStrict({
+          owner: lqProvider.publicKey,
+          globalConfig: globalConfigPDA,
+          pool: clPool,
+          position: positionPDA(-200, 200),
+          systemProgram: anchor.web3.SystemProgram.programId,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      assert.fail("open position on a withdraw only pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "WithdrawOnly");
+    }
+
+    try {
+      await program.methods
+        .initializeTickArray(320)
+        .accountsStrict({
+          payer: admin.publicKey,
+          globalConfig: globalConfigPDA,
+          pool: clPool,
+          tickArray: tickArrayPDA(320),
+          systemProgram: anchor.web3.SystemProgram.programId,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+      assert.fail("tick array on a withdraw only pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "WithdrawOnly");
+    }
+
+    try {
+      // the LP can still leave a withdraw only pool
+      await program.methods
+        .decreaseLiquidity(new BN(500_000_000), new BN(0), new BN(0))
+        .accountsStrict(liquidityAccounts)
+        .signers([lqProvider])
+        .rpc();
+
+      await setStatus({ active: {} });
+      const pool = await program.account.concentratedPool.fetch(clPool);
+      assert.deepEqual(pool.status, { active: {} });
+      assert.equal(pool.liquidity.toString(), "500000000");
+    } catch (error) {
+      throw new Error(`Error while testing the concentrated pool status ${error}`);
+    }
+  });
+
+  it("swap_route goes A to B to A through two pools", async () => {
+    const stableVaultA = getAssociatedTokenAddressSync(mintA, stablePool, true);
+    const stableVaultB = getAssociatedTokenAddressSync(mintB, stablePool, true);
+
+    // pool_config_account, observations, mint_a, mint_b, vault_a, vault_b, oracle
+    const hop = (
+      pool: anchor.web3.PublicKey,
+      observations: anchor.web3.PublicKey,
+      hopVaultA: anchor.web3.PublicKey,
+      hopVaultB: anchor.web3.PublicKey
+    ) =>
+      [
+        { pubkey: pool, isWritable: true },
+        { pubkey: observations, isWritable: true },
+        { pubkey: mintA, isWritable: false },
+        { pubkey: mintB, isWritable: false },
+        { pubkey: hopVaultA, isWritable: true },
+        { pubkey: hopVaultB, isWritable: true },
+        { pubkey: program.programId, isWritable: false }, // neither pool has an oracle
+      ].map((meta) => ({ ...meta, isSigner: false }));
+
+    const route = [
+      ...hop(poolConfigPDA, observationsPDA, vaultA, vaultB),
+      ...hop(stablePool, stableObservations, stableVaultA, stableVaultB),
+    ];
+    const routeAccounts = (mintOut: anchor.web3.PublicKey, userTokenOut: anchor.web3.PublicKey) => ({
+      user: user.publicKey,
+      mintIn: mintA,
+      mintOut: mintOut,
+      userTokenIn: userTokenA,
+      userTokenOut: userTokenOut,
+      globalConfig: globalConfigPDA,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+
+    try {
+      // the route ends in A, not B
+      await program.methods
+        .swapRoute(new anchor.BN(1_000), new anchor.BN(0))
+        .accountsStrict(routeAccounts(mintB, userTokenB))
+        .remainingAccounts(route)
+        .signers([user])
+        .rpc();
+
+      assert.fail("route should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidRoute");
+    }
+
+    try {
+      // two fees on the way, 1000 A can't come back as 1000 A
+      await program.methods
+        .swapRoute(new anchor.BN(1_000), new anchor.BN(1_000))
+        .accountsStrict(routeAccounts(mintA, userTokenA))
+        .remainingAccounts(route)
+        .signers([user])
+        .rpc();
+
+      assert.fail("route should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "SlippageLimitExceeded");
+    }
+
+    try {
+      const balance = async (account: anchor.web3.PublicKey) =>
+        Number((await getAccount(provider.connection, account)).amount);
+
+      const userABefore = await balance(userTokenA);
+      const userBBefore = await balance(userTokenB);
+      const vaultBBefore = await balance(vaultB);
+      const stableVaultBBefore = await balance(stableVaultB);
+
+      await program.methods
+        .swapRoute(new anchor.BN(1_000), new anchor.BN(0))
+        .accountsStrict(routeAccounts(mintA, userTokenA))
+        .remainingAccounts(route)
+        .signers([user])
+        .rpc();
+
+      // the B in the middle moved from one vault to the other without touching the user
+      const intermediate = vaultBBefore - (await balance(vaultB));
+      assert.isAbove(intermediate, 0);
+      assert.equal((await balance(stableVaultB)) - stableVaultBBefore, intermediate);
+      assert.equal(await balance(userTokenB), userBBefore);
+
+      const roundTripLoss = userABefore - (await balance(userTokenA));
+      assert.isAbove(roundTripLoss, 0);
+      assert.isBelow(roundTripLoss, 1_000);
+    } catch (error) {
+      throw new Error(`Error while routing through two pools ${error}`);
+    }
+  });
+
+  it("flash loan has to be repaid in the same transaction", async () => {
+    const flashAccounts = {
+      borrower: lqProvider.publicKey,
+      mintA: mintA,
+      mintB: mintB,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: poolConfigPDA,
+      borrowerTokenA: lqProviderA,
+      borrowerTokenB: lqProviderB,
+      vaultA: vaultA,
+      vaultB: vaultB,
+      instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    };
+    const borrowIx = await program.methods
+      .flashBorrow(new anchor.BN(1_000), new anchor.BN(0))
+      .accountsStrict(flashAccounts)
+      .instruction();
+    const repayIx = await program.methods
+      .flashRepay()
+      .accountsStrict(flashAccounts)
+      .instruction();
+
+    try {
+      await program.methods
+        .flashBorrow(new anchor.BN(1_000), new anchor.BN(0))
+        .accountsStrict(flashAccounts)
+        .signers([lqProvider])
+        .rpc();
+
+      assert.fail("borrow without repay should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "FlashRepayMissing");
+    }
+
+    try {
+      // the vaults are short in between, so the pool can't be traded
+      await program.methods
+        .swap(true, new anchor.BN(10), new anchor.BN(0))
+        .accountsStrict({
+          user: user.publicKey,
+          userTokenA: userTokenA,
+          userTokenB: userTokenB,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: poolConfigPDA,
+
+          mintA: mintA,
+          mintB: mintB,
+
+          vaultA: vaultA,
+          vaultB: vaultB,
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          observations: observationsPDA,
+          oracle: null,
+          program: program.programId,
+        })
+        .preInstructions([borrowIx])
+        .postInstructions([repayIx])
+        .signers([user, lqProvider])
+        .rpc();
+
+      assert.fail("swap during a flash loan should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "FlashLoanOutstanding");
+    }
+
+    try {
+      const vaultABefore = await getAccount(provider.connection, vaultA);
+
+      await program.methods
+        .flashBorrow(new anchor.BN(1_000), new anchor.BN(0))
+        .accountsStrict(flashAccounts)
+        .postInstructions([repayIx])
+        .signers([lqProvider])
+        .rpc();
+
+      // 0.3% of 1000, rounded up
+      const vaultAAfter = await getAccount(provider.connection, vaultA);
+      assert.equal(Number(vaultAAfter.amount) - Number(vaultABefore.amount), 3);
+
+      const pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(pool.flashLoanA.toNumber(), 0);
+      assert.equal(pool.flashLoanB.toNumber(), 0);
+    } catch (error) {
+      throw new Error(`Error while testing the flash loan ${error}`);
+    }
+  });
+
+  it("flash loan is only repaid by a flash_repay naming the pool", async () => {
+    const flashAccounts = (pool: anchor.web3.PublicKey) => ({
+      borrower: lqProvider.publicKey,
+      mintA: mintA,
+      mintB: mintB,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: pool,
+      borrowerTokenA: lqProviderA,
+      borrowerTokenB: lqProviderB,
+      vaultA: getAssociatedTokenAddressSync(mintA, pool, true),
+      vaultB: getAssociatedTokenAddressSync(mintB, pool, true),
+      instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+    const borrowIx = (pool: anchor.web3.PublicKey) =>
+      program.methods
+        .flashBorrow(new anchor.BN(1), new anchor.BN(0))
+        .accountsStrict(flashAccounts(pool))
+        .instruction();
+
+    try {
+      // the only repay is on the stable pool, the CP pool just rides along as a remaining account
+      const repayStableIx = await program.methods
+        .flashRepay()
+        .accountsStrict(flashAccounts(stablePool))
+        .remainingAccounts([{ pubkey: poolConfigPDA, isSigner: false, isWritable: false }])
+        .instruction();
+
+      await program.methods
+        .flashBorrow(new anchor.BN(1_000), new anchor.BN(0))
+        .accountsStrict(flashAccounts(poolConfigPDA))
+        .preInstructions([await borrowIx(stablePool)])
+        .postInstructions([repayStableIx])
+        .signers([lqProvider])
+        .rpc();
+
+      assert.fail("cross pool repay should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "FlashRepayMissing");
+    }
+
+    try {
+      const repayIx = await program.methods
+        .flashRepay()
+        .accountsStrict(flashAccounts(poolConfigPDA))
+        .instruction();
+
+      await program.methods
+        .flashBorrow(new anchor.BN(1_000), new anchor.BN(0))
+        .accountsStrict(flashAccounts(poolConfigPDA))
+        .postInstructions([repayIx, repayIx])
+        .signers([lqProvider])
+        .rpc();
+
+      assert.fail("second repay should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "DuplicateFlashRepay");
+    }
+
+    const pool = await program.account.poolConfig.fetch(poolConfigPDA);
+    assert.equal(pool.flashLoanA.toNumber(), 0);
+  });
+
+  it("flash swap pays the output before the callback pays the input", async () => {
+    const swapAccounts = {
+      user: user.publicKey,
+      userTokenA: userTokenA,
+      userTokenB: userTokenB,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: poolConfigPDA,
+
+      mintA: mintA,
+      mintB: mintB,
+
+      vaultA: vaultA,
+      vaultB: vaultB,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: observationsPDA,
+      oracle: null,
+      program: program.programId,
+    };
+
+    // the callback is a plain token transfer of amountIn from the user into vault A
+    const callback = (amountIn: number) => {
+      const transfer = createTransferInstruction(
+        userTokenA,
+        vaultA,
+        user.publicKey,
+        amountIn
+      );
+      return {
+        accounts: [
+          { pubkey: transfer.programId, isSigner: false, isWritable: false },
+          ...transfer.keys,
+        ],
+        data: transfer.data,
+      };
+    };
+
+    try {
+      // one unit in can't buy 100 out
+      const underpaid = callback(1);
+      await program.methods
+        .flashSwap(true, new anchor.BN(100), underpaid.data)
+        .accountsStrict(swapAccounts)
+        .remainingAccounts(underpaid.accounts)
+        .signers([user])
+        .rpc();
+
+      assert.fail("flash swap should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "FlashSwapNotRepaid");
+    }
+
+    try {
+      const userTokenBBefore = await getAccount(provider.connection, userTokenB);
+      const vaultABefore = await getAccount(provider.connection, vaultA);
+
+      const paid = callback(1_000);
+      await program.methods
+        .flashSwap(true, new anchor.BN(100), paid.data)
+        .accountsStrict(swapAccounts)
+        .remainingAccounts(paid.accounts)
+        .signers([user])
+        .rpc();
+
+      const userTokenBAfter = await getAccount(provider.connection, userTokenB);
+      const vaultAAfter = await getAccount(provider.connection, vaultA);
+      assert.equal(
+        Number(userTokenBAfter.amount) - Number(userTokenBBefore.amount),
+        100
+      );
+      assert.equal(Number(vaultAAfter.amount) - Number(vaultABefore.amount), 1_000);
+    } catch (error) {
+      throw new Error(`Error while testing the flash swap ${error}`);
+    }
+  });
+
+  it("pool status and the kill switch leave only withdrawals open", async () => {
+    const swapAccounts = {
+      user: user.publicKey,
+      userTokenA: userTokenA,
+      userTokenB: userTokenB,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: poolConfigPDA,
+
+      mintA: mintA,
+      mintB: mintB,
+
+      vaultA: vaultA,
+      vaultB: vaultB,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: observationsPDA,
+      oracle: null,
+      program: program.programId,
+    };
+    const withdrawAccounts = {
+      user: lqProvider.publicKey,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: poolConfigPDA,
+
+      mintA: mintA,
+      mintB: mintB,
+      mintLp: mintLP,
+
+      userTokenA: lqProviderA,
+      userTokenB: lqProviderB,
+      userTokenLp: getAssociatedTokenAddressSync(mintLP, lqProvider.publicKey),
+
+      vaultA: vaultA,
+      vaultB: vaultB,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      observations: observationsPDA,
+      program: program.programId,
+    };
+    const statusAccounts = (authority: anchor.web3.PublicKey) => ({
+      authority,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: poolConfigPDA,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+    const pauseAccounts = (authority: anchor.web3.PublicKey) => ({
+      authority,
+      globalConfig: globalConfigPDA,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+
+    const expectSwapError = async (name: string) => {
+      try {
+        await program.methods
+          .swap(true, new anchor.BN(10), new anchor.BN(0))
+          .accountsStrict(swapAccounts)
+          .signers([user])
+          .rpc();
+
+        assert.fail("swap should have failed");
+      } catch (error) {
+        console.log(`Dont worry this is suppoused to fail ${error}`);
+        assert.include(`${error}`, name);
+      }
+    };
+
+    const withdraw = () =>
+      program.methods
+        .withdrawAsset(new anchor.BN(10), new anchor.BN(0), new anchor.BN(0))
+        .accountsStrict(withdrawAccounts)
+        .signers([lqProvider])
+        .rpc();
+
+    try {
+      // only the pool owner or the guardian
+      await program.methods
+        .setPoolStatus({ swapsPaused: {} })
+        .accountsStrict(statusAccounts(user.publicKey))
+        .signers([user])
+        .rpc();
+
+      assert.fail("set pool status should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "Unauthorized");
+    }
+
+    try {
+      await program.methods
+        .setPoolStatus({ swapsPaused: {} })
+        .accountsStrict(statusAccounts(admin.publicKey))
+        .signers([admin])
+        .rpc();
+      await expectSwapError("SwapsPaused");
+
+      await program.methods
+        .setPoolStatus({ withdrawOnly: {} })
+        .accountsStrict(statusAccounts(guardian.publicKey))
+        .signers([guardian])
+        .rpc();
+      await expectSwapError("WithdrawOnly");
+      await withdraw();
+
+      await program.methods
+        .setPoolStatus({ active: {} })
+        .accountsStrict(statusAccounts(guardian.publicKey))
+        .signers([guardian])
+        .rpc();
+
+      // the kill switch stops every pool, whatever its own status
+      await program.methods
+        .setProtocolPause(true)
+        .accountsStrict(pauseAccounts(guardian.publicKey))
+        .signers([guardian])
+        .rpc();
+      await expectSwapError("ProtocolPaused");
+      await withdraw();
+
+      await program.methods
+        .setProtocolPause(false)
+        .accountsStrict(pauseAccounts(provider.wallet.publicKey))
+        .rpc();
+
+      const pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.deepEqual(pool.status, { active: {} });
+    } catch (error) {
+      throw new Error(`Error while testing the pool status ${error}`);
+    }
+  });
+
+  it("ownership moves in two steps and can be renounced", async () => {
+    const ownerAccounts = (owner: anchor.web3.PublicKey, pool: anchor.web3.PublicKey) => ({
+      owner,
+      poolConfigAccount: pool,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+    const renounceAccounts = (owner: anchor.web3.PublicKey, pool: anchor.web3.PublicKey) => ({
+      ...ownerAccounts(owner, pool),
+      pendingChange: pendingChangePDA(pool),
+    });
+    const acceptAccounts = (newOwner: anchor.web3.PublicKey) => ({
+      newOwner,
+      poolConfigAccount: poolConfigPDA,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+
+    try {
+      await program.methods
+        .proposeOwner(user.publicKey)
+        .accountsStrict(ownerAccounts(user.publicKey, poolConfigPDA))
+        .signers([user])
+        .rpc();
+
+      assert.fail("propose owner should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "Unauthorized");
+    }
+
+    try {
+      await program.methods
+        .proposeOwner(user.publicKey)
+        .accountsStrict(ownerAccounts(admin.publicKey, poolConfigPDA))
+        .signers([admin])
+        .rpc();
+
+      // the proposal alone changes nothing
+      let pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(pool.owner.toString(), admin.publicKey.toString());
+      assert.equal(pool.pendingOwner.toString(), user.publicKey.toString());
+    } catch (error) {
+      throw new Error(`Error while proposing the owner ${error}`);
+    }
+
+    try {
+      await program.methods
+        .acceptOwner()
+        .accountsStrict(acceptAccounts(lqProvider.publicKey))
+        .signers([lqProvider])
+        .rpc();
+
+      assert.fail("accept owner should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "NotPendingOwner");
+    }
+
+    try {
+      await program.methods
+        .acceptOwner()
+        .accountsStrict(acceptAccounts(user.publicKey))
+        .signers([user])
+        .rpc();
+
+      let pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(pool.owner.toString(), user.publicKey.toString());
+      assert.isNull(pool.pendingOwner);
+
+      // and back to the admin for the rest of the tests
+      await program.methods
+        .proposeOwner(admin.publicKey)
+        .accountsStrict(ownerAccounts(user.publicKey, poolConfigPDA))
+        .signers([user])
+        .rpc();
+      await program.methods
+        .acceptOwner()
+        .accountsStrict(acceptAccounts(admin.publicKey))
+        .signers([admin])
+        .rpc();
+
+      pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(pool.owner.toString(), admin.publicKey.toString());
+    } catch (error) {
+      throw new Error(`Error while accepting the owner ${error}`);
+    }
+
+    // the stable pool gives up its owner, its fees are claimed first
+    try {
+      await program.methods
+        .renounceOwnership()
+        .accountsStrict(renounceAccounts(admin.publicKey, stablePool))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("renounce should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "UnclaimedProtocolFees");
+    }
+
+    try {
+      await program.methods
+        .claimProtocolFees()
+        .accountsStrict({
+          owner: admin.publicKey,
+          mintA: mintA,
+          mintB: mintB,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: stablePool,
+
+          vaultA: getAssociatedTokenAddressSync(mintA, stablePool, true),
+          vaultB: getAssociatedTokenAddressSync(mintB, stablePool, true),
+
+          treasuryTokenA: getAssociatedTokenAddressSync(mintA, creationFeeRecipient),
+          treasuryTokenB: getAssociatedTokenAddressSync(mintB, creationFeeRecipient),
+
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([admin])
+        .rpc();
+    } catch (error) {
+      throw new Error(`Error while claiming the stable pool fees ${error}`);
+    }
+
+    // a queued change could never be executed or cancelled without an owner
+    try {
+      await program.methods
+        .queueChange({
+          oracle: {
+            oracle: null,
+            maxDeviationBps: 0,
+            maxConfidenceBps: 0,
+            maxAge: new anchor.BN(0),
+          },
+        })
+        .accountsStrict(queueAccounts(stablePool))
+        .signers([admin])
+        .rpc();
+
+      await program.methods
+        .renounceOwnership()
+        .accountsStrict(renounceAccounts(admin.publicKey, stablePool))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("renounce should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "ChangePending");
+    }
+
+    try {
+      await program.methods
+        .cancelChange()
+        .accountsStrict(resolveAccounts(stablePool))
+        .signers([admin])
+        .rpc();
+
+      // a protocol fee change queued by the global admin sits in its own slot, it doesn't hold up the renounce
+      await program.methods
+        .queueChange({ protocolFee: { protocolFeeBps: 10 } })
+        .accountsStrict(queueAccounts(stablePool, provider.wallet.publicKey, CHANGE_SLOT.admin))
+        .rpc();
+
+      await program.methods
+        .renounceOwnership()
+        .accountsStrict(renounceAccounts(admin.publicKey, stablePool))
+        .signers([admin])
+        .rpc();
+
+      const pool = await program.account.poolConfig.fetch(stablePool);
+      assert.isNull(pool.owner);
+      assert.equal(pool.protocolFeeBps, 0);
+
+      // it can never execute now, the global admin takes the rent back
+      await program.methods
+        .cancelChange()
+        .accountsStrict(resolveAccounts(stablePool, provider.wallet.publicKey, CHANGE_SLOT.admin))
+        .rpc();
+    } catch (error) {
+      throw new Error(`Error while renouncing the ownership ${error}`);
+    }
+
+    try {
+      await program.methods
+        .proposeOwner(admin.publicKey)
+        .accountsStrict(ownerAccounts(admin.publicKey, stablePool))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("propose owner should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "Unauthorized");
+    }
+  });
+
+  it("pool parameter changes wait out the timelock", async () => {
+    const globalAdmin = provider.wallet.publicKey;
+
+    try {
+      // the swap fee plus the protocol fee may not go over the max fee
+      await program.methods
+        .queueChange({ protocolFee: { protocolFeeBps: 1_000 } })
+        .accountsStrict(queueAccounts(poolConfigPDA, globalAdmin, CHANGE_SLOT.admin))
+        .rpc();
+
+      assert.fail("queue change should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "InvalidFee");
+    }
+
+    // the protocol fee is the global admin's, neither the pool owner nor anyone else can move it
+    for (const signer of [admin, user]) {
+      try {
+        await program.methods
+          .queueChange({ protocolFee: { protocolFeeBps: 10 } })
+          .accountsStrict(queueAccounts(poolConfigPDA, signer.publicKey, CHANGE_SLOT.admin))
+          .signers([signer])
+          .rpc();
+
+        assert.fail("queue change should have failed");
+      } catch (error) {
+        console.log(`Dont worry this is suppoused to fail ${error}`);
+        assert.include(`${error}`, "Unauthorized");
+      }
+    }
+
+    try {
+      await program.methods
+        .queueChange({ protocolFee: { protocolFeeBps: 10 } })
+        .accountsStrict(queueAccounts(poolConfigPDA, globalAdmin, CHANGE_SLOT.admin))
+        .rpc();
+
+      const pending = await program.account.pendingChange.fetch(
+        pendingChangePDA(poolConfigPDA, CHANGE_SLOT.admin)
+      );
+      assert.equal(
+        pending.executableAt.toNumber() - pending.queuedAt.toNumber(),
+        timelockDelay
+      );
+    } catch (error) {
+      throw new Error(`Error while queueing the change ${error}`);
+    }
+
+    try {
+      await program.methods
+        .executeChange()
+        .accountsStrict(resolveAccounts(poolConfigPDA, globalAdmin, CHANGE_SLOT.admin))
+        .rpc();
+
+      assert.fail("execute change should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "ChangeNotReady");
+    }
+
+    try {
+      // one change at a time
+      await program.methods
+        .queueChange({ protocolFee: { protocolFeeBps: 25 } })
+        .accountsStrict(queueAccounts(poolConfigPDA, globalAdmin, CHANGE_SLOT.admin))
+        .rpc();
+
+      assert.fail("queue change should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "already in use");
+    }
+
+    try {
+      // the pool owner can't cancel the global admin's change either
+      await program.methods
+        .cancelChange()
+        .accountsStrict(resolveAccounts(poolConfigPDA, admin.publicKey, CHANGE_SLOT.admin))
+        .signers([admin])
+        .rpc();
+
+      assert.fail("cancel change should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "Unauthorized");
+    }
+
+    try {
+      await program.methods
+        .cancelChange()
+        .accountsStrict(resolveAccounts(poolConfigPDA, globalAdmin, CHANGE_SLOT.admin))
+        .rpc();
+      assert.isNull(
+        await provider.connection.getAccountInfo(pendingChangePDA(poolConfigPDA, CHANGE_SLOT.admin))
+      );
+
+      await applyChange(poolConfigPDA, { protocolFee: { protocolFeeBps: 25 } }, null);
+
+      const pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(pool.protocolFeeBps, 25);
+      assert.isNull(
+        await provider.connection.getAccountInfo(pendingChangePDA(poolConfigPDA, CHANGE_SLOT.admin))
+      );
+    } catch (error) {
+      throw new Error(`Error while executing the change ${error}`);
+    }
+  });
+
+  it("the global admin and the pool owner queue changes on the same pool", async () => {
+    const globalAdmin = provider.wallet.publicKey;
+    const before = await program.account.poolConfig.fetch(poolConfigPDA);
+
+    try {
+      // a pending protocol fee change doesn't hold up the pool owner's changes, and the other way round
+      await program.methods
+        .queueChange({ protocolFee: { protocolFeeBps: 30 } })
+        .accountsStrict(queueAccounts(poolConfigPDA, globalAdmin, CHANGE_SLOT.admin))
+        .rpc();
+      await program.methods
+        .setOracle(
+          before.oracle,
+          before.oracleMaxDeviationBps,
+          before.oracleMaxConfidenceBps,
+          before.oracleMaxAge
+        )
+        .accountsStrict(queueAccounts(poolConfigPDA))
+        .signers([admin])
+        .rpc();
+
+      const adminChange = await program.account.pendingChange.fetch(
+        pendingChangePDA(poolConfigPDA, CHANGE_SLOT.admin)
+      );
+      const ownerChange = await program.account.pendingChange.fetch(
+        pendingChangePDA(poolConfigPDA, CHANGE_SLOT.owner)
+      );
+      assert.isDefined(adminChange.change.protocolFee);
+      assert.isDefined(ownerChange.change.oracle);
+    } catch (error) {
+      throw new Error(`Error while queueing the changes ${error}`);
+    }
+
+    try {
+      // neither can resolve the other's change
+      await program.methods
+        .cancelChange()
+        .accountsStrict(resolveAccounts(poolConfigPDA, globalAdmin))
+        .rpc();
+
+      assert.fail("cancel change should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "Unauthorized");
+    }
+
+    try {
+      await program.methods
+        .cancelChange()
+        .accountsStrict(resolveAccounts(poolConfigPDA))
+        .signers([admin])
+        .rpc();
+
+      await new Promise((resolve) => setTimeout(resolve, (timelockDelay + 1) * 1000));
+      await program.methods
+        .executeChange()
+        .accountsStrict(resolveAccounts(poolConfigPDA, globalAdmin, CHANGE_SLOT.admin))
+        .rpc();
+
+      const pool = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(pool.protocolFeeBps, 30);
+      assert.equal(pool.oracle?.toString(), before.oracle?.toString());
+      assert.isNull(
+        await provider.connection.getAccountInfo(pendingChangePDA(poolConfigPDA, CHANGE_SLOT.admin))
+      );
+      assert.isNull(
+        await provider.connection.getAccountInfo(pendingChangePDA(poolConfigPDA, CHANGE_SLOT.owner))
+      );
+    } catch (error) {
+      throw new Error(`Error while resolving the changes ${error}`);
+    }
+  });
+
+  it("close a drained pool and get the rent back", async () => {
+    // fresh mints, so the pair and fee tier registry has room for this pool
+    const mintC = await createMintAccount(provider, admin);
+    const mintD = await createMintAccount(provider, admin);
+    const lqProviderC = await createATA(provider, lqProvider, mintC, 0, admin);
+    const lqProviderD = await createATA(provider, lqProvider, mintD, 0, admin);
+    await mintTokens(provider, admin, mintC, lqProviderC, 10, 6, admin);
+    await mintTokens(provider, admin, mintD, lqProviderD, 10, 6, admin);
+
+    const closeSeed = new BN(randomBytes(8));
+    const closePool = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("liquid_pool"), closeSeed.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+    const closeMintLp = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_lp"), closePool.toBuffer()],
+      program.programId
+    )[0];
+    const closeObservations = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("observations"), closePool.toBuffer()],
+      program.programId
+    )[0];
+    const closeVaultC = getAssociatedTokenAddressSync(mintC, closePool, true);
+    const closeVaultD = getAssociatedTokenAddressSync(mintD, closePool, true);
+
+    const poolAccounts = {
+      mintA: mintC,
+      mintB: mintD,
+      mintLp: closeMintLp,
+      poolConfigAccount: closePool,
+      vaultA: closeVaultC,
+      vaultB: closeVaultD,
+      observations: closeObservations,
+
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    };
+    const closeLockedLpAuthority = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("locked_lp"), closePool.toBuffer()],
+      program.programId
+    )[0];
+    // what the locked liquidity leaves behind goes to the protocol, not the owner
+    const [treasuryC, treasuryD] = await Promise.all(
+      [mintC, mintD].map(async (mint) => {
+        const treasury = await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          admin,
+          mint,
+          creationFeeRecipient
+        );
+        return treasury.address;
+      })
+    );
+    const closeAccounts = {
+      ...poolAccounts,
+      owner: admin.publicKey,
+      globalConfig: globalConfigPDA,
+      poolPair: poolPairPDA(mintC, mintD, feeBps),
+      lockedLpAuthority: closeLockedLpAuthority,
+      lockedLpToken: getAssociatedTokenAddressSync(closeMintLp, closeLockedLpAuthority, true),
+      treasuryTokenA: treasuryC,
+      treasuryTokenB: treasuryD,
+    };
+    const statusAccounts = (authority: anchor.web3.PublicKey) => ({
+      authority,
+      globalConfig: globalConfigPDA,
+      poolConfigAccount: closePool,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+
+    try {
+      await program.methods
+        .initialize(closeSeed, feeBps, null, { constantProduct: {} })
+        .accountsStrict({
+          ...poolAccounts,
+          admin: admin.publicKey,
+          poolPair: poolPairPDA(mintC, mintD, feeBps),
+          ...creationFeeAccounts,
+        })
+        .signers([admin])
+        .rpc();
+
+      await program.methods
+        .depositeAsset(new anchor.BN(1_000_000), new anchor.BN(1_000_000), new anchor.BN(0))
+        .accountsPartial({
+          ...poolAccounts,
+          liquidProvider: lqProvider.publicKey,
+          providerTokenA: lqProviderC,
+          providerTokenB: lqProviderD,
+        })
+        .signers([lqProvider])
+        .rpc();
+    } catch (error) {
+      throw new Error(`Error while setting up the pool to close ${error}`);
+    }
+
+    try {
+      await program.methods
+        .closePool()
+        .accountsStrict(closeAccounts)
+        .signers([admin])
+        .rpc();
+
+      assert.fail("close pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "PoolNotEmpty");
+    }
+
+    try {
+      const lpBalance = (
+        await getAccount(
+          provider.connection,
+          getAssociatedTokenAddressSync(closeMintLp, lqProvider.publicKey)
+        )
+      ).amount;
+      await program.methods
+        .withdrawAsset(new anchor.BN(lpBalance.toString()), new anchor.BN(0), new anchor.BN(0))
+        .accountsPartial({
+          ...poolAccounts,
+          user: lqProvider.publicKey,
+          userTokenA: lqProviderC,
+          userTokenB: lqProviderD,
+        })
+        .signers([lqProvider])
+        .rpc();
+
+      // a plain transfer into a drained pool's vault doesn't keep it open, it goes to the treasury
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          createTransferInstruction(lqProviderC, closeVaultC, lqProvider.publicKey, 20_000)
+        ),
+        [lqProvider]
+      );
+    } catch (error) {
+      throw new Error(`Error while draining the pool to close ${error}`);
+    }
+
+    try {
+      // a drained pool in an emergency mode stays until it is Active again
+      await program.methods
+        .setPoolStatus({ withdrawOnly: {} })
+        .accountsStrict(statusAccounts(guardian.publicKey))
+        .signers([guardian])
+        .rpc();
+      await program.methods
+        .closePool()
+        .accountsStrict(closeAccounts)
+        .signers([admin])
+        .rpc();
+
+      assert.fail("close pool should have failed");
+    } catch (error) {
+      console.log(`Dont worry this is suppoused to fail ${error}`);
+      assert.include(`${error}`, "PoolNotActive");
+    }
+
+    try {
+      await program.methods
+        .setPoolStatus({ active: {} })
+        .accountsStrict(statusAccounts(guardian.publicKey))
+        .signers([guardian])
+        .rpc();
+
+      const adminBalance = await provider.connection.getBalance(admin.publicKey);
+      await program.methods
+        .closePool()
+        .accountsStrict(closeAccounts)
+        .signers([admin])
+        .rpc();
+
+      for (const account of [
+        closePool,
+        closeVaultC,
+        closeVaultD,
+        closeObservations,
+        poolPairPDA(mintC, mintD, feeBps),
+      ]) {
+        assert.isNull(await provider.connection.getAccountInfo(account));
+      }
+
+      // the locked liquidity was 1000 of 1_000_000, so is what backs it, plus the transfer
+      const treasuryCAfter = await getAccount(provider.connection, treasuryC);
+      assert.equal(Number(treasuryCAfter.amount), MINIMUM_LIQUIDITY + 20_000);
+      const ownerC = getAssociatedTokenAddressSync(mintC, admin.publicKey);
+      assert.isNull(await provider.connection.getAccountInfo(ownerC));
+      assert.isAbove(await provider.connection.getBalance(admin.publicKey), adminBalance);
+    } catch (error) {
+      throw new Error(`Error while closing the pool ${error}`);
+    }
+  });
+
+  it("migrate a pool written in the 0.1.0 layout, then close it", async () => {
+    // loaded by the validator from tests/fixtures, see Anchor.toml. The owner and the mints the
+    // legacy pool points at are the keypairs of these seeds
+    const legacyPool = new anchor.web3.PublicKey("4AYcxf9w8ArhLBQL8pUFZnRsBamm6wWK9oCukQhE5K9k");
+    const legacyMintLp = new anchor.web3.PublicKey("3MMLc4iUW9HNcJrjQ8CitiLULhGegVBoZAPVi5Erjjxq");
+    const [legacyOwner, legacyMintA, legacyMintB] = [1, 2, 3].map((seed) =>
+      anchor.web3.Keypair.fromSeed(new Uint8Array(32).fill(seed))
+    );
+    const legacyObservations = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("observations"), legacyPool.toBuffer()],
+      program.programId
+    )[0];
+    const migrateAccounts = (pool: anchor.web3.PublicKey) => ({
+      payer: lqProvider.publicKey,
+      poolConfigAccount: pool,
+      observations: anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("observations"), pool.toBuffer()],
+        program.programId
+      )[0],
+      systemProgram: anchor.web3.SystemProgram.programId,
+      eventAuthority: eventAuthority,
+      program: program.programId,
+    });
+
+    let legacyVaultA: anchor.web3.PublicKey;
+    let legacyVaultB: anchor.web3.PublicKey;
+    try {
+      // the mints and the vaults 0.1.0 created along with the pool
+      await airdropSOL(legacyOwner.publicKey, provider, 2);
+      for (const mint of [legacyMintA, legacyMintB]) {
+        await createMint(provider.connection, admin, admin.publicKey, null, 6, mint);
+      }
+      [legacyVaultA, legacyVaultB] = await Promise.all(
+        [legacyMintA, legacyMintB].map(async (mint) => {
+          const vault = await getOrCreateAssociatedTokenAccount(
+            provider.connection,
+            admin,
+            mint.publicKey,
+            legacyPool,
+            true
+          );
+          return vault.address;
+        })
+      );
+
+      const before = await provider.connection.getAccountInfo(legacyPool);
+      assert.equal(before.data.length, 117);
+
+      await program.methods
+        .migratePool()
+        .accountsStrict(migrateAccounts(legacyPool))
+        .signers([lqProvider])
+        .rpc();
+
+      const pool = await program.account.poolConfig.fetch(legacyPool);
+      assert.equal(pool.version, 1);
+      assert.equal(pool.owner.toBase58(), legacyOwner.publicKey.toBase58());
+      assert.equal(pool.mintA.toBase58(), legacyMintA.publicKey.toBase58());
+      assert.equal(pool.mintB.toBase58(), legacyMintB.publicKey.toBase58());
+      assert.equal(pool.seed.toNumber(), 7);
+      assert.equal(pool.poolBump, 253);
+      assert.equal(pool.poolMintBump, 254);
+      assert.equal(pool.feeBps, 0);
+      assert.equal(pool.protocolFeeBps, 0);
+      assert.deepEqual(pool.curve, { constantProduct: {} });
+      assert.deepEqual(pool.status, { active: {} });
+      assert.equal(pool.lockedLiquidity.toNumber(), 0);
+      assert.isFalse(pool.registered);
+
+      const observations = await program.account.observations.fetch(legacyObservations);
+      assert.equal(observations.pool.toBase58(), legacyPool.toBase58());
+
+      // new pools are written in the current layout straight away
+      const current = await program.account.poolConfig.fetch(poolConfigPDA);
+      assert.equal(current.version, 1);
+      assert.isTrue(current.registered);
+      assert.equal(current.lockedLiquidity.toNumber(), MINIMUM_LIQUIDITY);
+    } catch (error) {
+      throw new Error(`Error while migrating the 0.1.0 pool ${error}`);
+    }
+
+    // neither a migrated pool nor a pool created in the current layout can be migrated
+    for (const pool of [legacyPool, poolConfigPDA]) {
+      try {
+        await program.methods
+          .migratePool()
+          .accountsStrict(migrateAccounts(pool))
+          .signers([lqProvider])
+          .rpc();
+
+        assert.fail("migrate pool should have failed");
+      } catch (error) {
+        console.log(`Dont worry this is suppoused to fail ${error}`);
+        assert.include(`${error}`, "InvalidPoolVersion");
+      }
+    }
+
+    try {
+      const [legacyTreasuryA, legacyTreasuryB] = await Promise.all(
+        [legacyMintA, legacyMintB].map(async (mint) => {
+          const treasury = await getOrCreateAssociatedTokenAccount(
+            provider.connection,
+            admin,
+            mint.publicKey,
+            creationFeeRecipient
+          );
+          return treasury.address;
+        })
+      );
+
+      // no pool pair entry and no locked lp, the migrated pool closes without them
+      await program.methods
+        .closePool()
+        .accountsStrict({
+          owner: legacyOwner.publicKey,
+          mintA: legacyMintA.publicKey,
+          mintB: legacyMintB.publicKey,
+          globalConfig: globalConfigPDA,
+          poolConfigAccount: legacyPool,
+          poolPair: null,
+          observations: legacyObservations,
+          mintLp: legacyMintLp,
+          lockedLpAuthority: anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("locked_lp"), legacyPool.toBuffer()],
+            program.programId
+          )[0],
+          lockedLpToken: null,
+          vaultA: legacyVaultA,
+          vaultB: legacyVaultB,
+          treasuryTokenA: legacyTreasuryA,
+          treasuryTokenB: legacyTreasuryB,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_PROGRAM_ID,
+          eventAuthority: eventAuthority,
+          program: program.programId,
+        })
+        .signers([legacyOwner])
+        .rpc();
+
+      for (const account of [legacyPool, legacyVaultA, legacyVaultB, legacyObservations]) {
+        assert.isNull(await provider.connection.getAccountInfo(account));
+      }
+    } catch (error) {
+      throw new Error(`Error while closing the migrated pool ${error}`);
     }
   });
 });